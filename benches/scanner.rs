@@ -0,0 +1,98 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use org_parser::document;
+use org_parser::outline;
+use org_parser::scanner;
+
+const CHUNK: &str = "* TODO Write the report :work:
+SCHEDULED: <2019-09-25 Wed>
+:LOGBOOK:
+CLOCK: [2019-09-21 Sat 17:11]--[2019-09-21 Sat 18:24] =>  1:13
+:END:
+Some *bold*, /italic/ and _underlined_ text with a [[https://orgmode.org][link]],
+a plain https://example.com/page link and {{{macro(arg)}}}. Ünïcödé ☕ too.
+** DONE Sub task
+- [X] first item
+- [ ] second item
+";
+
+/// A document of roughly `bytes` bytes.
+fn document_of(bytes: usize) -> String {
+    CHUNK.repeat(bytes / CHUNK.len() + 1)
+}
+
+/// Time per byte should stay flat as the input grows.
+fn scanning(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan");
+    group.sample_size(10);
+
+    for megabytes in [1, 2, 4] {
+        let source = document_of(megabytes << 20);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(megabytes),
+            &source,
+            |b, source| b.iter(|| scanner::scan(source)),
+        );
+    }
+
+    group.finish();
+}
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("document");
+    group.sample_size(10);
+
+    for megabytes in [1, 2, 4] {
+        let source = document_of(megabytes << 20);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(megabytes),
+            &source,
+            |b, source| b.iter(|| document::parse(source)),
+        );
+    }
+
+    group.finish();
+}
+
+/// Only heading lines are parsed, so this should run well ahead of `document`.
+fn outlining(c: &mut Criterion) {
+    let mut group = c.benchmark_group("outline");
+    group.sample_size(10);
+
+    for megabytes in [1, 2, 4] {
+        let source = document_of(megabytes << 20);
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(megabytes),
+            &source,
+            |b, source| b.iter(|| outline::outline(source)),
+        );
+    }
+
+    group.finish();
+}
+
+/// Openers that never close, each of which once made the scanner read the
+/// rest of its line or the text; these should scan about as fast as `scan`.
+fn unclosed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unclosed");
+    group.sample_size(10);
+
+    for (name, unit) in [
+        ("brackets", "[[a "),
+        ("latex", "\\( "),
+        ("emphasis", "-*a "),
+    ] {
+        let source = unit.repeat((256 << 10) / unit.len());
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &source, |b, source| {
+            b.iter(|| scanner::scan(source))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, scanning, parsing, outlining, unclosed);
+criterion_main!(benches);
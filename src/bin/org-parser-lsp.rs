@@ -0,0 +1,79 @@
+use org_parser::lsp::Server;
+use org_parser::timestamp::Date;
+use serde_json::Value;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::process;
+use std::time::SystemTime;
+
+/// Speaks the language server protocol over stdin and stdout.
+fn main() {
+    let seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let mut server = Server::new(Date::from_days((seconds / 86_400) as i64));
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+
+    while let Some(message) = read_message(&mut input) {
+        let message: Value = match serde_json::from_slice(&message) {
+            Ok(message) => message,
+            Err(error) => {
+                eprintln!("org-parser-lsp: {}", error);
+                continue;
+            }
+        };
+
+        for reply in server.handle(&message) {
+            if write_message(&mut output, &reply).is_err() {
+                process::exit(1);
+            }
+        }
+
+        if let Some(status) = server.exit_code() {
+            process::exit(status);
+        }
+    }
+
+    // The client went away without saying `exit`.
+    process::exit(1);
+}
+
+/// The body of the next message, framed by a `Content-Length` header.
+fn read_message(input: &mut impl BufRead) -> Option<Vec<u8>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+
+        let header = header.trim_end();
+
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).ok()?;
+    Some(body)
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
@@ -0,0 +1,353 @@
+use org_parser::diagnostic::Diagnostic;
+use org_parser::diagnostic::Severity;
+use org_parser::document;
+use org_parser::format;
+use org_parser::format::FormatConfig;
+use org_parser::icalendar;
+use org_parser::icalendar::IcalConfig;
+use org_parser::icalendar::IncludeTodo;
+use org_parser::latex;
+use org_parser::latex::LatexConfig;
+use org_parser::latex::SrcBackend;
+use org_parser::lint;
+use org_parser::lint::LintConfig;
+use org_parser::lint::Rule;
+use std::env;
+use std::fs;
+use std::process;
+use std::time::SystemTime;
+
+const USAGE: &str = "usage: org lint [--disable RULE]... [--language LANGUAGE]... FILE...
+       org lint --rules
+       org fmt [--check] [--tags-column COLUMN] [--blank-lines COUNT] FILE...
+       org ical [--name NAME] [--todo open|all] [--alarm MINUTES] [--timezone ZONE]
+                [--exclude TAG]... FILE...
+       org import [--level LEVEL] [--utc-offset +HH:MM] FILE.ics...
+       org latex [--class CLASS] [--listings | --minted] [--body-only] FILE";
+
+/// Exits with 0 when there is nothing to report, 1 when there is (or, for
+/// `fmt --check`, when a file isn't formatted) and 2 on bad arguments or
+/// unreadable files.
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let status = match args.first().map(String::as_str) {
+        Some("lint") => lint_files(&args[1..]),
+        Some("fmt") => format_files(&args[1..]),
+        Some("ical") => export_calendar(&args[1..]),
+        Some("import") => import_calendars(&args[1..]),
+        Some("latex") => export_latex(&args[1..]),
+        _ => usage(),
+    };
+
+    process::exit(status);
+}
+
+fn usage() -> i32 {
+    eprintln!("{}", USAGE);
+    2
+}
+
+fn lint_files(args: &[String]) -> i32 {
+    let mut config = LintConfig::default();
+    let mut files = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rules" => {
+                for rule in Rule::ALL.iter() {
+                    println!("{}", rule.code());
+                }
+
+                return 0;
+            }
+            "--disable" => match args.next().and_then(|code| Rule::from_code(code)) {
+                Some(rule) => config.disabled.push(rule),
+                None => return usage(),
+            },
+            "--language" => match args.next() {
+                Some(language) => config.languages.push(language.clone()),
+                None => return usage(),
+            },
+            _ if arg.starts_with("--") => return usage(),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        return usage();
+    }
+
+    let mut status = 0;
+
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("{}: {}", file, error);
+                status = 2;
+                continue;
+            }
+        };
+
+        for diagnostic in lint::lint(&source, &config) {
+            println!("{}", describe(file, &source, &diagnostic));
+
+            if diagnostic.severity != Severity::Info {
+                status = status.max(1);
+            }
+        }
+    }
+
+    status
+}
+
+/// Formats files in place, or with `--check` lists those that would change.
+fn format_files(args: &[String]) -> i32 {
+    let mut config = FormatConfig::default();
+    let mut check = false;
+    let mut files = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--tags-column" => match args.next().and_then(|column| column.parse().ok()) {
+                Some(column) => config.tags_column = column,
+                None => return usage(),
+            },
+            "--blank-lines" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(count) => config.blank_lines = count,
+                None => return usage(),
+            },
+            _ if arg.starts_with("--") => return usage(),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        return usage();
+    }
+
+    let mut status = 0;
+
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("{}: {}", file, error);
+                status = 2;
+                continue;
+            }
+        };
+
+        let formatted = format::format(&source, &config);
+
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("{}", file);
+            status = status.max(1);
+        } else if let Err(error) = fs::write(file, formatted) {
+            eprintln!("{}: {}", file, error);
+            status = 2;
+        }
+    }
+
+    status
+}
+
+/// Writes one calendar with the entries of all the files to stdout.
+fn export_calendar(args: &[String]) -> i32 {
+    let mut config = IcalConfig::default();
+    let mut files = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" => match args.next() {
+                Some(name) => config.name = name.clone(),
+                None => return usage(),
+            },
+            "--todo" => match args.next().map(String::as_str) {
+                Some("open") => config.include_todo = IncludeTodo::Open,
+                Some("all") => config.include_todo = IncludeTodo::All,
+                _ => return usage(),
+            },
+            "--alarm" => match args.next().and_then(|minutes| minutes.parse().ok()) {
+                Some(minutes) => config.alarm_time = minutes,
+                None => return usage(),
+            },
+            "--timezone" => match args.next() {
+                Some(zone) => config.timezone = Some(zone.clone()),
+                None => return usage(),
+            },
+            "--exclude" => match args.next() {
+                Some(tag) => config.exclude_tags.push(tag.clone()),
+                None => return usage(),
+            },
+            _ if arg.starts_with("--") => return usage(),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        return usage();
+    }
+
+    let mut documents = vec![];
+
+    for file in files {
+        match fs::read_to_string(file) {
+            Ok(source) => documents.push((file.as_str(), document::parse(&source))),
+            Err(error) => {
+                eprintln!("{}: {}", file, error);
+                return 2;
+            }
+        }
+    }
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let files: Vec<(&str, &document::Document)> = documents
+        .iter()
+        .map(|(file, document)| (*file, document))
+        .collect();
+
+    print!("{}", icalendar::export(&files, &config, now));
+    0
+}
+
+/// Writes Org headings for the events and to-dos of iCalendar files to
+/// stdout.
+fn import_calendars(args: &[String]) -> i32 {
+    let mut level = 1;
+    let mut utc_offset = 0;
+    let mut files = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--level" => match args.next().and_then(|level| level.parse().ok()) {
+                Some(value) if value > 0 => level = value,
+                _ => return usage(),
+            },
+            "--utc-offset" => match args.next().and_then(|offset| minutes_east(offset)) {
+                Some(minutes) => utc_offset = minutes,
+                None => return usage(),
+            },
+            _ if arg.starts_with("--") => return usage(),
+            _ => files.push(arg),
+        }
+    }
+
+    if files.is_empty() {
+        return usage();
+    }
+
+    let mut status = 0;
+
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("{}: {}", file, error);
+                status = 2;
+                continue;
+            }
+        };
+
+        match icalendar::import(&source, level, utc_offset) {
+            Ok(headings) => {
+                for heading in headings {
+                    print!("{}", heading.to_org());
+                }
+            }
+            Err(error) => {
+                eprintln!("{}: {}", file, error);
+                status = 2;
+            }
+        }
+    }
+
+    status
+}
+
+/// Minutes east of UTC in an offset such as `+02:00` or `-0530`.
+fn minutes_east(offset: &str) -> Option<i64> {
+    let (sign, digits) = match offset.split_at_checked(1)? {
+        ("+", digits) => (1, digits.replace(':', "")),
+        ("-", digits) => (-1, digits.replace(':', "")),
+        _ => return None,
+    };
+
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits[2..].parse().ok()?;
+
+    (hours <= 14 && minutes < 60).then_some(sign * (hours * 60 + minutes))
+}
+
+/// Writes a file as LaTeX to stdout.
+fn export_latex(args: &[String]) -> i32 {
+    let mut config = LatexConfig::default();
+    let mut files = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--class" => match args.next() {
+                Some(class) => config.class = class.clone(),
+                None => return usage(),
+            },
+            "--listings" => config.src_backend = SrcBackend::Listings,
+            "--minted" => config.src_backend = SrcBackend::Minted,
+            "--body-only" => config.body_only = true,
+            _ if arg.starts_with("--") => return usage(),
+            _ => files.push(arg),
+        }
+    }
+
+    let file = match files.as_slice() {
+        [file] => file,
+        _ => return usage(),
+    };
+
+    match fs::read_to_string(file) {
+        Ok(source) => {
+            print!("{}", latex::export(&document::parse(&source), &config));
+            0
+        }
+        Err(error) => {
+            eprintln!("{}: {}", file, error);
+            2
+        }
+    }
+}
+
+/// `file:line:column: severity: message [code]`, as compilation buffers and
+/// editors expect.
+fn describe(file: &str, source: &str, diagnostic: &Diagnostic) -> String {
+    let column = diagnostic.span.as_ref().map_or(1, |span| {
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        source[line_start..span.start].chars().count() + 1
+    });
+
+    let severity = match diagnostic.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "info",
+    };
+
+    format!(
+        "{}:{}:{}: {}: {} [{}]",
+        file, diagnostic.line, column, severity, diagnostic.message, diagnostic.code
+    )
+}
@@ -0,0 +1,202 @@
+use crate::document::heading_level;
+use crate::document::BlockLine;
+use crate::document::Blocks;
+use crate::timestamp::Timestamp;
+use std::ops::Range;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+/// A problem found in a file. `span` is the byte range it covers, when
+/// known.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    pub file: Option<String>,
+    pub line: usize,
+    pub span: Option<Range<usize>>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &str, message: String, line: usize) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code: code.to_string(),
+            message,
+            file: None,
+            line,
+            span: None,
+        }
+    }
+
+    pub fn in_file(mut self, file: &str) -> Diagnostic {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    pub fn with_span(mut self, span: Range<usize>) -> Diagnostic {
+        self.span = Some(span);
+        self
+    }
+}
+
+/// Syntax problems in `source`, in order. The parser reads past all of
+/// them; these say where it had to guess:
+///
+/// - `unclosed-block`: a `#+BEGIN_<NAME>` line with no `#+END_<NAME>`,
+///   read as a keyword.
+/// - `unterminated-drawer`: a drawer with no `:END:` before the next
+///   heading, read as a paragraph.
+/// - `malformed-timestamp`: something like `[2019-13-40 Foo]`.
+/// - `unclosed-link`: a `[[` with no `]]` in the rest of the paragraph.
+/// - `stray-character`: a control character, or the replacement character
+///   left by invalid UTF-8.
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut blocks = Blocks::new(source.lines());
+    let mut drawer: Option<Diagnostic> = None;
+    let mut offset = 0;
+    let drawer_start = regex!(r"^:[\w-]+:$");
+    let mut link_ends = (Next::new("]]"), Next::new("\n\n"));
+
+    for (i, text) in source.split_inclusive('\n').enumerate() {
+        let line = i + 1;
+        let start = offset;
+        offset += text.len();
+
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let span = start..start + text.len();
+
+        stray_characters(text, line, start, &mut diagnostics);
+
+        let trimmed = text.trim();
+
+        match blocks.read(text) {
+            BlockLine::Outside => (),
+            BlockLine::Unclosed => {
+                let message = format!("{} is never closed", trimmed);
+                diagnostics.push(
+                    Diagnostic::new(Severity::Error, "unclosed-block", message, line)
+                        .with_span(span),
+                );
+                continue;
+            }
+            _ => continue,
+        }
+
+        if heading_level(text).is_some() {
+            diagnostics.extend(drawer.take());
+        }
+
+        if trimmed.eq_ignore_ascii_case(":END:") {
+            drawer = None;
+        } else if drawer_start.is_match(trimmed) {
+            let message = format!("drawer {} has no :END:", trimmed);
+            drawer = Some(
+                Diagnostic::new(Severity::Error, "unterminated-drawer", message, line)
+                    .with_span(span),
+            );
+        }
+
+        timestamps(text, line, start, &mut diagnostics);
+        links(source, text, line, start, &mut link_ends, &mut diagnostics);
+    }
+
+    diagnostics.extend(drawer);
+    diagnostics
+}
+
+fn stray_characters(text: &str, line: usize, start: usize, diagnostics: &mut Vec<Diagnostic>) {
+    for (i, c) in text.char_indices() {
+        if (c.is_control() && c != '\t') || c == '\u{FFFD}' {
+            let message = format!("stray character {:?}", c);
+            let at = start + i;
+            diagnostics.push(
+                Diagnostic::new(Severity::Warning, "stray-character", message, line)
+                    .with_span(at..at + c.len_utf8()),
+            );
+        }
+    }
+}
+
+fn timestamps(text: &str, line: usize, start: usize, diagnostics: &mut Vec<Diagnostic>) {
+    let candidate = regex!(r"[<\[]\d{4}-\d{2}-\d{2}[^\[\]<>]*[\]>]");
+
+    for found in candidate.find_iter(text) {
+        if Timestamp::parse(found.as_str()).is_none() {
+            let message = format!("malformed timestamp {}", found.as_str());
+            diagnostics.push(
+                Diagnostic::new(Severity::Warning, "malformed-timestamp", message, line)
+                    .with_span(start + found.start()..start + found.end()),
+            );
+        }
+    }
+}
+
+/// The next occurrence of `pattern` in a text, remembered so that asking
+/// again from a later byte before it doesn't search the text again.
+struct Next {
+    pattern: &'static str,
+    from: usize,
+    found: Option<usize>,
+}
+
+impl Next {
+    fn new(pattern: &'static str) -> Next {
+        Next {
+            pattern,
+            from: usize::MAX,
+            found: None,
+        }
+    }
+
+    fn find(&mut self, source: &str, from: usize) -> Option<usize> {
+        if self.from > from || self.found.is_some_and(|i| i < from) {
+            self.from = from;
+            self.found = source[from..].find(self.pattern).map(|i| from + i);
+        }
+
+        self.found
+    }
+}
+
+/// Warns about each `[[` on the line without a `]]` before the next blank
+/// line. `ends` holds the next `]]` and blank line found so far.
+fn links(
+    source: &str,
+    text: &str,
+    line: usize,
+    start: usize,
+    ends: &mut (Next, Next),
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (i, _) in text.match_indices("[[") {
+        let at = start + i;
+        let close = ends.0.find(source, at);
+        let blank = ends.1.find(source, at);
+
+        let closed = match (close, blank) {
+            (Some(close), Some(blank)) => close < blank,
+            (close, _) => close.is_some(),
+        };
+
+        if !closed {
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    "unclosed-link",
+                    "link is never closed".to_string(),
+                    line,
+                )
+                .with_span(at..at + 2),
+            );
+        }
+    }
+}
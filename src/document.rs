@@ -0,0 +1,569 @@
+use crate::diagnostic;
+use crate::diagnostic::Diagnostic;
+use crate::element;
+use crate::element::Element;
+use crate::element::Keyword;
+use crate::element::Kind;
+use crate::events::Event;
+use crate::events::Events;
+use crate::link::Link;
+use crate::scanner;
+use crate::scanner::Token;
+use crate::scanner::TokenType;
+use crate::timestamp::Timestamp;
+use std::collections::HashMap;
+
+/// A parsed Org file: the lines before the first heading, followed by the
+/// outline. Section contents are kept as raw lines so that a document can be
+/// written back out unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Document {
+    pub section: Vec<String>,
+    pub headings: Vec<Heading>,
+    pub todo_keywords: Vec<String>,
+    pub done_keywords: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub level: usize,
+    pub keyword: Option<String>,
+    pub priority: Option<char>,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub line: usize,
+    pub section: Vec<String>,
+    pub children: Vec<Heading>,
+}
+
+/// The `SCHEDULED:`, `DEADLINE:` and `CLOSED:` timestamps on the line
+/// directly below a heading.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Planning {
+    pub scheduled: Option<Timestamp>,
+    pub deadline: Option<Timestamp>,
+    pub closed: Option<Timestamp>,
+}
+
+/// Position of a heading in the outline, as indices into `headings` and then
+/// successive `children`.
+pub type Path = Vec<usize>;
+
+pub const TODO_KEYWORDS: [&str; 2] = ["TODO", "DONE"];
+
+/// Keyword lines that replace the default `TODO | DONE` sequence.
+pub const TODO_SETTINGS: [&str; 3] = ["#+TODO:", "#+SEQ_TODO:", "#+TYP_TODO:"];
+
+pub fn parse(source: &str) -> Document {
+    let document = Document {
+        section: vec![],
+        headings: vec![],
+        todo_keywords: vec![TODO_KEYWORDS[0].to_string()],
+        done_keywords: vec![TODO_KEYWORDS[1].to_string()],
+    };
+
+    read(document, source, 1, false)
+}
+
+/// Parses `source` like `parse`, along with the problems it read past.
+pub fn parse_with_diagnostics(source: &str) -> (Document, Vec<Diagnostic>) {
+    (parse(source), diagnostic::check(source))
+}
+
+/// Parses part of a larger document, starting at line `first_line`, with
+/// the TODO keywords already set up in `document`.
+pub fn parse_fragment(source: &str, first_line: usize, document: &Document) -> Document {
+    let fragment = Document {
+        section: vec![],
+        headings: vec![],
+        todo_keywords: document.todo_keywords.clone(),
+        done_keywords: document.done_keywords.clone(),
+    };
+
+    read(fragment, source, first_line, true)
+}
+
+/// Whether `line` sets up TODO keywords, which apply to the whole file.
+pub fn is_todo_setting(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    TODO_SETTINGS
+        .iter()
+        .any(|setting| strip_keyword(trimmed, setting).is_some())
+}
+
+/// The TODO and done keywords set up by `line`, if it is a TODO setting.
+pub fn todo_setting(line: &str) -> Option<(Vec<String>, Vec<String>)> {
+    let trimmed = line.trim_start();
+    let sequence = TODO_SETTINGS
+        .iter()
+        .find_map(|setting| strip_keyword(trimmed, setting))?;
+
+    Some(todo_sequence(sequence))
+}
+
+/// The TODO and done keywords set up by the TODO settings in `source`,
+/// wherever in the file they are, or `None` without any.
+pub fn todo_settings(source: &str) -> Option<(Vec<String>, Vec<String>)> {
+    let mut result: Option<(Vec<String>, Vec<String>)> = None;
+
+    for (todo, done) in source.lines().filter_map(todo_setting) {
+        let keywords = result.get_or_insert_with(Default::default);
+        keywords.0.extend(todo);
+        keywords.1.extend(done);
+    }
+
+    result
+}
+
+fn read(
+    mut document: Document,
+    source: &str,
+    first_line: usize,
+    custom_keywords: bool,
+) -> Document {
+    let mut events = Events::new(
+        source,
+        first_line,
+        document.todo_keywords.clone(),
+        document.done_keywords.clone(),
+        custom_keywords,
+    );
+    // Headings still collecting section lines or children, outermost first.
+    let mut open: Vec<Heading> = vec![];
+
+    for event in events.by_ref() {
+        match event {
+            Event::Start(heading) => open.push(heading),
+            Event::Text { text, .. } => match open.last_mut() {
+                Some(heading) => heading.section.push(text.to_string()),
+                None => document.section.push(text.to_string()),
+            },
+            Event::End(_) => {
+                let heading = open.pop().unwrap();
+
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(heading),
+                    None => document.headings.push(heading),
+                }
+            }
+        }
+    }
+
+    document.todo_keywords = events.todo_keywords().to_vec();
+    document.done_keywords = events.done_keywords().to_vec();
+
+    document
+}
+
+/// Splits a sequence such as `TODO NEXT(n) | DONE(d!) CANCELED` into TODO
+/// and done keywords: those after the bar, or the last one if there is no
+/// bar, are done states.
+pub fn todo_sequence(sequence: &str) -> (Vec<String>, Vec<String>) {
+    let keywords: Vec<String> = sequence
+        .split_whitespace()
+        .map(|keyword| keyword.split('(').next().unwrap().to_string())
+        .collect();
+
+    let bar = keywords.iter().position(|keyword| keyword == "|");
+
+    match bar {
+        Some(i) => (keywords[..i].to_vec(), keywords[i + 1..].to_vec()),
+        None if keywords.len() > 1 => (
+            keywords[..keywords.len() - 1].to_vec(),
+            keywords[keywords.len() - 1..].to_vec(),
+        ),
+        None => (keywords, vec![]),
+    }
+}
+
+/// Follows the blocks of a text a line at a time, the way the parser reads
+/// them: only an `#+END_` line with the name of its `#+BEGIN_` line closes
+/// a block, in any case, and a `#+BEGIN_` line with no such line after it
+/// doesn't open one at all.
+pub struct Blocks {
+    /// The last line closing a block of each name, by upper-case name.
+    last_end: HashMap<String, usize>,
+    /// The number of lines read.
+    line: usize,
+    /// The name of the block the last line read is in.
+    open: Option<String>,
+}
+
+/// What a line is to the blocks around it, as told by `Blocks::read`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockLine {
+    Outside,
+    /// A `#+BEGIN_` line, with the upper-case name of the block it opens.
+    Begin(String),
+    /// A `#+BEGIN_` line that nothing closes, read as a keyword.
+    Unclosed,
+    Inside(String),
+    End(String),
+}
+
+impl Blocks {
+    /// Looks over the lines of a text once for those that close blocks.
+    pub fn new<'a>(lines: impl IntoIterator<Item = &'a str>) -> Blocks {
+        let mut last_end = HashMap::new();
+
+        for (i, line) in lines.into_iter().enumerate() {
+            if let Some(name) = block_end(line) {
+                last_end.insert(name, i);
+            }
+        }
+
+        Blocks {
+            last_end,
+            line: 0,
+            open: None,
+        }
+    }
+
+    /// Reads the next line of the text.
+    pub fn read(&mut self, text: &str) -> BlockLine {
+        let line = self.line;
+        self.line += 1;
+
+        if let Some(name) = self.open.take() {
+            if block_end(text).is_some_and(|end| end == name) {
+                return BlockLine::End(name);
+            }
+
+            self.open = Some(name.clone());
+            return BlockLine::Inside(name);
+        }
+
+        match block_begin(text) {
+            Some(name) if self.last_end.get(&name).is_some_and(|end| *end > line) => {
+                self.open = Some(name.clone());
+                BlockLine::Begin(name)
+            }
+            Some(_) => BlockLine::Unclosed,
+            None => BlockLine::Outside,
+        }
+    }
+}
+
+/// The upper-case name of the block a `#+BEGIN_<NAME>` line opens.
+fn block_begin(text: &str) -> Option<String> {
+    let name = strip_keyword(text.trim(), "#+BEGIN_")?;
+    let name = name.split(char::is_whitespace).next()?;
+
+    (!name.is_empty()).then(|| name.to_uppercase())
+}
+
+/// The upper-case name of the block an `#+END_<NAME>` line closes.
+fn block_end(text: &str) -> Option<String> {
+    strip_keyword(text.trim(), "#+END_").map(|name| name.to_ascii_uppercase())
+}
+
+/// `text` after `prefix`, which it starts with in any case.
+fn strip_keyword<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    text.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &text[prefix.len()..])
+}
+
+/// Line numbers, counting from `first_line`, of the `#+BEGIN_` lines of
+/// `source` that no `#+END_` line closes.
+pub fn unclosed_blocks(source: &str, first_line: usize) -> Vec<usize> {
+    let mut blocks = Blocks::new(source.lines());
+
+    source
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| blocks.read(line) == BlockLine::Unclosed)
+        .map(|(i, _)| first_line + i)
+        .collect()
+}
+
+/// The number of stars if `line` is a heading.
+pub fn heading_level(line: &str) -> Option<usize> {
+    let level = line.chars().take_while(|c| *c == '*').count();
+
+    match line[level..].chars().next() {
+        Some(' ') | None if level > 0 => Some(level),
+        _ => None,
+    }
+}
+
+impl Heading {
+    /// Parses a heading line with `level` stars, recognising `keywords` as
+    /// TODO keywords.
+    pub fn parse(line: &str, level: usize, line_number: usize, keywords: &[&String]) -> Heading {
+        let mut heading = Heading {
+            level,
+            keyword: None,
+            priority: None,
+            title: String::new(),
+            tags: vec![],
+            line: line_number,
+            section: vec![],
+            children: vec![],
+        };
+
+        let mut rest = line[level..].trim();
+
+        if let Some((keyword, tail)) = rest.split_once(' ').or(Some((rest, ""))) {
+            if keywords.iter().any(|k| *k == keyword) {
+                heading.keyword = Some(keyword.to_string());
+                rest = tail.trim_start();
+            }
+        }
+
+        let priority = regex!(r"^\[#([A-Z0-9])\]\s*");
+
+        if let Some(captures) = priority.captures(rest) {
+            heading.priority = captures[1].chars().next();
+            rest = &rest[captures[0].len()..];
+        }
+
+        let tags = regex!(r"(^|\s+)(:[\w@#%:]+:)$");
+
+        if let Some(captures) = tags.captures(rest) {
+            heading.tags = captures[2]
+                .split(':')
+                .filter(|tag| !tag.is_empty())
+                .map(|tag| tag.to_string())
+                .collect();
+            rest = &rest[..rest.len() - captures[0].len()];
+        }
+
+        heading.title = rest.to_string();
+        heading
+    }
+
+    /// The title with whitespace collapsed, as used to match `[[*Title]]`.
+    pub fn normalised_title(&self) -> String {
+        self.title
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+
+    /// Key/value pairs of the `:PROPERTIES:` drawer, in order.
+    pub fn properties(&self) -> Vec<(String, String)> {
+        let mut properties = vec![];
+        let mut lines = self.section.iter().map(|line| line.trim());
+
+        if !lines.any(|line| line.eq_ignore_ascii_case(":PROPERTIES:")) {
+            return properties;
+        }
+
+        for line in lines {
+            if line.eq_ignore_ascii_case(":END:") {
+                break;
+            }
+
+            if let Some(rest) = line.strip_prefix(':') {
+                if let Some((key, value)) = rest.split_once(':') {
+                    properties.push((key.to_string(), value.trim().to_string()));
+                }
+            }
+        }
+
+        properties
+    }
+
+    pub fn property(&self, key: &str) -> Option<String> {
+        self.properties()
+            .into_iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    pub fn elements(&self) -> Vec<Element> {
+        element::parse(&self.section, self.line + 1, true)
+    }
+
+    pub fn planning(&self) -> Planning {
+        let mut planning = Planning::default();
+
+        let line = match self.section.first() {
+            Some(line) => line,
+            None => return planning,
+        };
+
+        let keyword = regex!(r"(SCHEDULED|DEADLINE|CLOSED):\s*([<\[][^>\]]*[>\]])");
+
+        for captures in keyword.captures_iter(line) {
+            let timestamp = Timestamp::parse(&captures[2]);
+
+            match &captures[1] {
+                "SCHEDULED" => planning.scheduled = timestamp,
+                "DEADLINE" => planning.deadline = timestamp,
+                _ => planning.closed = timestamp,
+            }
+        }
+
+        planning
+    }
+
+    /// Links in the title and section, with the line each one is on.
+    pub fn links(&self) -> Vec<(usize, Link)> {
+        let mut links = tokens(&self.title, self.line)
+            .iter()
+            .filter_map(|token| Link::from_token(token).map(|link| (token.line, link)))
+            .collect::<Vec<(usize, Link)>>();
+
+        links.extend(section_links(&self.section, self.line + 1));
+        links
+    }
+
+    pub fn heading_line(&self) -> String {
+        let mut line = "*".repeat(self.level);
+
+        if let Some(keyword) = &self.keyword {
+            line.push(' ');
+            line.push_str(keyword);
+        }
+
+        if let Some(priority) = self.priority {
+            line.push_str(&format!(" [#{}]", priority));
+        }
+
+        if !self.title.is_empty() {
+            line.push(' ');
+            line.push_str(&self.title);
+        }
+
+        if !self.tags.is_empty() {
+            line.push_str(&format!(" :{}:", self.tags.join(":")));
+        }
+
+        line
+    }
+
+    /// The heading and its subtree as Org text.
+    pub fn to_org(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut String) {
+        out.push_str(&self.heading_line());
+        out.push('\n');
+
+        for line in &self.section {
+            out.push_str(line);
+            out.push('\n');
+        }
+
+        for child in &self.children {
+            child.write(out);
+        }
+    }
+}
+
+impl Document {
+    pub fn heading(&self, path: &[usize]) -> Option<&Heading> {
+        let (first, rest) = path.split_first()?;
+        let mut heading = self.headings.get(*first)?;
+
+        for i in rest {
+            heading = heading.children.get(*i)?;
+        }
+
+        Some(heading)
+    }
+
+    pub fn heading_mut(&mut self, path: &[usize]) -> Option<&mut Heading> {
+        let (first, rest) = path.split_first()?;
+        let mut heading = self.headings.get_mut(*first)?;
+
+        for i in rest {
+            heading = heading.children.get_mut(*i)?;
+        }
+
+        Some(heading)
+    }
+
+    /// Every heading in document order, together with its path.
+    pub fn all_headings(&self) -> Vec<(Path, &Heading)> {
+        fn collect<'a>(headings: &'a [Heading], path: &Path, out: &mut Vec<(Path, &'a Heading)>) {
+            for (i, heading) in headings.iter().enumerate() {
+                let mut path = path.clone();
+                path.push(i);
+                out.push((path.clone(), heading));
+                collect(&heading.children, &path, out);
+            }
+        }
+
+        let mut headings = vec![];
+        collect(&self.headings, &vec![], &mut headings);
+        headings
+    }
+
+    /// Path of the innermost heading whose subtree contains `line`, or an
+    /// empty path for lines before the first heading.
+    pub fn path_at_line(&self, line: usize) -> Path {
+        self.all_headings()
+            .into_iter()
+            .rev()
+            .find(|(_, heading)| heading.line <= line)
+            .map(|(path, _)| path)
+            .unwrap_or_default()
+    }
+
+    /// Elements before the first heading.
+    pub fn elements(&self) -> Vec<Element> {
+        element::parse(&self.section, 1, false)
+    }
+
+    /// Every `#+KEY: value` keyword in the file, in order, leaving out
+    /// affiliated keywords.
+    pub fn keywords(&self) -> Vec<Keyword> {
+        let mut elements = self.elements();
+
+        for (_, heading) in self.all_headings() {
+            elements.extend(heading.elements());
+        }
+
+        elements
+            .into_iter()
+            .filter_map(|element| match element.kind {
+                Kind::Keyword(keyword) => Some(keyword),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Links outside any heading, with the line each one is on.
+    pub fn links(&self) -> Vec<(usize, Link)> {
+        section_links(&self.section, 1)
+    }
+
+    pub fn to_org(&self) -> String {
+        let mut out = String::new();
+
+        for line in &self.section {
+            out.push_str(line);
+            out.push('\n');
+        }
+
+        for heading in &self.headings {
+            heading.write(&mut out);
+        }
+
+        out
+    }
+}
+
+/// Scans `text` into inline tokens, numbering lines from `first_line`.
+pub fn tokens(text: &str, first_line: usize) -> Vec<Token<'_>> {
+    scanner::tokens(text)
+        .filter(|token| token.token_type != TokenType::EOF)
+        .map(|mut token| {
+            token.line += first_line - 1;
+            token
+        })
+        .collect()
+}
+
+fn section_links(section: &[String], first_line: usize) -> Vec<(usize, Link)> {
+    tokens(&section.join("\n"), first_line)
+        .iter()
+        .filter_map(|token| Link::from_token(token).map(|link| (token.line, link)))
+        .collect()
+}
@@ -0,0 +1,475 @@
+use crate::document::heading_level;
+use crate::document::parse;
+use crate::document::Document;
+use crate::document::Heading;
+use crate::document::Path;
+use crate::element::is_planning;
+use crate::timestamp::Timestamp;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditError {
+    NoSuchHeading(Path),
+    /// An insertion index past the end of the siblings.
+    InvalidIndex(usize),
+    /// Moving a subtree below itself.
+    MoveIntoItself(Path),
+    /// Promoting a top-level heading.
+    InvalidLevel(Path),
+    UnknownKeyword(String),
+    InvalidPriority(char),
+    InvalidTag(String),
+    InvalidProperty(String),
+    /// A text range that is reversed, out of bounds or not on character
+    /// boundaries.
+    InvalidRange(usize, usize),
+    /// Text that would break the outline, such as a title with a newline.
+    InvalidText(String),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::NoSuchHeading(path) => write!(f, "no heading at {:?}", path),
+            EditError::InvalidIndex(index) => write!(f, "no position {} among siblings", index),
+            EditError::MoveIntoItself(path) => write!(f, "cannot move {:?} into itself", path),
+            EditError::InvalidLevel(path) => write!(f, "cannot promote {:?} further", path),
+            EditError::UnknownKeyword(keyword) => write!(f, "unknown TODO keyword {}", keyword),
+            EditError::InvalidPriority(priority) => write!(f, "invalid priority {}", priority),
+            EditError::InvalidTag(tag) => write!(f, "invalid tag {}", tag),
+            EditError::InvalidProperty(key) => write!(f, "invalid property {}", key),
+            EditError::InvalidRange(start, end) => write!(f, "invalid range {}..{}", start, end),
+            EditError::InvalidText(text) => write!(f, "invalid text {:?}", text),
+        }
+    }
+}
+
+impl Heading {
+    /// A level 1 heading with `title` and nothing else, to be inserted with
+    /// `Document::insert_subtree`.
+    pub fn new(title: &str) -> Heading {
+        Heading {
+            level: 1,
+            keyword: None,
+            priority: None,
+            title: title.to_string(),
+            tags: vec![],
+            line: 0,
+            section: vec![],
+            children: vec![],
+        }
+    }
+
+    /// Sets the level of the heading, shifting its descendants along.
+    fn shift_to(&mut self, level: usize) {
+        let delta = level as isize - self.level as isize;
+        self.shift(delta);
+    }
+
+    fn shift(&mut self, delta: isize) {
+        self.level = (self.level as isize + delta).max(1) as usize;
+
+        for child in self.children.iter_mut() {
+            child.shift(delta);
+        }
+    }
+}
+
+/// Structural edits. Each one leaves the document as `parse` would read
+/// its `to_org()` output, with line numbers and nesting up to date, and
+/// fails without changing anything if the edit is not possible.
+impl Document {
+    /// Inserts `heading` and its subtree as child `index` of `parent`, or at
+    /// the top level if `parent` is empty. Levels are adjusted to fit.
+    pub fn insert_subtree(
+        &mut self,
+        parent: &[usize],
+        index: usize,
+        mut heading: Heading,
+    ) -> Result<Path, EditError> {
+        check_subtree(&heading)?;
+
+        let siblings = match parent {
+            [] => {
+                heading.shift_to(1);
+                &mut self.headings
+            }
+            _ => {
+                let parent_heading = self.edit(parent)?;
+                heading.shift_to(parent_heading.level + 1);
+                &mut parent_heading.children
+            }
+        };
+
+        if index > siblings.len() {
+            return Err(EditError::InvalidIndex(index));
+        }
+
+        siblings.insert(index, heading);
+        self.refresh();
+
+        let mut path = parent.to_vec();
+        path.push(index);
+        Ok(path)
+    }
+
+    pub fn remove_subtree(&mut self, path: &[usize]) -> Result<Heading, EditError> {
+        let (last, parent) = path
+            .split_last()
+            .ok_or_else(|| EditError::NoSuchHeading(path.to_vec()))?;
+        self.edit(path)?;
+
+        let heading = match parent {
+            [] => self.headings.remove(*last),
+            _ => self.edit(parent)?.children.remove(*last),
+        };
+
+        self.refresh();
+        Ok(heading)
+    }
+
+    /// Moves the subtree at `from` to child `index` of `parent`, where
+    /// `parent` and `index` refer to the outline after the subtree has been
+    /// taken out.
+    pub fn move_subtree(
+        &mut self,
+        from: &[usize],
+        parent: &[usize],
+        index: usize,
+    ) -> Result<Path, EditError> {
+        self.edit(from)?;
+
+        if parent.starts_with(from) {
+            return Err(EditError::MoveIntoItself(from.to_vec()));
+        }
+
+        let mut parent = parent.to_vec();
+        let depth = from.len() - 1;
+
+        // Taking `from` out moves its later siblings, and everything below
+        // them, up by one.
+        if parent.len() > depth && parent[..depth] == from[..depth] && parent[depth] > from[depth] {
+            parent[depth] -= 1;
+        }
+
+        let mut document = self.clone();
+        let heading = document.remove_subtree(from)?;
+        let path = document.insert_subtree(&parent, index, heading)?;
+
+        *self = document;
+        Ok(path)
+    }
+
+    /// Moves the subtree one level up, like `org-promote-subtree`. Siblings
+    /// after it become its children, as they would on re-reading the file.
+    /// Returns the new path of the heading.
+    pub fn promote(&mut self, path: &[usize]) -> Result<Path, EditError> {
+        let heading = self.edit(path)?;
+
+        if heading.level == 1 {
+            return Err(EditError::InvalidLevel(path.to_vec()));
+        }
+
+        heading.shift(-1);
+        Ok(self.restructure(path))
+    }
+
+    /// Moves the subtree one level down, like `org-demote-subtree`. It
+    /// becomes the last child of its previous sibling, if there is one.
+    pub fn demote(&mut self, path: &[usize]) -> Result<Path, EditError> {
+        self.edit(path)?.shift(1);
+        Ok(self.restructure(path))
+    }
+
+    /// Sets or clears the TODO keyword, which must be one of the document's
+    /// TODO or done keywords.
+    pub fn set_keyword(&mut self, path: &[usize], keyword: Option<&str>) -> Result<(), EditError> {
+        if let Some(keyword) = keyword {
+            if !self
+                .todo_keywords
+                .iter()
+                .chain(&self.done_keywords)
+                .any(|k| k == keyword)
+            {
+                return Err(EditError::UnknownKeyword(keyword.to_string()));
+            }
+        }
+
+        self.edit(path)?.keyword = keyword.map(|keyword| keyword.to_string());
+        self.refresh();
+        Ok(())
+    }
+
+    /// Sets or clears the `[#A]` cookie. Priorities are `A`-`Z` or `0`-`9`.
+    pub fn set_priority(
+        &mut self,
+        path: &[usize],
+        priority: Option<char>,
+    ) -> Result<(), EditError> {
+        if let Some(priority) = priority {
+            if !priority.is_ascii_uppercase() && !priority.is_ascii_digit() {
+                return Err(EditError::InvalidPriority(priority));
+            }
+        }
+
+        self.edit(path)?.priority = priority;
+        self.refresh();
+        Ok(())
+    }
+
+    pub fn add_tag(&mut self, path: &[usize], tag: &str) -> Result<(), EditError> {
+        if !is_tag(tag) {
+            return Err(EditError::InvalidTag(tag.to_string()));
+        }
+
+        let heading = self.edit(path)?;
+
+        if !heading.tags.iter().any(|t| t == tag) {
+            heading.tags.push(tag.to_string());
+        }
+
+        self.refresh();
+        Ok(())
+    }
+
+    pub fn remove_tag(&mut self, path: &[usize], tag: &str) -> Result<(), EditError> {
+        self.edit(path)?.tags.retain(|t| t != tag);
+        self.refresh();
+        Ok(())
+    }
+
+    /// Sets `key` in the `:PROPERTIES:` drawer, creating the drawer after
+    /// the planning line if there is none.
+    pub fn set_property(
+        &mut self,
+        path: &[usize],
+        key: &str,
+        value: &str,
+    ) -> Result<(), EditError> {
+        if key.is_empty() || key.contains(|c: char| c == ':' || c.is_whitespace()) {
+            return Err(EditError::InvalidProperty(key.to_string()));
+        }
+
+        check_line(value)?;
+
+        let section = &mut self.edit(path)?.section;
+        let line = format!(":{}: {}", key, value).trim_end().to_string();
+        let start = planning_end(section);
+
+        match drawer(section, start, ":PROPERTIES:") {
+            Some(end) => {
+                let existing = (start + 1..end).find(|i| {
+                    section[*i]
+                        .trim()
+                        .strip_prefix(':')
+                        .and_then(|property| property.split_once(':'))
+                        .is_some_and(|(k, _)| k.eq_ignore_ascii_case(key))
+                });
+
+                match existing {
+                    // Keep the key as it was written.
+                    Some(i) => {
+                        let indent = section[i].len() - section[i].trim_start().len();
+                        let key_end = indent + section[i][indent + 1..].find(':').unwrap() + 2;
+                        section[i] = format!("{} {}", &section[i][..key_end], value)
+                            .trim_end()
+                            .to_string();
+                    }
+                    None => section.insert(end, line),
+                }
+            }
+            None => {
+                let drawer = vec![":PROPERTIES:".to_string(), line, ":END:".to_string()];
+                section.splice(start..start, drawer);
+            }
+        }
+
+        self.refresh();
+        Ok(())
+    }
+
+    /// Sets or clears the `SCHEDULED:` timestamp.
+    pub fn set_scheduled(
+        &mut self,
+        path: &[usize],
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), EditError> {
+        let heading = self.edit(path)?;
+        let mut planning = heading.planning();
+        planning.scheduled = timestamp;
+        set_planning(
+            heading,
+            planning.deadline,
+            planning.scheduled,
+            planning.closed,
+        );
+
+        self.refresh();
+        Ok(())
+    }
+
+    /// Sets or clears the `DEADLINE:` timestamp.
+    pub fn set_deadline(
+        &mut self,
+        path: &[usize],
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), EditError> {
+        let heading = self.edit(path)?;
+        let mut planning = heading.planning();
+        planning.deadline = timestamp;
+        set_planning(
+            heading,
+            planning.deadline,
+            planning.scheduled,
+            planning.closed,
+        );
+
+        self.refresh();
+        Ok(())
+    }
+
+    /// Adds a `- Note taken on [...]` entry at the top of the `:LOGBOOK:`
+    /// drawer, creating the drawer after the planning line and properties if
+    /// needed.
+    pub fn add_note(
+        &mut self,
+        path: &[usize],
+        note: &str,
+        time: &Timestamp,
+    ) -> Result<(), EditError> {
+        let section = &mut self.edit(path)?.section;
+
+        let mut time = time.clone();
+        time.active = false;
+
+        let mut entry = vec![format!("- Note taken on {} \\\\", time.to_org())];
+        entry.extend(
+            note.lines()
+                .map(|line| format!("  {}", line).trim_end().to_string()),
+        );
+
+        let mut start = planning_end(section);
+
+        if let Some(end) = drawer(section, start, ":PROPERTIES:") {
+            start = end + 1;
+        }
+
+        match drawer(section, start, ":LOGBOOK:") {
+            Some(_) => {
+                section.splice(start + 1..start + 1, entry);
+            }
+            None => {
+                entry.insert(0, ":LOGBOOK:".to_string());
+                entry.push(":END:".to_string());
+                section.splice(start..start, entry);
+            }
+        }
+
+        self.refresh();
+        Ok(())
+    }
+
+    fn edit(&mut self, path: &[usize]) -> Result<&mut Heading, EditError> {
+        self.heading_mut(path)
+            .ok_or_else(|| EditError::NoSuchHeading(path.to_vec()))
+    }
+
+    /// Re-reads the document from its text, which settles nesting after
+    /// level changes and renumbers lines.
+    fn refresh(&mut self) {
+        *self = parse(&self.to_org());
+    }
+
+    /// Refreshes after a level change at `path` and returns the heading's
+    /// new path. Its line does not change, only its place in the outline.
+    fn restructure(&mut self, path: &[usize]) -> Path {
+        let line = self.heading(path).unwrap().line;
+        self.refresh();
+        self.path_at_line(line)
+    }
+}
+
+/// Index just past the planning line, if the section starts with one.
+fn planning_end(section: &[String]) -> usize {
+    match section.first() {
+        Some(line) if is_planning(line.trim()) => 1,
+        _ => 0,
+    }
+}
+
+/// Index of the `:END:` of the drawer `name` starting at `section[start]`.
+fn drawer(section: &[String], start: usize, name: &str) -> Option<usize> {
+    if !section.get(start)?.trim().eq_ignore_ascii_case(name) {
+        return None;
+    }
+
+    (start + 1..section.len()).find(|i| section[*i].trim().eq_ignore_ascii_case(":END:"))
+}
+
+/// Rewrites the planning line, in the order Org itself writes it, removing
+/// it when nothing is left.
+fn set_planning(
+    heading: &mut Heading,
+    deadline: Option<Timestamp>,
+    scheduled: Option<Timestamp>,
+    closed: Option<Timestamp>,
+) {
+    let mut parts = vec![];
+
+    for (keyword, timestamp) in [
+        ("DEADLINE", deadline),
+        ("SCHEDULED", scheduled),
+        ("CLOSED", closed),
+    ] {
+        if let Some(timestamp) = timestamp {
+            parts.push(format!("{}: {}", keyword, timestamp.to_org()));
+        }
+    }
+
+    let existing = planning_end(&heading.section) == 1;
+
+    match (existing, parts.is_empty()) {
+        (true, true) => {
+            heading.section.remove(0);
+        }
+        (true, false) => {
+            let line = &heading.section[0];
+            let indent = &line[..line.len() - line.trim_start().len()];
+            heading.section[0] = format!("{}{}", indent, parts.join(" "));
+        }
+        (false, false) => heading.section.insert(0, parts.join(" ")),
+        (false, true) => (),
+    }
+}
+
+fn is_tag(tag: &str) -> bool {
+    regex!(r"^[\w@#%]+$").is_match(tag)
+}
+
+fn check_line(text: &str) -> Result<(), EditError> {
+    if text.contains('\n') {
+        Err(EditError::InvalidText(text.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Titles and tags that would not read back the same, or section lines that
+/// would read as headings.
+fn check_subtree(heading: &Heading) -> Result<(), EditError> {
+    check_line(&heading.title)?;
+
+    if let Some(tag) = heading.tags.iter().find(|tag| !is_tag(tag)) {
+        return Err(EditError::InvalidTag(tag.to_string()));
+    }
+
+    if let Some(line) = heading
+        .section
+        .iter()
+        .find(|line| line.contains('\n') || heading_level(line).is_some())
+    {
+        return Err(EditError::InvalidText(line.to_string()));
+    }
+
+    heading.children.iter().try_for_each(check_subtree)
+}
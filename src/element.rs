@@ -0,0 +1,348 @@
+/// `#+KEY: value`. Affiliated keywords may carry an optional value in
+/// brackets, e.g. `#+CAPTION[Short]: Long caption`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Keyword {
+    pub key: String,
+    pub optional: Option<String>,
+    pub value: String,
+    pub line: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Item {
+    pub indent: usize,
+    pub bullet: String,
+    pub checkbox: Option<char>,
+    pub line: usize,
+    pub lines: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Kind {
+    Keyword(Keyword),
+    Paragraph,
+    List(Vec<Item>),
+    Table,
+    Block {
+        name: String,
+        parameters: String,
+    },
+    /// Lines from `\begin{name}` to `\end{name}`, kept as written.
+    LatexEnvironment {
+        name: String,
+    },
+    Drawer {
+        name: String,
+    },
+    Planning,
+    Comment,
+    FixedWidth,
+    HorizontalRule,
+    Blank,
+}
+
+/// A block-level element of a section. `lines` holds the raw lines,
+/// starting with those of the affiliated keywords, so that concatenating the
+/// lines of every element gives back the section.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Element {
+    pub kind: Kind,
+    pub affiliated: Vec<Keyword>,
+    pub line: usize,
+    pub lines: Vec<String>,
+}
+
+/// Keywords attached to the element that follows them rather than standing
+/// on their own.
+pub const AFFILIATED_KEYWORDS: [&str; 6] =
+    ["NAME", "CAPTION", "HEADER", "PLOT", "RESULTS", "TBLNAME"];
+
+impl Element {
+    /// Lines of the element itself, without affiliated keywords.
+    pub fn body(&self) -> &[String] {
+        &self.lines[self.affiliated.len()..]
+    }
+
+    /// Value of the affiliated `#+NAME:`.
+    pub fn name(&self) -> Option<&str> {
+        self.affiliated
+            .iter()
+            .find(|keyword| keyword.key == "NAME")
+            .map(|keyword| keyword.value.as_str())
+    }
+
+    /// Values of `#+ATTR_BACKEND:` lines, e.g. `attributes("HTML")`.
+    pub fn attributes(&self, backend: &str) -> Vec<&str> {
+        let key = format!("ATTR_{}", backend.to_uppercase());
+
+        self.affiliated
+            .iter()
+            .filter(|keyword| keyword.key == key)
+            .map(|keyword| keyword.value.as_str())
+            .collect()
+    }
+}
+
+impl Keyword {
+    pub fn parse(line: &str, line_number: usize) -> Option<Keyword> {
+        let keyword = regex!(r"^\s*#\+([^\s:\[]+)(?:\[(.*)\])?:(?:\s+(.*?))?\s*$");
+
+        keyword.captures(line).map(|captures| Keyword {
+            key: captures[1].to_uppercase(),
+            optional: captures.get(2).map(|m| m.as_str().to_string()),
+            value: captures
+                .get(3)
+                .map(|m| m.as_str().to_string())
+                .unwrap_or_default(),
+            line: line_number,
+        })
+    }
+
+    pub fn is_affiliated(&self) -> bool {
+        AFFILIATED_KEYWORDS.contains(&self.key.as_str()) || self.key.starts_with("ATTR_")
+    }
+}
+
+/// Splits the lines of a section into elements. `first_line` is the line
+/// number of `lines[0]`; `heading` tells whether the section belongs to a
+/// heading, where the first line may be a planning line.
+pub fn parse(lines: &[String], first_line: usize, heading: bool) -> Vec<Element> {
+    let mut elements = vec![];
+    let mut i = 0;
+
+    while i < lines.len() {
+        let start = i;
+        let mut affiliated = vec![];
+
+        while let Some(keyword) = Keyword::parse(&lines[i], first_line + i) {
+            if !keyword.is_affiliated() || i + 1 == lines.len() || is_blank(&lines[i + 1]) {
+                break;
+            }
+
+            affiliated.push(keyword);
+            i += 1;
+        }
+
+        let (kind, end) = element(lines, i, first_line, heading && i == 0);
+
+        elements.push(Element {
+            kind,
+            affiliated,
+            line: first_line + start,
+            lines: lines[start..end].to_vec(),
+        });
+
+        i = end;
+    }
+
+    elements
+}
+
+/// The element starting at `lines[i]` and the index just past its end.
+fn element(lines: &[String], i: usize, first_line: usize, planning: bool) -> (Kind, usize) {
+    let line = &lines[i];
+    let trimmed = line.trim();
+
+    if is_blank(line) {
+        let end = (i..lines.len())
+            .find(|j| !is_blank(&lines[*j]))
+            .unwrap_or(lines.len());
+        return (Kind::Blank, end);
+    }
+
+    if planning && is_planning(trimmed) {
+        return (Kind::Planning, i + 1);
+    }
+
+    if let Some((name, parameters)) = block_start(trimmed) {
+        let end = format!("#+END_{}", name);
+
+        if let Some(j) = (i + 1..lines.len()).find(|j| lines[*j].trim().eq_ignore_ascii_case(&end))
+        {
+            return (Kind::Block { name, parameters }, j + 1);
+        }
+    }
+
+    if let Some(name) = latex_environment_start(trimmed) {
+        let end = format!("\\end{{{}}}", name);
+
+        if let Some(j) = (i + 1..lines.len()).find(|j| lines[*j].trim_start().starts_with(&end)) {
+            return (Kind::LatexEnvironment { name }, j + 1);
+        }
+    }
+
+    if let Some(keyword) = Keyword::parse(line, first_line + i) {
+        return (Kind::Keyword(keyword), i + 1);
+    }
+
+    if let Some(name) = drawer_start(trimmed) {
+        if let Some(j) =
+            (i + 1..lines.len()).find(|j| lines[*j].trim().eq_ignore_ascii_case(":END:"))
+        {
+            return (Kind::Drawer { name }, j + 1);
+        }
+    }
+
+    if trimmed.starts_with('|') {
+        let end = (i..lines.len())
+            .find(|j| {
+                let line = lines[*j].trim_start();
+                !line.starts_with('|') && !line.to_uppercase().starts_with("#+TBLFM:")
+            })
+            .unwrap_or(lines.len());
+        return (Kind::Table, end);
+    }
+
+    if is_comment(trimmed) {
+        let end = (i..lines.len())
+            .find(|j| !is_comment(lines[*j].trim()))
+            .unwrap_or(lines.len());
+        return (Kind::Comment, end);
+    }
+
+    if is_fixed_width(trimmed) {
+        let end = (i..lines.len())
+            .find(|j| !is_fixed_width(lines[*j].trim()))
+            .unwrap_or(lines.len());
+        return (Kind::FixedWidth, end);
+    }
+
+    if trimmed.len() >= 5 && trimmed.chars().all(|c| c == '-') {
+        return (Kind::HorizontalRule, i + 1);
+    }
+
+    if item(line, first_line + i).is_some() {
+        return list(lines, i, first_line);
+    }
+
+    let end = (i + 1..lines.len())
+        .find(|j| starts_element(&lines[*j], first_line + j))
+        .unwrap_or(lines.len());
+
+    (Kind::Paragraph, end)
+}
+
+/// Whether a line interrupts a paragraph.
+fn starts_element(line: &str, line_number: usize) -> bool {
+    let trimmed = line.trim();
+
+    is_blank(line)
+        || block_start(trimmed).is_some()
+        || latex_environment_start(trimmed).is_some()
+        || Keyword::parse(line, line_number).is_some()
+        || drawer_start(trimmed).is_some()
+        || trimmed.starts_with('|')
+        || is_comment(trimmed)
+        || is_fixed_width(trimmed)
+        || item(line, line_number).is_some()
+}
+
+fn list(lines: &[String], start: usize, first_line: usize) -> (Kind, usize) {
+    let mut items: Vec<Item> = vec![];
+    let base = indentation(&lines[start]);
+    let mut i = start;
+
+    while i < lines.len() {
+        let line = &lines[i];
+
+        if let Some(item) = item(line, first_line + i) {
+            if item.indent < base {
+                break;
+            }
+
+            items.push(item);
+            i += 1;
+            continue;
+        }
+
+        // Continuation lines are indented deeper than the bullets; a single
+        // blank line may separate them, two end the list.
+        let next = if is_blank(line) { i + 1 } else { i };
+        let continues = next < lines.len()
+            && !is_blank(&lines[next])
+            && (indentation(&lines[next]) > base
+                || item(&lines[next], first_line + next).is_some_and(|item| item.indent >= base));
+
+        if !continues {
+            break;
+        }
+
+        items.last_mut().unwrap().lines.push(line.to_string());
+        i += 1;
+    }
+
+    (Kind::List(items), i)
+}
+
+fn item(line: &str, line_number: usize) -> Option<Item> {
+    let bullet = regex!(r"^(\s*)([-+*]|\d+[.)])(?:\s+|$)(\[[ xX-]\]\s)?");
+    let captures = bullet.captures(line)?;
+    let indent = captures[1].len();
+
+    // A star at the start of a line is a heading, not a bullet.
+    if &captures[2] == "*" && indent == 0 {
+        return None;
+    }
+
+    Some(Item {
+        indent,
+        bullet: captures[2].to_string(),
+        checkbox: captures.get(3).and_then(|m| m.as_str().chars().nth(1)),
+        line: line_number,
+        lines: vec![line.to_string()],
+    })
+}
+
+fn block_start(trimmed: &str) -> Option<(String, String)> {
+    let begin = regex!(r"(?i)^#\+BEGIN_(\S+)(?:\s+(.*))?$");
+
+    begin.captures(trimmed).map(|captures| {
+        (
+            captures[1].to_uppercase(),
+            captures
+                .get(2)
+                .map(|m| m.as_str().trim().to_string())
+                .unwrap_or_default(),
+        )
+    })
+}
+
+/// The name of the environment a `\begin{name}` line opens.
+pub fn latex_environment_start(trimmed: &str) -> Option<String> {
+    let begin = regex!(r"^\\begin\{([A-Za-z0-9*]+)\}");
+
+    begin
+        .captures(trimmed)
+        .map(|captures| captures[1].to_string())
+}
+
+pub fn drawer_start(trimmed: &str) -> Option<String> {
+    let drawer = regex!(r"^:([\w-]+):$");
+
+    match drawer.captures(trimmed) {
+        Some(captures) if !captures[1].eq_ignore_ascii_case("END") => Some(captures[1].to_string()),
+        _ => None,
+    }
+}
+
+pub fn is_planning(trimmed: &str) -> bool {
+    ["SCHEDULED:", "DEADLINE:", "CLOSED:"]
+        .iter()
+        .any(|keyword| trimmed.starts_with(keyword))
+}
+
+fn is_comment(trimmed: &str) -> bool {
+    trimmed == "#" || trimmed.starts_with("# ")
+}
+
+fn is_fixed_width(trimmed: &str) -> bool {
+    trimmed == ":" || trimmed.starts_with(": ")
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
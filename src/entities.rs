@@ -0,0 +1,380 @@
+use crate::scanner::Token;
+use crate::scanner::TokenType;
+use std::ops::Range;
+
+/// A named entity such as `\alpha` and how each kind of export writes it,
+/// after `org-entities`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Entity {
+    pub name: &'static str,
+    pub latex: &'static str,
+    /// Whether `latex` only works in math mode.
+    pub math: bool,
+    pub html: &'static str,
+    pub ascii: &'static str,
+    pub utf8: &'static str,
+}
+
+/// Strings that exports turn into entities, like
+/// `org-export-with-special-strings`. Longer ones come first.
+pub const SPECIAL_STRINGS: [(&str, &str); 4] = [
+    ("---", "mdash"),
+    ("--", "ndash"),
+    ("...", "hellip"),
+    ("\\-", "shy"),
+];
+
+/// Name, LaTeX, math mode, HTML, ASCII and UTF-8.
+#[rustfmt::skip]
+static ENTITIES: [(&str, &str, bool, &str, &str, &str); 251] = [
+    // Latin letters.
+    ("Agrave", "\\`{A}", false, "&Agrave;", "A", "À"),
+    ("agrave", "\\`{a}", false, "&agrave;", "a", "à"),
+    ("Aacute", "\\'{A}", false, "&Aacute;", "A", "Á"),
+    ("aacute", "\\'{a}", false, "&aacute;", "a", "á"),
+    ("Acirc", "\\^{A}", false, "&Acirc;", "A", "Â"),
+    ("acirc", "\\^{a}", false, "&acirc;", "a", "â"),
+    ("Atilde", "\\~{A}", false, "&Atilde;", "A", "Ã"),
+    ("atilde", "\\~{a}", false, "&atilde;", "a", "ã"),
+    ("Auml", "\\\"{A}", false, "&Auml;", "Ae", "Ä"),
+    ("auml", "\\\"{a}", false, "&auml;", "ae", "ä"),
+    ("Aring", "\\AA{}", false, "&Aring;", "A", "Å"),
+    ("aring", "\\aa{}", false, "&aring;", "a", "å"),
+    ("AElig", "\\AE{}", false, "&AElig;", "AE", "Æ"),
+    ("aelig", "\\ae{}", false, "&aelig;", "ae", "æ"),
+    ("Ccedil", "\\c{C}", false, "&Ccedil;", "C", "Ç"),
+    ("ccedil", "\\c{c}", false, "&ccedil;", "c", "ç"),
+    ("Egrave", "\\`{E}", false, "&Egrave;", "E", "È"),
+    ("egrave", "\\`{e}", false, "&egrave;", "e", "è"),
+    ("Eacute", "\\'{E}", false, "&Eacute;", "E", "É"),
+    ("eacute", "\\'{e}", false, "&eacute;", "e", "é"),
+    ("Ecirc", "\\^{E}", false, "&Ecirc;", "E", "Ê"),
+    ("ecirc", "\\^{e}", false, "&ecirc;", "e", "ê"),
+    ("Euml", "\\\"{E}", false, "&Euml;", "E", "Ë"),
+    ("euml", "\\\"{e}", false, "&euml;", "e", "ë"),
+    ("Igrave", "\\`{I}", false, "&Igrave;", "I", "Ì"),
+    ("igrave", "\\`{i}", false, "&igrave;", "i", "ì"),
+    ("Iacute", "\\'{I}", false, "&Iacute;", "I", "Í"),
+    ("iacute", "\\'{i}", false, "&iacute;", "i", "í"),
+    ("Icirc", "\\^{I}", false, "&Icirc;", "I", "Î"),
+    ("icirc", "\\^{i}", false, "&icirc;", "i", "î"),
+    ("Iuml", "\\\"{I}", false, "&Iuml;", "I", "Ï"),
+    ("iuml", "\\\"{i}", false, "&iuml;", "i", "ï"),
+    ("Ntilde", "\\~{N}", false, "&Ntilde;", "N", "Ñ"),
+    ("ntilde", "\\~{n}", false, "&ntilde;", "n", "ñ"),
+    ("Ograve", "\\`{O}", false, "&Ograve;", "O", "Ò"),
+    ("ograve", "\\`{o}", false, "&ograve;", "o", "ò"),
+    ("Oacute", "\\'{O}", false, "&Oacute;", "O", "Ó"),
+    ("oacute", "\\'{o}", false, "&oacute;", "o", "ó"),
+    ("Ocirc", "\\^{O}", false, "&Ocirc;", "O", "Ô"),
+    ("ocirc", "\\^{o}", false, "&ocirc;", "o", "ô"),
+    ("Otilde", "\\~{O}", false, "&Otilde;", "O", "Õ"),
+    ("otilde", "\\~{o}", false, "&otilde;", "o", "õ"),
+    ("Ouml", "\\\"{O}", false, "&Ouml;", "Oe", "Ö"),
+    ("ouml", "\\\"{o}", false, "&ouml;", "oe", "ö"),
+    ("Oslash", "\\O", false, "&Oslash;", "O", "Ø"),
+    ("oslash", "\\o{}", false, "&oslash;", "o", "ø"),
+    ("OElig", "\\OE{}", false, "&OElig;", "OE", "Œ"),
+    ("oelig", "\\oe{}", false, "&oelig;", "oe", "œ"),
+    ("szlig", "\\ss{}", false, "&szlig;", "ss", "ß"),
+    ("Ugrave", "\\`{U}", false, "&Ugrave;", "U", "Ù"),
+    ("ugrave", "\\`{u}", false, "&ugrave;", "u", "ù"),
+    ("Uacute", "\\'{U}", false, "&Uacute;", "U", "Ú"),
+    ("uacute", "\\'{u}", false, "&uacute;", "u", "ú"),
+    ("Ucirc", "\\^{U}", false, "&Ucirc;", "U", "Û"),
+    ("ucirc", "\\^{u}", false, "&ucirc;", "u", "û"),
+    ("Uuml", "\\\"{U}", false, "&Uuml;", "Ue", "Ü"),
+    ("uuml", "\\\"{u}", false, "&uuml;", "ue", "ü"),
+    ("Yacute", "\\'{Y}", false, "&Yacute;", "Y", "Ý"),
+    ("yacute", "\\'{y}", false, "&yacute;", "y", "ý"),
+    ("yuml", "\\\"{y}", false, "&yuml;", "y", "ÿ"),
+    ("ETH", "\\DH{}", false, "&ETH;", "D", "Ð"),
+    ("eth", "\\dh{}", false, "&eth;", "dh", "ð"),
+    ("THORN", "\\TH{}", false, "&THORN;", "TH", "Þ"),
+    ("thorn", "\\th{}", false, "&thorn;", "th", "þ"),
+    // Greek letters.
+    ("Alpha", "A", false, "&Alpha;", "Alpha", "Α"),
+    ("alpha", "\\alpha", true, "&alpha;", "alpha", "α"),
+    ("Beta", "B", false, "&Beta;", "Beta", "Β"),
+    ("beta", "\\beta", true, "&beta;", "beta", "β"),
+    ("Gamma", "\\Gamma", true, "&Gamma;", "Gamma", "Γ"),
+    ("gamma", "\\gamma", true, "&gamma;", "gamma", "γ"),
+    ("Delta", "\\Delta", true, "&Delta;", "Delta", "Δ"),
+    ("delta", "\\delta", true, "&delta;", "delta", "δ"),
+    ("Epsilon", "E", false, "&Epsilon;", "Epsilon", "Ε"),
+    ("epsilon", "\\epsilon", true, "&epsilon;", "epsilon", "ε"),
+    ("varepsilon", "\\varepsilon", true, "&epsilon;", "varepsilon", "ε"),
+    ("Zeta", "Z", false, "&Zeta;", "Zeta", "Ζ"),
+    ("zeta", "\\zeta", true, "&zeta;", "zeta", "ζ"),
+    ("Eta", "H", false, "&Eta;", "Eta", "Η"),
+    ("eta", "\\eta", true, "&eta;", "eta", "η"),
+    ("Theta", "\\Theta", true, "&Theta;", "Theta", "Θ"),
+    ("theta", "\\theta", true, "&theta;", "theta", "θ"),
+    ("thetasym", "\\vartheta", true, "&thetasym;", "theta", "ϑ"),
+    ("vartheta", "\\vartheta", true, "&thetasym;", "theta", "ϑ"),
+    ("Iota", "I", false, "&Iota;", "Iota", "Ι"),
+    ("iota", "\\iota", true, "&iota;", "iota", "ι"),
+    ("Kappa", "K", false, "&Kappa;", "Kappa", "Κ"),
+    ("kappa", "\\kappa", true, "&kappa;", "kappa", "κ"),
+    ("Lambda", "\\Lambda", true, "&Lambda;", "Lambda", "Λ"),
+    ("lambda", "\\lambda", true, "&lambda;", "lambda", "λ"),
+    ("Mu", "M", false, "&Mu;", "Mu", "Μ"),
+    ("mu", "\\mu", true, "&mu;", "mu", "μ"),
+    ("Nu", "N", false, "&Nu;", "Nu", "Ν"),
+    ("nu", "\\nu", true, "&nu;", "nu", "ν"),
+    ("Xi", "\\Xi", true, "&Xi;", "Xi", "Ξ"),
+    ("xi", "\\xi", true, "&xi;", "xi", "ξ"),
+    ("Omicron", "O", false, "&Omicron;", "Omicron", "Ο"),
+    ("omicron", "\\textit{o}", false, "&omicron;", "omicron", "ο"),
+    ("Pi", "\\Pi", true, "&Pi;", "Pi", "Π"),
+    ("pi", "\\pi", true, "&pi;", "pi", "π"),
+    ("Rho", "P", false, "&Rho;", "Rho", "Ρ"),
+    ("rho", "\\rho", true, "&rho;", "rho", "ρ"),
+    ("Sigma", "\\Sigma", true, "&Sigma;", "Sigma", "Σ"),
+    ("sigma", "\\sigma", true, "&sigma;", "sigma", "σ"),
+    ("sigmaf", "\\varsigma", true, "&sigmaf;", "sigmaf", "ς"),
+    ("varsigma", "\\varsigma", true, "&sigmaf;", "varsigma", "ς"),
+    ("Tau", "T", false, "&Tau;", "Tau", "Τ"),
+    ("tau", "\\tau", true, "&tau;", "tau", "τ"),
+    ("Upsilon", "\\Upsilon", true, "&Upsilon;", "Upsilon", "Υ"),
+    ("upsilon", "\\upsilon", true, "&upsilon;", "upsilon", "υ"),
+    ("Phi", "\\Phi", true, "&Phi;", "Phi", "Φ"),
+    ("phi", "\\phi", true, "&phi;", "phi", "ɸ"),
+    ("varphi", "\\varphi", true, "&varphi;", "varphi", "φ"),
+    ("Chi", "X", false, "&Chi;", "Chi", "Χ"),
+    ("chi", "\\chi", true, "&chi;", "chi", "χ"),
+    ("Psi", "\\Psi", true, "&Psi;", "Psi", "Ψ"),
+    ("psi", "\\psi", true, "&psi;", "psi", "ψ"),
+    ("Omega", "\\Omega", true, "&Omega;", "Omega", "Ω"),
+    ("omega", "\\omega", true, "&omega;", "omega", "ω"),
+    // Spaces, dashes and quotes.
+    ("nbsp", "~", false, "&nbsp;", " ", "\u{a0}"),
+    ("ensp", "\\hspace*{.5em}", false, "&ensp;", " ", "\u{2002}"),
+    ("emsp", "\\hspace*{1em}", false, "&emsp;", " ", "\u{2003}"),
+    ("thinsp", "\\hspace*{.2em}", false, "&thinsp;", " ", "\u{2009}"),
+    ("shy", "\\-", false, "&shy;", "", "\u{ad}"),
+    ("ndash", "--", false, "&ndash;", "-", "–"),
+    ("mdash", "---", false, "&mdash;", "--", "—"),
+    ("hellip", "\\dots{}", false, "&hellip;", "...", "…"),
+    ("dots", "\\dots{}", false, "&hellip;", "...", "…"),
+    ("laquo", "\\guillemotleft{}", false, "&laquo;", "<<", "«"),
+    ("raquo", "\\guillemotright{}", false, "&raquo;", ">>", "»"),
+    ("lsquo", "\\textquoteleft{}", false, "&lsquo;", "`", "‘"),
+    ("rsquo", "\\textquoteright{}", false, "&rsquo;", "'", "’"),
+    ("ldquo", "\\textquotedblleft{}", false, "&ldquo;", "\"", "“"),
+    ("rdquo", "\\textquotedblright{}", false, "&rdquo;", "\"", "”"),
+    ("bdquo", "\\quotedblbase{}", false, "&bdquo;", "\"", "„"),
+    ("iexcl", "!`", false, "&iexcl;", "!", "¡"),
+    ("iquest", "?`", false, "&iquest;", "?", "¿"),
+    // Other symbols.
+    ("amp", "\\&", false, "&amp;", "&", "&"),
+    ("lt", "\\textless{}", false, "&lt;", "<", "<"),
+    ("gt", "\\textgreater{}", false, "&gt;", ">", ">"),
+    ("dollar", "\\$", false, "&#36;", "$", "$"),
+    ("percent", "\\%", false, "&#37;", "%", "%"),
+    ("backslash", "\\textbackslash{}", false, "&#92;", "\\", "\\"),
+    ("sect", "\\S", false, "&sect;", "paragraph", "§"),
+    ("para", "\\P{}", false, "&para;", "[pilcrow]", "¶"),
+    ("copy", "\\textcopyright{}", false, "&copy;", "(c)", "©"),
+    ("reg", "\\textregistered{}", false, "&reg;", "(r)", "®"),
+    ("trade", "\\texttrademark{}", false, "&trade;", "TM", "™"),
+    ("deg", "\\textdegree{}", false, "&deg;", "degree", "°"),
+    ("micro", "\\textmu{}", false, "&micro;", "micro", "µ"),
+    ("middot", "\\textperiodcentered{}", false, "&middot;", ".", "·"),
+    ("bull", "\\textbullet{}", false, "&bull;", "*", "•"),
+    ("dagger", "\\textdagger{}", false, "&dagger;", "[dagger]", "†"),
+    ("Dagger", "\\textdaggerdbl{}", false, "&Dagger;", "[doubledagger]", "‡"),
+    ("euro", "\\texteuro{}", false, "&euro;", "EUR", "€"),
+    ("cent", "\\textcent{}", false, "&cent;", "cent", "¢"),
+    ("pound", "\\pounds{}", false, "&pound;", "pound", "£"),
+    ("yen", "\\textyen{}", false, "&yen;", "yen", "¥"),
+    ("frac12", "\\textonehalf{}", false, "&frac12;", "1/2", "½"),
+    ("frac14", "\\textonequarter{}", false, "&frac14;", "1/4", "¼"),
+    ("frac34", "\\textthreequarters{}", false, "&frac34;", "3/4", "¾"),
+    ("sup2", "\\textsuperscript{2}", false, "&sup2;", "^2", "²"),
+    ("sup3", "\\textsuperscript{3}", false, "&sup3;", "^3", "³"),
+    ("plusmn", "\\textpm{}", false, "&plusmn;", "+-", "±"),
+    ("not", "\\textlnot{}", false, "&not;", "[angled dash]", "¬"),
+    // Mathematics.
+    ("pm", "\\pm", true, "&plusmn;", "+-", "±"),
+    ("minus", "\\minus", true, "&minus;", "-", "−"),
+    ("times", "\\times", true, "&times;", "*", "×"),
+    ("div", "\\div", true, "&divide;", "/", "÷"),
+    ("ast", "\\ast", true, "&lowast;", "*", "∗"),
+    ("lowast", "\\ast", true, "&lowast;", "*", "∗"),
+    ("star", "\\star", true, "*", "*", "⋆"),
+    ("cdot", "\\cdot", true, "&sdot;", "[dot]", "⋅"),
+    ("sdot", "\\cdot", true, "&sdot;", "[dot]", "⋅"),
+    ("prime", "\\prime", true, "&prime;", "'", "′"),
+    ("Prime", "\\prime{}\\prime", true, "&Prime;", "''", "″"),
+    ("infin", "\\infty", true, "&infin;", "[infinity]", "∞"),
+    ("infty", "\\infty", true, "&infin;", "[infinity]", "∞"),
+    ("le", "\\le", true, "&le;", "<=", "≤"),
+    ("leq", "\\le", true, "&le;", "<=", "≤"),
+    ("ge", "\\ge", true, "&ge;", ">=", "≥"),
+    ("geq", "\\ge", true, "&ge;", ">=", "≥"),
+    ("ne", "\\ne", true, "&ne;", "[not equal to]", "≠"),
+    ("neq", "\\neq", true, "&ne;", "[not equal to]", "≠"),
+    ("equiv", "\\equiv", true, "&equiv;", "[identical to]", "≡"),
+    ("approx", "\\approx", true, "&asymp;", "[approximately equal to]", "≈"),
+    ("sim", "\\sim", true, "&sim;", "~", "∼"),
+    ("cong", "\\cong", true, "&cong;", "[approx. equal to]", "≅"),
+    ("prop", "\\propto", true, "&prop;", "[proportional to]", "∝"),
+    ("propto", "\\propto", true, "&prop;", "[proportional to]", "∝"),
+    ("sum", "\\sum", true, "&sum;", "[sum]", "∑"),
+    ("prod", "\\prod", true, "&prod;", "[product]", "∏"),
+    ("int", "\\int", true, "&int;", "[integral]", "∫"),
+    ("radic", "\\sqrt{\\,}", true, "&radic;", "[square root]", "√"),
+    ("partial", "\\partial", true, "&part;", "[partial differential]", "∂"),
+    ("nabla", "\\nabla", true, "&nabla;", "[nabla]", "∇"),
+    ("forall", "\\forall", true, "&forall;", "[for all]", "∀"),
+    ("exist", "\\exists", true, "&exist;", "[there exists]", "∃"),
+    ("exists", "\\exists", true, "&exist;", "[there exists]", "∃"),
+    ("empty", "\\emptyset", true, "&empty;", "[empty set]", "∅"),
+    ("emptyset", "\\emptyset", true, "&empty;", "[empty set]", "∅"),
+    ("isin", "\\in", true, "&isin;", "[element of]", "∈"),
+    ("in", "\\in", true, "&isin;", "[element of]", "∈"),
+    ("notin", "\\notin", true, "&notin;", "[not an element of]", "∉"),
+    ("sub", "\\subset", true, "&sub;", "[subset of]", "⊂"),
+    ("subset", "\\subset", true, "&sub;", "[subset of]", "⊂"),
+    ("sup", "\\supset", true, "&sup;", "[superset of]", "⊃"),
+    ("supset", "\\supset", true, "&sup;", "[superset of]", "⊃"),
+    ("cap", "\\cap", true, "&cap;", "[intersection]", "∩"),
+    ("cup", "\\cup", true, "&cup;", "[union]", "∪"),
+    ("and", "\\wedge", true, "&and;", "[logical and]", "∧"),
+    ("or", "\\vee", true, "&or;", "[logical or]", "∨"),
+    ("ang", "\\angle", true, "&ang;", "[angle]", "∠"),
+    ("perp", "\\perp", true, "&perp;", "[up tack]", "⊥"),
+    ("there4", "\\therefore", true, "&there4;", "[therefore]", "∴"),
+    ("oplus", "\\oplus", true, "&oplus;", "[circled plus]", "⊕"),
+    ("otimes", "\\otimes", true, "&otimes;", "[circled times]", "⊗"),
+    ("aleph", "\\aleph", true, "&alefsym;", "aleph", "ℵ"),
+    ("hbar", "\\hbar", true, "&hbar;", "hbar", "ℏ"),
+    ("ell", "\\ell", true, "&ell;", "ell", "ℓ"),
+    ("Re", "\\Re", true, "&real;", "R", "ℜ"),
+    ("Im", "\\Im", true, "&image;", "I", "ℑ"),
+    ("wp", "\\wp", true, "&weierp;", "P", "℘"),
+    ("lang", "\\langle", true, "&lang;", "<", "⟨"),
+    ("rang", "\\rangle", true, "&rang;", ">", "⟩"),
+    ("lceil", "\\lceil", true, "&lceil;", "[left ceiling]", "⌈"),
+    ("rceil", "\\rceil", true, "&rceil;", "[right ceiling]", "⌉"),
+    ("lfloor", "\\lfloor", true, "&lfloor;", "[left floor]", "⌊"),
+    ("rfloor", "\\rfloor", true, "&rfloor;", "[right floor]", "⌋"),
+    // Arrows.
+    ("larr", "\\leftarrow", true, "&larr;", "<-", "←"),
+    ("leftarrow", "\\leftarrow", true, "&larr;", "<-", "←"),
+    ("rarr", "\\rightarrow", true, "&rarr;", "->", "→"),
+    ("rightarrow", "\\rightarrow", true, "&rarr;", "->", "→"),
+    ("to", "\\to", true, "&rarr;", "->", "→"),
+    ("uarr", "\\uparrow", true, "&uarr;", "[uparrow]", "↑"),
+    ("darr", "\\downarrow", true, "&darr;", "[downarrow]", "↓"),
+    ("harr", "\\leftrightarrow", true, "&harr;", "<->", "↔"),
+    ("lArr", "\\Leftarrow", true, "&lArr;", "<=", "⇐"),
+    ("Leftarrow", "\\Leftarrow", true, "&lArr;", "<=", "⇐"),
+    ("rArr", "\\Rightarrow", true, "&rArr;", "=>", "⇒"),
+    ("Rightarrow", "\\Rightarrow", true, "&rArr;", "=>", "⇒"),
+    ("hArr", "\\Leftrightarrow", true, "&hArr;", "<=>", "⇔"),
+    ("crarr", "\\hookleftarrow", true, "&crarr;", "<-'", "↵"),
+    ("mapsto", "\\mapsto", true, "&mapsto;", "|->", "↦"),
+    // Miscellaneous.
+    ("check", "\\checkmark", true, "&#10003;", "[checkmark]", "✓"),
+    ("loz", "\\lozenge", true, "&loz;", "[lozenge]", "◊"),
+    ("clubs", "\\clubsuit", true, "&clubs;", "[clubs]", "♣"),
+    ("spades", "\\spadesuit", true, "&spades;", "[spades]", "♠"),
+    ("hearts", "\\heartsuit", true, "&hearts;", "[hearts]", "♥"),
+    ("diams", "\\diamondsuit", true, "&diams;", "[diamonds]", "♦"),
+    ("smile", "\\smile", true, "&#8995;", ":-)", "⌣"),
+    ("frown", "\\frown", true, "&#8994;", ":-(", "⌢"),
+];
+
+/// The entity called `name`, e.g. `entity("alpha")`.
+pub fn entity(name: &str) -> Option<Entity> {
+    ENTITIES
+        .iter()
+        .find(|row| row.0 == name)
+        .map(|&(name, latex, math, html, ascii, utf8)| Entity {
+            name,
+            latex,
+            math,
+            html,
+            ascii,
+            utf8,
+        })
+}
+
+impl Entity {
+    /// Parses `\name` or `\name{}`.
+    pub fn parse(lexeme: &str) -> Option<Entity> {
+        let name = lexeme.strip_prefix('\\')?;
+        entity(name.strip_suffix("{}").unwrap_or(name))
+    }
+
+    pub fn from_token(token: &Token) -> Option<Entity> {
+        if token.token_type == TokenType::Entity {
+            Entity::parse(&token.lexeme)
+        } else {
+            None
+        }
+    }
+}
+
+/// The special strings in `text`, which should be plain text, with their
+/// byte ranges. A `\\` line break is not a `\-` hyphen.
+pub fn special_strings(text: &str) -> Vec<(Range<usize>, Entity)> {
+    let mut found = vec![];
+    let mut i = 0;
+
+    while i < text.len() {
+        if text[i..].starts_with("\\\\") {
+            i += 2;
+            continue;
+        }
+
+        match SPECIAL_STRINGS
+            .iter()
+            .find(|(s, _)| text[i..].starts_with(s))
+        {
+            Some((string, name)) => {
+                found.push((i..i + string.len(), entity(name).unwrap()));
+                i += string.len();
+            }
+            None => i += text[i..].chars().next().unwrap().len_utf8(),
+        }
+    }
+
+    found
+}
+
+/// `text` with entities and special strings written in UTF-8, as plain
+/// text exports show them.
+pub fn utf8(text: &str) -> String {
+    let mut out = String::new();
+    let mut at = 0;
+
+    for captures in regex!(r"\\([a-zA-Z]+)(\{\})?").captures_iter(text) {
+        let whole = captures.get(0).unwrap();
+
+        if let Some(entity) = entity(&captures[1]) {
+            out.push_str(&plain(&text[at..whole.start()]));
+            out.push_str(entity.utf8);
+            at = whole.end();
+        }
+    }
+
+    out.push_str(&plain(&text[at..]));
+    out
+}
+
+fn plain(text: &str) -> String {
+    let mut out = String::new();
+    let mut at = 0;
+
+    for (range, entity) in special_strings(text) {
+        out.push_str(&text[at..range.start]);
+        out.push_str(entity.utf8);
+        at = range.end;
+    }
+
+    out.push_str(&text[at..]);
+    out
+}
@@ -0,0 +1,142 @@
+use crate::document::heading_level;
+use crate::document::todo_settings;
+use crate::document::BlockLine;
+use crate::document::Blocks;
+use crate::document::Heading;
+use crate::document::TODO_KEYWORDS;
+use std::collections::VecDeque;
+use std::str::Lines;
+
+/// A step through an Org file. `Start` and `End` come in pairs around the
+/// section lines and subheadings of a heading; the heading they carry has
+/// an empty `section` and no `children`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<'a> {
+    Start(Heading),
+    Text { line: usize, text: &'a str },
+    End(Heading),
+}
+
+/// A pull parser over the lines of a file. Only the headings still open are
+/// kept, so huge files can be processed a heading at a time.
+///
+/// Blocks are read as the parser reads them, so a `#+BEGIN_` line without
+/// an `#+END_` of its name after it doesn't swallow the rest of the file;
+/// finding those takes one look over the text before the first event.
+pub struct Events<'a> {
+    lines: Lines<'a>,
+    line: usize,
+    /// Headings started but not yet ended, outermost first.
+    open: Vec<Heading>,
+    queue: VecDeque<Event<'a>>,
+    blocks: Blocks,
+    todo_keywords: Vec<String>,
+    done_keywords: Vec<String>,
+}
+
+pub fn events(source: &str) -> Events<'_> {
+    Events::new(
+        source,
+        1,
+        vec![TODO_KEYWORDS[0].to_string()],
+        vec![TODO_KEYWORDS[1].to_string()],
+        false,
+    )
+}
+
+impl<'a> Events<'a> {
+    /// Reads `source` from line `first_line`. Unless `custom_keywords` is
+    /// set, the TODO settings in the file replace the keywords given; they
+    /// apply to the headings before them as well as after.
+    pub fn new(
+        source: &'a str,
+        first_line: usize,
+        todo_keywords: Vec<String>,
+        done_keywords: Vec<String>,
+        custom_keywords: bool,
+    ) -> Events<'a> {
+        let (todo_keywords, done_keywords) = match todo_settings(source) {
+            Some(keywords) if !custom_keywords => keywords,
+            _ => (todo_keywords, done_keywords),
+        };
+
+        Events {
+            lines: source.lines(),
+            line: first_line,
+            open: vec![],
+            queue: VecDeque::new(),
+            blocks: Blocks::new(source.lines()),
+            todo_keywords,
+            done_keywords,
+        }
+    }
+
+    /// The TODO keywords of the file.
+    pub fn todo_keywords(&self) -> &[String] {
+        &self.todo_keywords
+    }
+
+    pub fn done_keywords(&self) -> &[String] {
+        &self.done_keywords
+    }
+
+    /// The headings that contain the current position, outermost first.
+    pub fn open(&self) -> &[Heading] {
+        &self.open
+    }
+
+    fn read_line(&mut self, text: &'a str) {
+        let line = self.line;
+        self.line += 1;
+
+        let level = match self.blocks.read(text) {
+            BlockLine::Outside => heading_level(text),
+            _ => None,
+        };
+
+        if let Some(level) = level {
+            self.close(level);
+
+            let keywords: Vec<&String> = self
+                .todo_keywords
+                .iter()
+                .chain(self.done_keywords.iter())
+                .collect();
+            let heading = Heading::parse(text, level, line, &keywords);
+
+            self.open.push(heading.clone());
+            self.queue.push_back(Event::Start(heading));
+            return;
+        }
+
+        self.queue.push_back(Event::Text { line, text });
+    }
+
+    /// Ends every open heading at `level` or deeper.
+    fn close(&mut self, level: usize) {
+        while self
+            .open
+            .last()
+            .is_some_and(|heading| heading.level >= level)
+        {
+            let heading = self.open.pop().unwrap();
+            self.queue.push_back(Event::End(heading));
+        }
+    }
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Event<'a>;
+
+    fn next(&mut self) -> Option<Event<'a>> {
+        while self.queue.is_empty() {
+            match self.lines.next() {
+                Some(text) => self.read_line(text),
+                None if !self.open.is_empty() => self.close(1),
+                None => return None,
+            }
+        }
+
+        self.queue.pop_front()
+    }
+}
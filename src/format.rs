@@ -0,0 +1,387 @@
+use crate::document;
+use crate::document::Heading;
+use crate::element::Element;
+use crate::element::Item;
+use crate::element::Kind;
+use crate::timestamp::Timestamp;
+
+/// How `format` lays out a file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FormatConfig {
+    /// Like `org-tags-column`: tags start at this column, or end at it when
+    /// it is negative.
+    pub tags_column: isize,
+    /// Blank lines before every heading but one at the top of the file.
+    pub blank_lines: usize,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            tags_column: -77,
+            blank_lines: 0,
+        }
+    }
+}
+
+/// `source` in canonical form. Tags are aligned, tables re-aligned, list
+/// bullets and indentation normalised, timestamps written with the right
+/// weekday, properties sorted and `CLOCK` durations recomputed. Blocks,
+/// comments and fixed-width lines are left alone. Formatting the result
+/// again gives it back unchanged.
+pub fn format(source: &str, config: &FormatConfig) -> String {
+    let document = document::parse(source);
+    let mut sections = vec![format_section(&document.elements())];
+    let mut headings = vec![];
+
+    for (_, heading) in document.all_headings() {
+        headings.push(heading_line(heading, config));
+        sections.push(format_section(&heading.elements()));
+    }
+
+    let last = sections.len() - 1;
+    let mut out = String::new();
+
+    for (i, mut section) in sections.into_iter().enumerate() {
+        while section.last().is_some_and(|line| line.trim().is_empty()) {
+            section.pop();
+        }
+
+        if i > 0 {
+            out.push_str(&headings[i - 1]);
+            out.push('\n');
+        }
+
+        let top = i == 0 && section.is_empty();
+
+        if i < last && !top {
+            section.extend(vec![String::new(); config.blank_lines]);
+        }
+
+        for line in section {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+/// The heading line with its tags at `config.tags_column`.
+fn heading_line(heading: &Heading, config: &FormatConfig) -> String {
+    let mut line = Heading {
+        tags: vec![],
+        title: canonical_timestamps(&heading.title),
+        ..heading.clone()
+    }
+    .heading_line();
+
+    if heading.tags.is_empty() {
+        return line;
+    }
+
+    let tags = format!(":{}:", heading.tags.join(":"));
+    let width = line.chars().count() as isize;
+    let tags_width = tags.chars().count() as isize;
+
+    let padding = if config.tags_column < 0 {
+        -config.tags_column - tags_width - width
+    } else {
+        config.tags_column - width
+    };
+
+    line.push_str(&" ".repeat(padding.max(1) as usize));
+    line.push_str(&tags);
+    line
+}
+
+fn format_section(elements: &[Element]) -> Vec<String> {
+    let mut lines = vec![];
+
+    for element in elements {
+        let affiliated = element.affiliated.len();
+        lines.extend(element.lines[..affiliated].iter().cloned());
+
+        let body = element.body();
+
+        match &element.kind {
+            Kind::Block { .. }
+            | Kind::LatexEnvironment { .. }
+            | Kind::Comment
+            | Kind::FixedWidth => {
+                lines.extend(body.iter().cloned());
+            }
+            Kind::Table => lines.extend(table(&timestamps(body))),
+            Kind::List(items) => lines.extend(timestamps(&list(items))),
+            Kind::Drawer { name } if name.eq_ignore_ascii_case("PROPERTIES") => {
+                lines.extend(properties(&timestamps(body)));
+            }
+            _ => lines.extend(timestamps(body).iter().map(|line| clock(line))),
+        }
+    }
+
+    lines
+}
+
+fn timestamps(lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .map(|line| canonical_timestamps(line))
+        .collect()
+}
+
+/// Rewrites every valid timestamp in `text` the way Org writes it, with the
+/// weekday that goes with the date.
+fn canonical_timestamps(text: &str) -> String {
+    let candidate = regex!(r"[<\[]\d{4}-\d{2}-\d{2}[^\[\]<>\n]*[\]>]");
+
+    candidate
+        .replace_all(text, |captures: &regex::Captures| {
+            let found = &captures[0];
+            Timestamp::parse(found).map_or(found.to_string(), |timestamp| timestamp.to_org())
+        })
+        .to_string()
+}
+
+/// A `CLOCK:` line with both ends, with its duration worked out again.
+fn clock(line: &str) -> String {
+    let clock = regex!(r"^(\s*)CLOCK:\s*(\[[^\]]*\])--(\[[^\]]*\])(?:\s*=>\s*-?\d+:\d{2})?\s*$");
+
+    let captures = match clock.captures(line) {
+        Some(captures) => captures,
+        None => return line.to_string(),
+    };
+
+    let start = Timestamp::parse(&captures[2]).and_then(|timestamp| timestamp.minutes());
+    let end = Timestamp::parse(&captures[3]).and_then(|timestamp| timestamp.minutes());
+
+    match (start, end) {
+        (Some(start), Some(end)) if end >= start => format!(
+            "{}CLOCK: {}--{} => {:2}:{:02}",
+            &captures[1],
+            &captures[2],
+            &captures[3],
+            (end - start) / 60,
+            (end - start) % 60
+        ),
+        _ => line.to_string(),
+    }
+}
+
+/// The lines of a property drawer with the properties sorted by name. Sorting
+/// is stable, so `:KEY+:` lines stay after the `:KEY:` they add to. A drawer
+/// holding anything but properties is left as it is.
+fn properties(lines: &[String]) -> Vec<String> {
+    let property = regex!(r"^\s*:\S+:(\s|$)");
+
+    if lines.len() < 2
+        || !lines[1..lines.len() - 1]
+            .iter()
+            .all(|line| property.is_match(line))
+    {
+        return lines.to_vec();
+    }
+
+    let key = |line: &String| {
+        let line = line.trim_start().trim_start_matches(':');
+        let name = line.split(':').next().unwrap_or_default();
+        name.trim_end_matches('+').to_lowercase()
+    };
+
+    let mut properties = lines[1..lines.len() - 1].to_vec();
+    properties.sort_by_key(key);
+
+    let mut out = vec![lines[0].clone()];
+    out.extend(properties);
+    out.push(lines[lines.len() - 1].clone());
+    out
+}
+
+/// The items of a list with `-` for unordered bullets, ordered ones
+/// numbered again from 1 and sub-items indented to the text of their
+/// parent. Continuation lines move with their item.
+fn list(items: &[Item]) -> Vec<String> {
+    // The bullets of the items the current one may be nested in, as their
+    // old indentation, new indentation and new bullet.
+    let mut parents: Vec<(usize, usize, String)> = vec![];
+    let mut counters: Vec<usize> = vec![0];
+    let base = items.first().map_or(0, |item| item.indent);
+    let mut lines = vec![];
+
+    for item in items {
+        while parents
+            .last()
+            .is_some_and(|(indent, _, _)| *indent >= item.indent)
+        {
+            parents.pop();
+            counters.pop();
+        }
+
+        let indent = match parents.last() {
+            Some((_, indent, bullet)) => indent + bullet.len() + 1,
+            None => base,
+        };
+
+        let counter = counters.last_mut().unwrap();
+        *counter += 1;
+
+        let bullet = if item.bullet.starts_with(|c: char| c.is_ascii_digit()) {
+            format!("{}{}", counter, item.bullet.chars().last().unwrap())
+        } else {
+            "-".to_string()
+        };
+
+        let first = &item.lines[0];
+        let rest = first.trim_start()[item.bullet.len()..].trim_start();
+
+        let mut line = format!("{}{}", " ".repeat(indent), bullet);
+        if !rest.is_empty() {
+            line.push(' ');
+            line.push_str(rest);
+        }
+        lines.push(line);
+
+        let delta = indent as isize - item.indent as isize;
+
+        for continuation in &item.lines[1..] {
+            let text = continuation.trim_start();
+
+            if text.is_empty() {
+                lines.push(String::new());
+            } else {
+                let old = (continuation.len() - text.len()) as isize;
+                let new = (old + delta).max(base as isize + 1) as usize;
+                lines.push(format!("{}{}", " ".repeat(new), text));
+            }
+        }
+
+        parents.push((item.indent, indent, bullet));
+        counters.push(0);
+    }
+
+    lines
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+/// Re-aligns the rows of a table, padding cells to the widest in their
+/// column. Columns of mostly numbers are right-aligned, as Org does, unless
+/// a `<l>`, `<c>` or `<r>` cookie says otherwise. `#+TBLFM:` lines are kept.
+fn table(lines: &[String]) -> Vec<String> {
+    let count = lines
+        .iter()
+        .take_while(|line| line.trim_start().starts_with('|'))
+        .count();
+    let (rows, rest) = lines.split_at(count);
+
+    let indent = rows
+        .first()
+        .map_or("", |line| &line[..line.len() - line.trim_start().len()]);
+
+    let rows: Vec<Option<Vec<String>>> = rows
+        .iter()
+        .map(|line| {
+            let line = line.trim();
+
+            if line.starts_with("|-") {
+                return None;
+            }
+
+            let line = line.strip_prefix('|').unwrap_or(line);
+            let line = line.strip_suffix('|').unwrap_or(line);
+            Some(
+                line.split('|')
+                    .map(|cell| cell.trim().to_string())
+                    .collect(),
+            )
+        })
+        .collect();
+
+    let columns = rows.iter().flatten().map(Vec::len).max().unwrap_or(1);
+    let mut widths = vec![1; columns];
+    let mut aligns = vec![];
+    let cookie = regex!(r"^<([lrc])?\d*>$");
+
+    for (column, width) in widths.iter_mut().enumerate() {
+        let cells: Vec<&str> = rows
+            .iter()
+            .flatten()
+            .filter_map(|row| row.get(column))
+            .map(String::as_str)
+            .filter(|cell| !cell.is_empty())
+            .collect();
+
+        for cell in &cells {
+            *width = (*width).max(cell.chars().count());
+        }
+
+        let numbers = cells.iter().filter(|cell| is_number(cell)).count();
+        let mut align = if numbers * 2 > cells.len() {
+            Align::Right
+        } else {
+            Align::Left
+        };
+
+        if let Some(captures) = cells.iter().find_map(|cell| cookie.captures(cell)) {
+            align = match captures.get(1).map(|m| m.as_str()) {
+                Some("r") => Align::Right,
+                Some("c") => Align::Center,
+                Some(_) => Align::Left,
+                None => align,
+            };
+        }
+
+        aligns.push(align);
+    }
+
+    let mut out = vec![];
+
+    for row in rows {
+        let line = match row {
+            None => {
+                let dashes: Vec<String> =
+                    widths.iter().map(|width| "-".repeat(width + 2)).collect();
+                format!("{}|{}|", indent, dashes.join("+"))
+            }
+            Some(cells) => {
+                let cells: Vec<String> = (0..columns)
+                    .map(|column| {
+                        let cell = cells.get(column).map_or("", String::as_str);
+                        pad(cell, widths[column], aligns[column])
+                    })
+                    .collect();
+                format!("{}| {} |", indent, cells.join(" | "))
+            }
+        };
+
+        out.push(line);
+    }
+
+    out.extend(rest.iter().cloned());
+    out
+}
+
+fn pad(cell: &str, width: usize, align: Align) -> String {
+    let space = width - cell.chars().count();
+
+    match align {
+        Align::Left => format!("{}{}", cell, " ".repeat(space)),
+        Align::Right => format!("{}{}", " ".repeat(space), cell),
+        Align::Center => format!(
+            "{}{}{}",
+            " ".repeat(space / 2),
+            cell,
+            " ".repeat(space - space / 2)
+        ),
+    }
+}
+
+/// Roughly `org-table-number-regexp`.
+pub fn is_number(cell: &str) -> bool {
+    regex!(r"^[<>]?[-+^.0-9]*[0-9][-+^.0-9eEdDx()%:]*$").is_match(cell)
+}
@@ -0,0 +1,269 @@
+use crate::document::Document;
+use crate::document::Path;
+use crate::link::Link;
+use crate::link::LinkKind;
+use crate::resolve::normalise_path;
+use crate::resolve::Location;
+use crate::resolve::Resolver;
+use std::collections::BTreeMap;
+
+/// An internal link: where it is written, and what it resolves to if
+/// anything.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub source: Location,
+    pub target: Option<Location>,
+    pub link: Link,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GraphNode {
+    pub id: String,
+    pub file: String,
+    pub path: Path,
+    pub title: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub line: usize,
+}
+
+/// Index of the internal links between a set of documents, answering "what
+/// links here" for files and headings.
+#[derive(Default)]
+pub struct LinkGraph {
+    documents: BTreeMap<String, Document>,
+    edges: BTreeMap<String, Vec<Edge>>,
+}
+
+impl LinkGraph {
+    pub fn new() -> LinkGraph {
+        LinkGraph::default()
+    }
+
+    /// Adds `document` under `file`, or replaces the previous version of it.
+    /// Only the links of `file`, and links elsewhere that could point into
+    /// it, are resolved again.
+    pub fn update(&mut self, file: &str, document: Document) {
+        let file = normalise_path(file);
+
+        self.documents.insert(file.clone(), document);
+
+        let edges = self.edges_from(&file);
+        self.edges.insert(file.clone(), edges);
+        self.refresh(&file);
+    }
+
+    pub fn remove(&mut self, file: &str) {
+        let file = normalise_path(file);
+
+        self.documents.remove(&file);
+        self.edges.remove(&file);
+        self.refresh(&file);
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.values().flatten()
+    }
+
+    /// Links from `file`, resolved or not.
+    pub fn outgoing(&self, file: &str) -> Vec<&Edge> {
+        self.edges
+            .get(&normalise_path(file))
+            .map(|edges| edges.iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Links to the heading at `path` in `file`.
+    pub fn backlinks(&self, file: &str, path: &[usize]) -> Vec<&Edge> {
+        let file = normalise_path(file);
+
+        self.edges()
+            .filter(|edge| {
+                edge.target
+                    .as_ref()
+                    .is_some_and(|target| target.file == file && target.path == path)
+            })
+            .collect()
+    }
+
+    /// Links to `file` or anything inside it.
+    pub fn file_backlinks(&self, file: &str) -> Vec<&Edge> {
+        let file = normalise_path(file);
+
+        self.edges()
+            .filter(|edge| {
+                edge.target
+                    .as_ref()
+                    .is_some_and(|target| target.file == file)
+            })
+            .collect()
+    }
+
+    /// Files and headings taking part in at least one resolved link.
+    pub fn nodes(&self) -> Vec<GraphNode> {
+        let mut nodes: BTreeMap<String, GraphNode> = BTreeMap::new();
+
+        for edge in self.edges() {
+            if let Some(target) = &edge.target {
+                for location in [&edge.source, target] {
+                    nodes
+                        .entry(node_id(location))
+                        .or_insert_with(|| self.node(location));
+                }
+            }
+        }
+
+        nodes.into_values().collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Graph {
+            nodes: Vec<GraphNode>,
+            edges: Vec<GraphEdge>,
+        }
+
+        let graph = Graph {
+            nodes: self.nodes(),
+            edges: self.graph_edges(),
+        };
+
+        serde_json::to_string(&graph).unwrap()
+    }
+
+    /// The graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph links {\n");
+
+        for node in self.nodes() {
+            dot.push_str(&format!(
+                "  {} [label={}];\n",
+                quote(&node.id),
+                quote(&node.title)
+            ));
+        }
+
+        for edge in self.graph_edges() {
+            dot.push_str(&format!(
+                "  {} -> {};\n",
+                quote(&edge.source),
+                quote(&edge.target)
+            ));
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn graph_edges(&self) -> Vec<GraphEdge> {
+        self.edges()
+            .filter_map(|edge| {
+                edge.target.as_ref().map(|target| GraphEdge {
+                    source: node_id(&edge.source),
+                    target: node_id(target),
+                    line: edge.source.line,
+                })
+            })
+            .collect()
+    }
+
+    fn node(&self, location: &Location) -> GraphNode {
+        let heading = self
+            .documents
+            .get(&location.file)
+            .and_then(|document| document.heading(&location.path));
+
+        GraphNode {
+            id: node_id(location),
+            file: location.file.clone(),
+            path: location.path.clone(),
+            title: match heading {
+                Some(heading) => heading.normalised_title(),
+                None => location.file.clone(),
+            },
+        }
+    }
+
+    fn resolver(&self) -> Resolver<'_> {
+        let mut resolver = Resolver::new();
+
+        for (file, document) in &self.documents {
+            resolver.add(file, document);
+        }
+
+        resolver
+    }
+
+    fn edges_from(&self, file: &str) -> Vec<Edge> {
+        let document = match self.documents.get(file) {
+            Some(document) => document,
+            None => return vec![],
+        };
+
+        let resolver = self.resolver();
+        let mut links = document.links();
+
+        for (_, heading) in document.all_headings() {
+            links.extend(heading.links());
+        }
+
+        links
+            .into_iter()
+            .filter(|(_, link)| Resolver::is_internal(link))
+            .map(|(line, link)| Edge {
+                source: Location {
+                    file: file.to_string(),
+                    path: document.path_at_line(line),
+                    line,
+                },
+                target: resolver.resolve(file, &link),
+                link,
+            })
+            .collect()
+    }
+
+    /// Resolves again the links in other files that pointed into `file`, or
+    /// that might now: `id:` links and links that didn't resolve.
+    fn refresh(&mut self, file: &str) {
+        let resolver = self.resolver();
+        let mut updated = vec![];
+
+        for (source, edges) in &self.edges {
+            if source == file {
+                continue;
+            }
+
+            for (i, edge) in edges.iter().enumerate() {
+                let stale = match &edge.target {
+                    Some(target) => target.file == file || edge.link.kind == LinkKind::Id,
+                    None => true,
+                };
+
+                if stale {
+                    updated.push((source.clone(), i, resolver.resolve(source, &edge.link)));
+                }
+            }
+        }
+
+        for (source, i, target) in updated {
+            self.edges.get_mut(&source).unwrap()[i].target = target;
+        }
+    }
+}
+
+fn node_id(location: &Location) -> String {
+    if location.path.is_empty() {
+        location.file.clone()
+    } else {
+        let path: Vec<String> = location.path.iter().map(|i| i.to_string()).collect();
+        format!("{}::{}", location.file, path.join("."))
+    }
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,383 @@
+use crate::document::heading_level;
+use crate::document::todo_settings;
+use crate::document::BlockLine;
+use crate::document::Blocks;
+use crate::document::TODO_KEYWORDS;
+use crate::element::drawer_start;
+use crate::element::latex_environment_start;
+use crate::scanner;
+use crate::scanner::TokenType;
+use crate::timestamp::Timestamp;
+use std::ops::Range;
+
+/// What a highlighted piece of text is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SemanticType {
+    /// The stars of a heading.
+    Stars,
+    /// Title text of a heading not covered by another token.
+    Headline,
+    TodoKeyword,
+    Priority,
+    Tag,
+    Timestamp,
+    /// `*bold*`, `/italic/`, `_underline_` or `+strikethrough+`.
+    Emphasis,
+    /// `=verbatim=`, `~code~` and fixed-width lines.
+    Verbatim,
+    Link,
+    Target,
+    Macro,
+    /// The `#+KEY:` of a keyword line.
+    Keyword,
+    /// `SCHEDULED:`, `DEADLINE:`, `CLOSED:` or `CLOCK:`.
+    Planning,
+    /// A drawer's opening or closing line.
+    Drawer,
+    /// The `:KEY:` of a property.
+    Property,
+    BlockDelimiter,
+    Comment,
+    /// LaTeX fragments, entities and the lines of LaTeX environments.
+    Latex,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Modifier {
+    /// A TODO keyword that marks the heading as done.
+    Done,
+    Active,
+    Inactive,
+    Bold,
+    Italic,
+    Underline,
+    Strikethrough,
+    /// `~code~`, as against `=verbatim=`.
+    Code,
+    Radio,
+}
+
+/// A highlighted piece of text. `span` is a byte range of the source and
+/// never crosses a line break. Tokens on a heading line carry its `level`,
+/// which is 0 elsewhere.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SemanticToken {
+    pub kind: SemanticType,
+    pub modifiers: Vec<Modifier>,
+    pub level: usize,
+    pub line: usize,
+    pub span: Range<usize>,
+}
+
+/// Blocks whose contents are not Org markup.
+const VERBATIM_BLOCKS: [&str; 4] = ["SRC", "EXAMPLE", "EXPORT", "COMMENT"];
+
+/// Semantic tokens for the whole of `source`, in order and not overlapping.
+/// Text that is nothing in particular, like most of a paragraph or the body
+/// of a source block, has no token.
+pub fn highlight(source: &str) -> Vec<SemanticToken> {
+    highlight_range(source, 0..source.len())
+}
+
+/// The tokens of `source` that overlap the byte range `range`, e.g. the part
+/// of a file shown in an editor. Lines before it are only read far enough to
+/// follow blocks, drawers and TODO keyword settings.
+pub fn highlight_range(source: &str, range: Range<usize>) -> Vec<SemanticToken> {
+    let mut highlighter = Highlighter {
+        tokens: vec![],
+        level: 0,
+        line: 0,
+    };
+    let (todo_keywords, done_keywords) = todo_settings(source).unwrap_or_else(|| {
+        (
+            vec![TODO_KEYWORDS[0].to_string()],
+            vec![TODO_KEYWORDS[1].to_string()],
+        )
+    });
+    let mut blocks = Blocks::new(source.lines());
+    let mut drawer: Option<String> = None;
+    let mut environment: Option<String> = None;
+    let keyword = regex!(r"^#\+[^\s:]+:");
+    let property = regex!(r"^:[^\s:]+:");
+    let mut offset = 0;
+
+    for (i, text) in source.split_inclusive('\n').enumerate() {
+        let start = offset;
+        offset += text.len();
+
+        if start >= range.end {
+            break;
+        }
+
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let trimmed = text.trim_start();
+        let indent = start + text.len() - trimmed.len();
+        let visible = offset > range.start;
+
+        highlighter.level = 0;
+        highlighter.line = i + 1;
+        let mark = highlighter.tokens.len();
+        let block = blocks.read(text);
+
+        if let Some(end) = &environment {
+            highlighter.push(
+                SemanticType::Latex,
+                vec![],
+                indent..start + text.trim_end().len(),
+            );
+
+            if trimmed.starts_with(end.as_str()) {
+                environment = None;
+            }
+        } else if let BlockLine::Inside(name) = &block {
+            if name == "COMMENT" {
+                highlighter.push(SemanticType::Comment, vec![], indent..start + text.len());
+            } else if !VERBATIM_BLOCKS.contains(&name.as_str()) && visible {
+                highlighter.inline(text, start, None);
+            }
+        } else if matches!(block, BlockLine::Begin(_) | BlockLine::End(_)) {
+            highlighter.push(
+                SemanticType::BlockDelimiter,
+                vec![],
+                indent..start + text.len(),
+            );
+        } else if let Some(level) = heading_level(text) {
+            drawer = None;
+            highlighter.level = level;
+
+            if visible {
+                highlighter.heading(text, start, level, &todo_keywords, &done_keywords);
+            }
+        } else if let Some(end) = latex_environment_start(trimmed)
+            .map(|name| format!("\\end{{{}}}", name))
+            .filter(|end| {
+                source[offset..]
+                    .lines()
+                    .take_while(|line| heading_level(line).is_none())
+                    .any(|line| line.trim_start().starts_with(end.as_str()))
+            })
+        {
+            highlighter.push(
+                SemanticType::Latex,
+                vec![],
+                indent..start + text.trim_end().len(),
+            );
+            environment = Some(end);
+        } else if let Some(name) = drawer_start(trimmed.trim_end()).filter(|_| drawer.is_none()) {
+            drawer = Some(name.to_uppercase());
+            highlighter.push(
+                SemanticType::Drawer,
+                vec![],
+                indent..start + text.trim_end().len(),
+            );
+        } else if drawer.is_some() && trimmed.trim_end().eq_ignore_ascii_case(":END:") {
+            drawer = None;
+            highlighter.push(
+                SemanticType::Drawer,
+                vec![],
+                indent..start + text.trim_end().len(),
+            );
+        } else if let Some(captures) = keyword.captures(trimmed) {
+            let key = indent..indent + captures[0].len();
+
+            highlighter.push(SemanticType::Keyword, vec![], key.clone());
+
+            if visible {
+                highlighter.inline(&text[key.end - start..], key.end, None);
+            }
+        } else if trimmed == "#" || trimmed.starts_with("# ") {
+            highlighter.push(SemanticType::Comment, vec![], indent..start + text.len());
+        } else if trimmed == ":" || trimmed.starts_with(": ") {
+            highlighter.push(SemanticType::Verbatim, vec![], indent..start + text.len());
+        } else if let Some(captures) = property
+            .captures(trimmed)
+            .filter(|_| drawer.as_deref() == Some("PROPERTIES"))
+        {
+            let key = indent..indent + captures[0].len();
+            highlighter.push(SemanticType::Property, vec![], key.clone());
+
+            if visible {
+                highlighter.inline(&text[key.end - start..], key.end, None);
+            }
+        } else if visible {
+            highlighter.planning(text, start);
+        }
+
+        if !visible {
+            highlighter.tokens.truncate(mark);
+        }
+    }
+
+    highlighter
+        .tokens
+        .retain(|token| token.span.start < range.end && token.span.end > range.start);
+    highlighter.tokens
+}
+
+struct Highlighter {
+    tokens: Vec<SemanticToken>,
+    /// The level of the heading being highlighted, if any.
+    level: usize,
+    line: usize,
+}
+
+impl Highlighter {
+    fn push(&mut self, kind: SemanticType, modifiers: Vec<Modifier>, span: Range<usize>) {
+        if span.is_empty() {
+            return;
+        }
+
+        self.tokens.push(SemanticToken {
+            kind,
+            modifiers,
+            level: self.level,
+            line: self.line,
+            span,
+        });
+    }
+
+    fn heading(
+        &mut self,
+        text: &str,
+        start: usize,
+        level: usize,
+        todo: &[String],
+        done: &[String],
+    ) {
+        self.push(SemanticType::Stars, vec![], start..start + level);
+
+        let mut rest = start + level;
+        let mut title = text[level..].trim_start();
+        rest += text[level..].len() - title.len();
+
+        if let Some((keyword, _)) = title.split_once(' ').or(Some((title, ""))) {
+            let modifiers = if done.iter().any(|k| k == keyword) {
+                Some(vec![Modifier::Done])
+            } else if todo.iter().any(|k| k == keyword) {
+                Some(vec![])
+            } else {
+                None
+            };
+
+            if let Some(modifiers) = modifiers {
+                self.push(
+                    SemanticType::TodoKeyword,
+                    modifiers,
+                    rest..rest + keyword.len(),
+                );
+                let after = title[keyword.len()..].trim_start();
+                rest += title.len() - after.len();
+                title = after;
+            }
+        }
+
+        if let Some(found) = regex!(r"^\[#[A-Z0-9]\]").find(title) {
+            self.push(SemanticType::Priority, vec![], rest..rest + found.end());
+            let after = title[found.end()..].trim_start();
+            rest += title.len() - after.len();
+            title = after;
+        }
+
+        let tags = regex!(r"(^|\s+)(:[\w@#%:]+:)\s*$").captures(title);
+        let end = tags
+            .as_ref()
+            .map_or(title.len(), |captures| captures.get(0).unwrap().start());
+
+        self.inline(&title[..end], rest, Some(SemanticType::Headline));
+
+        if let Some(tags) = tags.and_then(|captures| captures.get(2)) {
+            let mut at = rest + tags.start();
+
+            for tag in tags.as_str().split(':') {
+                if !tag.is_empty() {
+                    self.push(SemanticType::Tag, vec![], at..at + tag.len());
+                }
+
+                at += tag.len() + 1;
+            }
+        }
+    }
+
+    /// Planning and clock lines, or else inline markup.
+    fn planning(&mut self, text: &str, start: usize) {
+        let keyword = regex!(r"\b(SCHEDULED|DEADLINE|CLOSED|CLOCK):");
+        let trimmed = text.trim_start();
+
+        if keyword.find(trimmed).is_none_or(|found| found.start() > 0) {
+            return self.inline(text, start, None);
+        }
+
+        let mut at = 0;
+
+        for found in keyword.find_iter(text) {
+            self.inline(&text[at..found.start()], start + at, None);
+            self.push(
+                SemanticType::Planning,
+                vec![],
+                start + found.start()..start + found.end(),
+            );
+            at = found.end();
+        }
+
+        self.inline(&text[at..], start + at, None);
+    }
+
+    /// Tokens for the markup in `text`, which starts at byte `start`. The
+    /// gaps between them get a `fill` token if there is one.
+    fn inline(&mut self, text: &str, start: usize, fill: Option<SemanticType>) {
+        let mut tokens = scanner::tokens(text);
+        let mut gap = 0;
+
+        while let Some(token) = tokens.next() {
+            let span = tokens.span();
+
+            let (kind, modifiers) = match token.token_type {
+                TokenType::Bold => (SemanticType::Emphasis, vec![Modifier::Bold]),
+                TokenType::Italic => (SemanticType::Emphasis, vec![Modifier::Italic]),
+                TokenType::Underline => (SemanticType::Emphasis, vec![Modifier::Underline]),
+                TokenType::Strikethrough => (SemanticType::Emphasis, vec![Modifier::Strikethrough]),
+                TokenType::Verbatim => (SemanticType::Verbatim, vec![]),
+                TokenType::Code => (SemanticType::Verbatim, vec![Modifier::Code]),
+                TokenType::Link => (SemanticType::Link, vec![]),
+                TokenType::Macro => (SemanticType::Macro, vec![]),
+                TokenType::LatexFragment | TokenType::Entity => (SemanticType::Latex, vec![]),
+                TokenType::Target => (SemanticType::Target, vec![]),
+                TokenType::RadioTarget => (SemanticType::Target, vec![Modifier::Radio]),
+                _ => match Timestamp::parse(&token.lexeme) {
+                    Some(timestamp) if token.lexeme.starts_with(['<', '[']) => {
+                        let modifier = if timestamp.active {
+                            Modifier::Active
+                        } else {
+                            Modifier::Inactive
+                        };
+
+                        (SemanticType::Timestamp, vec![modifier])
+                    }
+                    _ => continue,
+                },
+            };
+
+            if let Some(fill) = fill {
+                self.fill(fill, text, start, gap..span.start);
+            }
+
+            self.push(kind, modifiers, start + span.start..start + span.end);
+            gap = span.end;
+        }
+
+        if let Some(fill) = fill {
+            self.fill(fill, text, start, gap..text.len());
+        }
+    }
+
+    /// A `kind` token over `gap` of `text`, less surrounding whitespace.
+    fn fill(&mut self, kind: SemanticType, text: &str, start: usize, gap: Range<usize>) {
+        let piece = &text[gap.clone()];
+        let trimmed = piece.trim_start();
+        let from = gap.start + piece.len() - trimmed.len();
+        let to = from + trimmed.trim_end().len();
+
+        self.push(kind, vec![], start + from..start + to);
+    }
+}
@@ -0,0 +1,1221 @@
+use crate::document;
+use crate::document::heading_level;
+use crate::document::Document;
+use crate::document::Heading;
+use crate::edit::EditError;
+use crate::element::is_planning;
+use crate::element::Kind;
+use crate::entities;
+use crate::timestamp::Date;
+use crate::timestamp::Interval;
+use crate::timestamp::Time;
+use crate::timestamp::Timestamp;
+use crate::timestamp::Unit;
+use std::fmt;
+use std::ops::Range;
+
+/// When a `SCHEDULED` or `DEADLINE` timestamp is exported, like the values
+/// of `org-icalendar-use-scheduled` and `org-icalendar-use-deadline`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Usage {
+    /// As an event, for headings without a TODO keyword.
+    EventIfNotTodo,
+    /// As an event, for headings with any TODO keyword.
+    EventIfTodo,
+    /// As an event, for headings with a TODO keyword that isn't done.
+    EventIfTodoNotDone,
+    /// As the start (`SCHEDULED`) or due date (`DEADLINE`) of the to-do.
+    Todo,
+}
+
+/// Which TODO headings become `VTODO` entries, like
+/// `org-icalendar-include-todo`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum IncludeTodo {
+    None,
+    /// Those that aren't done.
+    Open,
+    All,
+    /// Those with one of these keywords.
+    Keywords(Vec<String>),
+}
+
+/// What goes into `CATEGORIES`, like `org-icalendar-categories`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    LocalTags,
+    AllTags,
+    TodoState,
+    /// The `:CATEGORY:` property, `#+CATEGORY:` or the file name.
+    Category,
+}
+
+/// Options for `export`, after those of `ox-icalendar`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IcalConfig {
+    pub use_scheduled: Vec<Usage>,
+    pub use_deadline: Vec<Usage>,
+    pub include_todo: IncludeTodo,
+    pub categories: Vec<Category>,
+    /// Minutes before timed events to alert when they have no warning
+    /// delay of their own; 0 for no alarm.
+    pub alarm_time: u32,
+    /// Whether other active timestamps in an entry become events.
+    pub use_plain_timestamp: bool,
+    /// How many characters of an entry's text to use as its description.
+    pub include_body: Option<usize>,
+    /// Headings with these tags are left out, with their subtrees, along
+    /// with those in each file's `#+EXCLUDE_TAGS:`.
+    pub exclude_tags: Vec<String>,
+    /// The `X-WR-TIMEZONE` of the calendar. Times are written as local
+    /// times either way.
+    pub timezone: Option<String>,
+    /// The `X-WR-CALNAME` of the calendar.
+    pub name: String,
+}
+
+impl Default for IcalConfig {
+    fn default() -> Self {
+        IcalConfig {
+            use_scheduled: vec![Usage::Todo],
+            use_deadline: vec![Usage::EventIfNotTodo, Usage::Todo],
+            include_todo: IncludeTodo::None,
+            categories: vec![Category::LocalTags, Category::Category],
+            alarm_time: 0,
+            use_plain_timestamp: true,
+            include_body: Some(100),
+            exclude_tags: vec![],
+            timezone: None,
+            name: "OrgMode".to_string(),
+        }
+    }
+}
+
+/// A heading being exported, with what it gets from its file and parents.
+struct Entry<'a> {
+    heading: &'a Heading,
+    uid: String,
+    tags: Vec<String>,
+    category: String,
+    /// `None` without a TODO keyword, otherwise whether it is done.
+    done: Option<bool>,
+}
+
+/// An iCalendar file with the events and to-dos of `files`, given as file
+/// names and the documents read from them. `now`, in seconds since
+/// 1970-01-01 UTC, is the `DTSTAMP` of every entry. Lines end in CRLF and
+/// are folded at 75 octets, as RFC 5545 asks.
+pub fn export(files: &[(&str, &Document)], config: &IcalConfig, now: i64) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//org-parser//NONSGML Org//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("X-WR-CALNAME:{}", escape(&config.name)),
+    ];
+
+    if let Some(timezone) = &config.timezone {
+        lines.push(format!("X-WR-TIMEZONE:{}", escape(timezone)));
+    }
+
+    let stamp = format!(
+        "DTSTAMP:{}T{}Z",
+        date_value(&Date::from_days(now.div_euclid(86_400))),
+        time_value(now.rem_euclid(86_400) / 60, now.rem_euclid(60))
+    );
+
+    for (file, document) in files {
+        for entry in entries(file, document, config) {
+            components(&entry, document, config, &stamp, &mut lines);
+        }
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+/// The headings of `document` to export, in order.
+fn entries<'a>(file: &str, document: &'a Document, config: &IcalConfig) -> Vec<Entry<'a>> {
+    let settings = document.settings();
+    let filetags = document.filetags();
+    let category = document
+        .keywords()
+        .into_iter()
+        .find(|keyword| keyword.key == "CATEGORY")
+        .map(|keyword| keyword.value)
+        .unwrap_or_else(|| {
+            let name = file.rsplit('/').next().unwrap_or(file);
+            name.split('.').next().unwrap_or(name).to_string()
+        });
+
+    let mut entries = vec![];
+    let mut skipped: Vec<Vec<usize>> = vec![];
+
+    for (path, heading) in document.all_headings() {
+        if skipped.iter().any(|skip| path.starts_with(skip)) {
+            continue;
+        }
+
+        let mut tags = filetags.clone();
+        let mut category = category.clone();
+
+        for i in 1..=path.len() {
+            let ancestor = document.heading(&path[..i]).unwrap();
+
+            for tag in &ancestor.tags {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+
+            if let Some(value) = ancestor.property("CATEGORY") {
+                category = value;
+            }
+        }
+
+        let excluded = tags
+            .iter()
+            .any(|tag| config.exclude_tags.contains(tag) || settings.exclude_tags.contains(tag));
+
+        let comment = heading.title == "COMMENT" || heading.title.starts_with("COMMENT ");
+
+        if excluded || comment {
+            skipped.push(path);
+            continue;
+        }
+
+        let done = heading.keyword.as_ref().and_then(|keyword| {
+            if document.done_keywords.contains(keyword) {
+                Some(true)
+            } else if document.todo_keywords.contains(keyword) {
+                Some(false)
+            } else {
+                None
+            }
+        });
+
+        // Without an ID, the UID comes from the file name and the titles
+        // leading to the heading, so it survives edits elsewhere.
+        let uid = heading.property("ID").unwrap_or_else(|| {
+            let mut key = file.to_string();
+
+            for i in 1..=path.len() {
+                key.push('\n');
+                key.push_str(&document.heading(&path[..i]).unwrap().title);
+            }
+
+            format!("{:016x}", hash(&key))
+        });
+
+        entries.push(Entry {
+            heading,
+            uid,
+            tags,
+            category,
+            done,
+        });
+    }
+
+    entries
+}
+
+/// The `VEVENT`s and `VTODO` for one heading.
+fn components(
+    entry: &Entry,
+    document: &Document,
+    config: &IcalConfig,
+    stamp: &str,
+    lines: &mut Vec<String>,
+) {
+    let heading = entry.heading;
+    let planning = heading.planning();
+    let title = summary(heading);
+    let title = title.as_str();
+
+    let used = |usage: &[Usage]| match entry.done {
+        None => usage.contains(&Usage::EventIfNotTodo),
+        Some(done) => {
+            usage.contains(&Usage::EventIfTodo)
+                || (!done && usage.contains(&Usage::EventIfTodoNotDone))
+        }
+    };
+
+    let event = |prefix: String, summary: String, start: &Timestamp, end: Option<&Timestamp>| {
+        let mut event = vec![
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}-{}", prefix, entry.uid),
+            stamp.to_string(),
+        ];
+
+        event.push(date_time("DTSTART", start, false));
+        event.extend(event_end(start, end));
+
+        if let Some(repeater) = &start.repeater {
+            event.push(rrule(repeater));
+        }
+
+        event.extend(common(entry, &summary, config));
+
+        let trigger = match &start.warning {
+            Some(warning) => Some(format!("-P{}", duration(warning))),
+            None if config.alarm_time > 0 && start.time.is_some() => {
+                Some(format!("-PT{}M", config.alarm_time))
+            }
+            None => None,
+        };
+
+        if let Some(trigger) = trigger {
+            event.extend(vec![
+                "BEGIN:VALARM".to_string(),
+                "ACTION:DISPLAY".to_string(),
+                format!("DESCRIPTION:{}", escape(&summary)),
+                format!("TRIGGER:{}", trigger),
+                "END:VALARM".to_string(),
+            ]);
+        }
+
+        event.push("END:VEVENT".to_string());
+        event
+    };
+
+    if let Some(deadline) = planning.deadline.as_ref().filter(|t| t.active) {
+        if used(&config.use_deadline) {
+            lines.extend(event(
+                "DL".to_string(),
+                format!("DL: {}", title),
+                deadline,
+                None,
+            ));
+        }
+    }
+
+    if let Some(scheduled) = planning.scheduled.as_ref().filter(|t| t.active) {
+        if used(&config.use_scheduled) {
+            lines.extend(event(
+                "SC".to_string(),
+                format!("S: {}", title),
+                scheduled,
+                None,
+            ));
+        }
+    }
+
+    if config.use_plain_timestamp {
+        for (i, (start, end)) in plain_timestamps(heading).iter().enumerate() {
+            let prefix = match i {
+                0 => "TS".to_string(),
+                i => format!("TS{}", i + 1),
+            };
+
+            lines.extend(event(prefix, title.to_string(), start, end.as_ref()));
+        }
+    }
+
+    let keyword = heading.keyword.clone().unwrap_or_default();
+    let included = match (&config.include_todo, entry.done) {
+        (_, None) | (IncludeTodo::None, _) => false,
+        (IncludeTodo::Open, Some(done)) => !done,
+        (IncludeTodo::All, _) => true,
+        (IncludeTodo::Keywords(keywords), _) => keywords.contains(&keyword),
+    };
+
+    if !included {
+        return;
+    }
+
+    lines.push("BEGIN:VTODO".to_string());
+    lines.push(format!("UID:TODO-{}", entry.uid));
+    lines.push(stamp.to_string());
+
+    if let Some(scheduled) = planning.scheduled.as_ref().filter(|t| t.active) {
+        if config.use_scheduled.contains(&Usage::Todo) {
+            lines.push(date_time("DTSTART", scheduled, false));
+
+            if let Some(repeater) = &scheduled.repeater {
+                lines.push(rrule(repeater));
+            }
+        }
+    }
+
+    if let Some(deadline) = &planning.deadline {
+        if config.use_deadline.contains(&Usage::Todo) {
+            lines.push(date_time("DUE", deadline, false));
+        }
+    }
+
+    lines.extend(common(entry, title, config));
+
+    let status = if entry.done == Some(true) {
+        "COMPLETED"
+    } else if document.todo_keywords.first() == Some(&keyword) {
+        "NEEDS-ACTION"
+    } else {
+        "IN-PROCESS"
+    };
+
+    lines.push(format!("STATUS:{}", status));
+
+    // Org's default priority is B, and A to C map onto iCalendar's 1 to 9.
+    let priority = match heading.priority.unwrap_or('B') {
+        'A' => 1,
+        'B' => 5,
+        'C' => 9,
+        _ => 0,
+    };
+
+    lines.push(format!("PRIORITY:{}", priority));
+    lines.push("END:VTODO".to_string());
+}
+
+/// `SUMMARY`, `DESCRIPTION` and `CATEGORIES`.
+fn common(entry: &Entry, summary: &str, config: &IcalConfig) -> Vec<String> {
+    let mut lines = vec![format!("SUMMARY:{}", escape(summary))];
+
+    if let Some(limit) = config.include_body {
+        let body = body(entry.heading);
+
+        if !body.is_empty() && limit > 0 {
+            let body: String = body.chars().take(limit).collect();
+            lines.push(format!("DESCRIPTION:{}", escape(body.trim_end())));
+        }
+    }
+
+    let mut categories: Vec<String> = vec![];
+
+    for category in &config.categories {
+        let values = match category {
+            Category::LocalTags => entry.heading.tags.clone(),
+            Category::AllTags => entry.tags.clone(),
+            Category::TodoState => entry.heading.keyword.iter().cloned().collect(),
+            Category::Category => vec![entry.category.clone()],
+        };
+
+        for value in values {
+            if !value.is_empty() && !categories.contains(&value) {
+                categories.push(value);
+            }
+        }
+    }
+
+    if !categories.is_empty() {
+        let escaped: Vec<String> = categories.iter().map(|c| escape(c)).collect();
+        lines.push(format!("CATEGORIES:{}", escaped.join(",")));
+    }
+
+    lines
+}
+
+/// The title of `heading` without the timestamps that the entry's dates come
+/// from, with entities and special strings in UTF-8.
+fn summary(heading: &Heading) -> String {
+    let stripped = timestamp_range().replace_all(&heading.title, "");
+    entities::utf8(&stripped.split_whitespace().collect::<Vec<&str>>().join(" "))
+}
+
+/// The paragraphs and lists of a heading's section, less lines holding
+/// nothing but the timestamps the entry's dates come from, with entities
+/// and special strings in UTF-8.
+fn body(heading: &Heading) -> String {
+    let mut lines = vec![];
+
+    for element in heading.elements() {
+        if let Kind::Paragraph | Kind::List(_) = element.kind {
+            lines.extend(
+                element
+                    .body()
+                    .iter()
+                    .filter(|line| !timestamp_range().replace_all(line, "").trim().is_empty())
+                    .map(|line| entities::utf8(line.trim())),
+            );
+        }
+    }
+
+    lines.join("\n").trim().to_string()
+}
+
+/// Active timestamps and ranges in the title and section of `heading`,
+/// leaving out its planning line.
+fn plain_timestamps(heading: &Heading) -> Vec<(Timestamp, Option<Timestamp>)> {
+    let range = timestamp_range();
+    let mut texts = vec![heading.title.as_str()];
+
+    for (i, line) in heading.section.iter().enumerate() {
+        if !(i == 0 && is_planning(line.trim_start())) {
+            texts.push(line);
+        }
+    }
+
+    let mut timestamps = vec![];
+
+    for text in texts {
+        for captures in range.captures_iter(text) {
+            if let Some(start) = Timestamp::parse(&captures[1]) {
+                let end = captures
+                    .get(2)
+                    .and_then(|end| Timestamp::parse(end.as_str()));
+                timestamps.push((start, end));
+            }
+        }
+    }
+
+    timestamps
+}
+
+/// An active timestamp, or a range of two.
+fn timestamp_range() -> &'static regex::Regex {
+    regex!(r"(<\d{4}-\d{2}-\d{2}[^<>\n]*>)(?:--(<\d{4}-\d{2}-\d{2}[^<>\n]*>))?")
+}
+
+/// `DTEND` for an event from `start` to `end`, or lasting for `start`'s
+/// own time range or day.
+fn event_end(start: &Timestamp, end: Option<&Timestamp>) -> Option<String> {
+    match end {
+        Some(end) => Some(date_time("DTEND", end, true)),
+        None if start.time.is_none() => Some(date_time("DTEND", start, true)),
+        None => start.end_time.map(|end_time| {
+            let end = Timestamp {
+                time: Some(end_time),
+                end_time: None,
+                ..start.clone()
+            };
+
+            date_time("DTEND", &end, false)
+        }),
+    }
+}
+
+/// A `DTSTART`, `DTEND` or `DUE` line. A whole day is a `DATE`; as an end,
+/// which iCalendar does not include, it is given as the day after. A time
+/// range ends at its end time when `end` is set.
+fn date_time(name: &str, timestamp: &Timestamp, end: bool) -> String {
+    match timestamp.time {
+        None => {
+            let date = if end {
+                timestamp.date.add_days(1)
+            } else {
+                timestamp.date
+            };
+
+            format!("{};VALUE=DATE:{}", name, date_value(&date))
+        }
+        Some(time) => {
+            let time = if end {
+                timestamp.end_time.unwrap_or(time)
+            } else {
+                time
+            };
+
+            format!(
+                "{}:{}T{}",
+                name,
+                date_value(&timestamp.date),
+                time_value(time.minutes() as i64, 0)
+            )
+        }
+    }
+}
+
+fn date_value(date: &Date) -> String {
+    format!("{:04}{:02}{:02}", date.year, date.month, date.day)
+}
+
+fn time_value(minutes: i64, seconds: i64) -> String {
+    format!("{:02}{:02}{:02}", minutes / 60, minutes % 60, seconds)
+}
+
+fn rrule(repeater: &Interval) -> String {
+    let frequency = match repeater.unit {
+        Unit::Hour => "HOURLY",
+        Unit::Day => "DAILY",
+        Unit::Week => "WEEKLY",
+        Unit::Month => "MONTHLY",
+        Unit::Year => "YEARLY",
+    };
+
+    format!("RRULE:FREQ={};INTERVAL={}", frequency, repeater.value)
+}
+
+/// A warning delay as an iCalendar duration, without the sign and `P`.
+/// Months and years have no duration of their own, so are taken as 30 and
+/// 365 days.
+fn duration(interval: &Interval) -> String {
+    match interval.unit {
+        Unit::Hour => format!("T{}H", interval.value),
+        Unit::Day => format!("{}D", interval.value),
+        Unit::Week => format!("{}W", interval.value),
+        Unit::Month => format!("{}D", interval.value * 30),
+        Unit::Year => format!("{}D", interval.value * 365),
+    }
+}
+
+/// Escapes a `TEXT` value.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// `line` with CRLF, split into lines of at most 75 octets that each go on
+/// from the last after a space.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+
+        folded.push(c);
+        width += c.len_utf8();
+    }
+
+    folded.push_str("\r\n");
+    folded
+}
+
+/// FNV-1a, which unlike the standard library's hasher gives the same value
+/// in every build, keeping UIDs stable.
+fn hash(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for byte in text.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportError {
+    /// A `BEGIN` without its `END`, or the other way round.
+    Unbalanced {
+        line: usize,
+        component: String,
+    },
+    /// A content line without a `:` between name and value.
+    Malformed(usize),
+    /// A date or date-time that can't be read.
+    InvalidDate {
+        line: usize,
+        value: String,
+    },
+    Edit(EditError),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Unbalanced { line, component } => {
+                write!(f, "line {}: unbalanced BEGIN/END:{}", line, component)
+            }
+            ImportError::Malformed(line) => write!(f, "line {}: malformed content line", line),
+            ImportError::InvalidDate { line, value } => {
+                write!(f, "line {}: invalid date {}", line, value)
+            }
+            ImportError::Edit(error) => error.fmt(f),
+        }
+    }
+}
+
+impl From<EditError> for ImportError {
+    fn from(error: EditError) -> Self {
+        ImportError::Edit(error)
+    }
+}
+
+/// A content line: `NAME;PARAM=VALUE:value`, unfolded.
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+    line: usize,
+}
+
+impl Property {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A `VEVENT` or `VTODO` with the triggers of its alarms.
+struct Component {
+    kind: String,
+    properties: Vec<Property>,
+    triggers: Vec<Property>,
+}
+
+impl Component {
+    fn get(&self, name: &str) -> Option<&Property> {
+        self.properties
+            .iter()
+            .find(|property| property.name == name)
+    }
+
+    fn text(&self, name: &str) -> Option<String> {
+        self.get(name).map(|property| unescape(&property.value))
+    }
+}
+
+/// A date or date-time value and whether it has a time.
+type DateTime = (Date, Option<Time>);
+
+/// Headings at `level` for the events and to-dos of an iCalendar file, in
+/// the order they appear. An event's dates go in an active timestamp under
+/// its properties, as a range if it spans days; a to-do's become its
+/// `SCHEDULED` and `DEADLINE`. `UID` and `LOCATION` become the `:ID:` and
+/// `:LOCATION:` properties, `CATEGORIES` tags and `DESCRIPTION` the text of
+/// the section.
+///
+/// Times in UTC, ending in `Z`, are moved to the zone `utc_offset` minutes
+/// east of it. Others are taken as written, in their `TZID` zone or none;
+/// the zone the times were given in is kept in a `:TIMEZONE:` property. A
+/// simple `RRULE` becomes a repeater; one Org can't express, with a count,
+/// an end or a `BY` part, is kept in an `:RRULE:` property instead. An
+/// alarm some days, weeks or hours ahead becomes a warning delay.
+pub fn import(source: &str, level: usize, utc_offset: i64) -> Result<Vec<Heading>, ImportError> {
+    let mut headings = vec![];
+
+    for component in components_of(source)? {
+        let mut document = document::parse("");
+        document.insert_subtree(&[], 0, entry_heading(&component, utc_offset)?)?;
+
+        if component.kind == "VTODO" {
+            let start = timestamp_of(&component, "DTSTART", utc_offset)?;
+            let due = timestamp_of(&component, "DUE", utc_offset)?;
+            let repeater = rrule_of(&component).ok().flatten();
+
+            match (start, due) {
+                (Some(mut start), due) => {
+                    start.repeater = repeater;
+                    start.warning = warning_of(&component);
+                    document.set_scheduled(&[0], Some(start))?;
+                    document.set_deadline(&[0], due)?;
+                }
+                (None, Some(mut due)) => {
+                    due.repeater = repeater;
+                    due.warning = warning_of(&component);
+                    document.set_deadline(&[0], Some(due))?;
+                }
+                (None, None) => (),
+            }
+        }
+
+        if let Some(uid) = component.text("UID") {
+            document.set_property(&[0], "ID", &one_line(&uid))?;
+        }
+
+        if let Some(location) = component.text("LOCATION").filter(|l| !l.trim().is_empty()) {
+            document.set_property(&[0], "LOCATION", &one_line(&location))?;
+        }
+
+        if let Err(rule) = rrule_of(&component) {
+            document.set_property(&[0], "RRULE", &rule)?;
+        }
+
+        if let Some(zone) = zone_of(&component) {
+            document.set_property(&[0], "TIMEZONE", &zone)?;
+        }
+
+        let mut heading = document.headings.remove(0);
+        heading.level = level.max(1);
+        headings.push(heading);
+    }
+
+    Ok(headings)
+}
+
+/// The heading line and section text of `component`, before planning and
+/// properties are added.
+fn entry_heading(component: &Component, utc_offset: i64) -> Result<Heading, ImportError> {
+    let mut heading = Heading::new(&one_line(&component.text("SUMMARY").unwrap_or_default()));
+
+    if let Some(categories) = component.get("CATEGORIES") {
+        for category in split_unescaped(&categories.value) {
+            let tag: String = one_line(&category)
+                .chars()
+                .map(|c| {
+                    if c.is_alphanumeric() || "_@#%".contains(c) {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+
+            if !tag.is_empty() && !heading.tags.contains(&tag) {
+                heading.tags.push(tag);
+            }
+        }
+    }
+
+    if component.kind == "VTODO" {
+        let status = component.text("STATUS").unwrap_or_default();
+        let done = status.eq_ignore_ascii_case("COMPLETED") || component.get("COMPLETED").is_some();
+        heading.keyword = Some(if done { "DONE" } else { "TODO" }.to_string());
+
+        // The reverse of the mapping in `export`.
+        heading.priority = match component
+            .text("PRIORITY")
+            .and_then(|p| p.trim().parse::<u32>().ok())
+        {
+            Some(1..=4) => Some('A'),
+            Some(5) => Some('B'),
+            Some(6..=9) => Some('C'),
+            Some(_) | None => None,
+        };
+    } else if let Some(timestamp) = event_timestamp(component, utc_offset)? {
+        heading.section.push(timestamp);
+    }
+
+    if let Some(description) = component.text("DESCRIPTION") {
+        for line in description.trim_end().lines() {
+            let line = line.trim_end();
+
+            // A line that would read as a heading is indented.
+            if heading_level(line).is_some() {
+                heading.section.push(format!(" {}", line));
+            } else {
+                heading.section.push(line.to_string());
+            }
+        }
+    }
+
+    Ok(heading)
+}
+
+/// The active timestamp, or range, of an event.
+fn event_timestamp(component: &Component, utc_offset: i64) -> Result<Option<String>, ImportError> {
+    let mut start = match timestamp_of(component, "DTSTART", utc_offset)? {
+        Some(start) => start,
+        None => return Ok(None),
+    };
+
+    start.repeater = rrule_of(component).ok().flatten();
+    start.warning = warning_of(component);
+
+    let end = match component.get("DTEND") {
+        Some(property) => Some(read_date_time(property, utc_offset)?),
+        None => component
+            .text("DURATION")
+            .and_then(|duration| minutes_of(&duration))
+            .map(|minutes| add_minutes((start.date, start.time), minutes)),
+    };
+
+    let end = match end {
+        // A whole-day end is the day after the last.
+        Some((date, None)) => Some((date.add_days(-1), None)),
+        end => end,
+    };
+
+    match end {
+        Some((date, time)) if date == start.date && time.is_some() && start.time.is_some() => {
+            if time != start.time {
+                start.end_time = time;
+            }
+
+            Ok(Some(start.to_org()))
+        }
+        Some((date, time)) if date > start.date => {
+            let end = Timestamp {
+                active: true,
+                date,
+                time: time.filter(|_| start.time.is_some()),
+                end_time: None,
+                repeater: None,
+                warning: None,
+            };
+
+            Ok(Some(format!("{}--{}", start.to_org(), end.to_org())))
+        }
+        _ => Ok(Some(start.to_org())),
+    }
+}
+
+fn timestamp_of(
+    component: &Component,
+    name: &str,
+    utc_offset: i64,
+) -> Result<Option<Timestamp>, ImportError> {
+    let property = match component.get(name) {
+        Some(property) => property,
+        None => return Ok(None),
+    };
+
+    let (date, time) = read_date_time(property, utc_offset)?;
+
+    Ok(Some(Timestamp {
+        active: true,
+        date,
+        time,
+        end_time: None,
+        repeater: None,
+        warning: None,
+    }))
+}
+
+/// Reads `20190925` or `20190925T100000`, or `20190925T100000Z` in UTC,
+/// which is moved `utc_offset` minutes on.
+fn read_date_time(property: &Property, utc_offset: i64) -> Result<DateTime, ImportError> {
+    let utc = property.value.trim().ends_with('Z');
+    let value = property.value.trim().trim_end_matches('Z');
+    let invalid = || ImportError::InvalidDate {
+        line: property.line,
+        value: property.value.clone(),
+    };
+
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+
+    let number = |text: &str, range: Range<usize>| -> Option<u32> {
+        text.get(range).and_then(|digits| digits.parse().ok())
+    };
+
+    if date.len() != 8 {
+        return Err(invalid());
+    }
+
+    let date = Date::new(
+        number(date, 0..4).ok_or_else(invalid)? as i32,
+        number(date, 4..6).ok_or_else(invalid)?,
+        number(date, 6..8).ok_or_else(invalid)?,
+    )
+    .ok_or_else(invalid)?;
+
+    let time = match time {
+        Some(time) if time.len() >= 4 => {
+            let hour = number(time, 0..2).ok_or_else(invalid)?;
+            let minute = number(time, 2..4).ok_or_else(invalid)?;
+
+            if hour > 23 || minute > 59 {
+                return Err(invalid());
+            }
+
+            Some(Time { hour, minute })
+        }
+        Some(_) => return Err(invalid()),
+        None => None,
+    };
+
+    if utc {
+        return Ok(add_minutes((date, time), utc_offset));
+    }
+
+    Ok((date, time))
+}
+
+/// The zone the times of `component` are given in: the `TZID` of the
+/// first, or `UTC` if it ends in `Z`.
+fn zone_of(component: &Component) -> Option<String> {
+    let property = ["DTSTART", "DUE", "DTEND"]
+        .iter()
+        .find_map(|name| component.get(name))?;
+
+    match property.param("TZID") {
+        Some(zone) => Some(zone.trim_matches('"').to_string()),
+        None if property.value.trim().ends_with('Z') => Some("UTC".to_string()),
+        None => None,
+    }
+}
+
+fn add_minutes((date, time): DateTime, minutes: i64) -> DateTime {
+    match time {
+        None => (date.add_days(minutes / (24 * 60)), None),
+        Some(time) => {
+            let total = time.minutes() as i64 + minutes;
+            let time = Time {
+                hour: (total.rem_euclid(24 * 60) / 60) as u32,
+                minute: (total.rem_euclid(60)) as u32,
+            };
+
+            (date.add_days(total.div_euclid(24 * 60)), Some(time))
+        }
+    }
+}
+
+/// Minutes in a duration such as `P1DT2H30M` or `-PT15M`.
+fn minutes_of(duration: &str) -> Option<i64> {
+    let pattern =
+        regex!(r"^([+-])?P(?:(\d+)W)?(?:(\d+)D)?(?:T(?:(\d+)H)?(?:(\d+)M)?(?:(\d+)S)?)?$");
+    let captures = pattern.captures(duration.trim())?;
+
+    let part = |i: usize| -> i64 {
+        captures
+            .get(i)
+            .and_then(|m| m.as_str().parse().ok())
+            .unwrap_or(0)
+    };
+
+    let minutes = part(2) * 7 * 24 * 60 + part(3) * 24 * 60 + part(4) * 60 + part(5);
+
+    match captures.get(1).map(|m| m.as_str()) {
+        Some("-") => Some(-minutes),
+        _ => Some(minutes),
+    }
+}
+
+/// The repeater for the component's `RRULE`, or the rule itself if Org
+/// can't express it.
+fn rrule_of(component: &Component) -> Result<Option<Interval>, String> {
+    let rule = match component.get("RRULE") {
+        Some(rule) => rule.value.trim(),
+        None => return Ok(None),
+    };
+
+    let mut unit = None;
+    let mut value = 1;
+
+    for part in rule.split(';') {
+        let (key, part_value) = part.split_once('=').ok_or_else(|| rule.to_string())?;
+
+        match key.to_uppercase().as_str() {
+            "FREQ" => {
+                unit = match part_value.to_uppercase().as_str() {
+                    "HOURLY" => Some(Unit::Hour),
+                    "DAILY" => Some(Unit::Day),
+                    "WEEKLY" => Some(Unit::Week),
+                    "MONTHLY" => Some(Unit::Month),
+                    "YEARLY" => Some(Unit::Year),
+                    _ => return Err(rule.to_string()),
+                }
+            }
+            "INTERVAL" => value = part_value.parse().map_err(|_| rule.to_string())?,
+            "WKST" => (),
+            _ => return Err(rule.to_string()),
+        }
+    }
+
+    match unit {
+        Some(unit) if value > 0 => Ok(Some(Interval {
+            mark: "+".to_string(),
+            value,
+            unit,
+        })),
+        _ => Err(rule.to_string()),
+    }
+}
+
+/// A warning delay for the first alarm set off a whole number of hours,
+/// days or weeks before the start.
+fn warning_of(component: &Component) -> Option<Interval> {
+    component.triggers.iter().find_map(|trigger| {
+        if trigger
+            .param("RELATED")
+            .is_some_and(|r| r.eq_ignore_ascii_case("END"))
+            || trigger
+                .param("VALUE")
+                .is_some_and(|v| v.eq_ignore_ascii_case("DATE-TIME"))
+        {
+            return None;
+        }
+
+        let minutes = -minutes_of(&trigger.value)?;
+
+        let (value, unit) = match minutes {
+            m if m <= 0 => return None,
+            m if m % (7 * 24 * 60) == 0 => (m / (7 * 24 * 60), Unit::Week),
+            m if m % (24 * 60) == 0 => (m / (24 * 60), Unit::Day),
+            m if m % 60 == 0 => (m / 60, Unit::Hour),
+            _ => return None,
+        };
+
+        Some(Interval {
+            mark: "-".to_string(),
+            value: value as u32,
+            unit,
+        })
+    })
+}
+
+/// The events and to-dos of `source`, with the triggers of their alarms.
+fn components_of(source: &str) -> Result<Vec<Component>, ImportError> {
+    let mut components = vec![];
+    let mut open: Vec<(String, usize)> = vec![];
+    let mut current: Option<Component> = None;
+
+    for property in content_lines(source)? {
+        let name = property.name.as_str();
+        let value = property.value.trim().to_uppercase();
+
+        if name == "BEGIN" {
+            if (value == "VEVENT" || value == "VTODO") && current.is_none() {
+                current = Some(Component {
+                    kind: value.clone(),
+                    properties: vec![],
+                    triggers: vec![],
+                });
+            }
+
+            open.push((value, property.line));
+            continue;
+        }
+
+        if name == "END" {
+            match open.pop() {
+                Some((component, _)) if component == value => (),
+                _ => {
+                    return Err(ImportError::Unbalanced {
+                        line: property.line,
+                        component: value,
+                    })
+                }
+            }
+
+            if (value == "VEVENT" || value == "VTODO")
+                && current.as_ref().is_some_and(|c| c.kind == value)
+            {
+                components.extend(current.take());
+            }
+
+            continue;
+        }
+
+        let in_alarm = open.last().is_some_and(|(kind, _)| kind == "VALARM");
+
+        if let Some(component) = current.as_mut() {
+            if in_alarm && name == "TRIGGER" {
+                component.triggers.push(property);
+            } else if open.last().is_some_and(|(kind, _)| *kind == component.kind) {
+                component.properties.push(property);
+            }
+        }
+    }
+
+    match open.pop() {
+        Some((component, line)) => Err(ImportError::Unbalanced { line, component }),
+        None => Ok(components),
+    }
+}
+
+/// The unfolded content lines of `source`, with names in upper case.
+fn content_lines(source: &str) -> Result<Vec<Property>, ImportError> {
+    let mut lines: Vec<(usize, String)> = vec![];
+
+    for (i, line) in source.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        match line.strip_prefix([' ', '\t']) {
+            Some(rest) if !lines.is_empty() => lines.last_mut().unwrap().1.push_str(rest),
+            _ if line.trim().is_empty() => (),
+            _ => lines.push((i + 1, line.to_string())),
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|(line, text)| parse_content_line(&text, line).ok_or(ImportError::Malformed(line)))
+        .collect()
+}
+
+fn parse_content_line(text: &str, line: usize) -> Option<Property> {
+    // The value starts at the first colon outside a quoted parameter.
+    let mut quoted = false;
+    let colon = text.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+
+    let mut parts = split_params(&text[..colon]).into_iter();
+    let name = parts.next()?.trim().to_uppercase();
+
+    if name.is_empty() {
+        return None;
+    }
+
+    let params = parts
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            Some((
+                key.trim().to_uppercase(),
+                value.trim_matches('"').to_string(),
+            ))
+        })
+        .collect();
+
+    Some(Property {
+        name,
+        params,
+        value: text[colon + 1..].to_string(),
+        line,
+    })
+}
+
+/// Splits `NAME;A=1;B="x;y"` at semicolons outside quotes.
+fn split_params(text: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parts.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Reverses `escape`.
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') | Some('N') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+/// The values of a list such as `CATEGORIES`, split at unescaped commas.
+fn split_unescaped(text: &str) -> Vec<String> {
+    let mut values = vec![];
+    let mut value = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                value.push(c);
+                value.extend(chars.next());
+            }
+            ',' => values.push(unescape(&std::mem::take(&mut value))),
+            _ => value.push(c),
+        }
+    }
+
+    values.push(unescape(&value));
+    values
+}
+
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
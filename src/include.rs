@@ -0,0 +1,339 @@
+use crate::document;
+use crate::document::heading_level;
+use crate::document::BlockLine;
+use crate::document::Blocks;
+use crate::resolve::join;
+use crate::resolve::normalise_path;
+use std::fmt;
+
+/// Reads the files named by `#+INCLUDE:` and `#+SETUPFILE:`. Paths are
+/// already resolved relative to the including file.
+pub trait FileLoader {
+    fn load(&self, path: &str) -> Result<String, String>;
+}
+
+/// Loads files from the local filesystem.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FsLoader;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileLoader for FsLoader {
+    fn load(&self, path: &str) -> Result<String, String> {
+        std::fs::read_to_string(path).map_err(|error| error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IncludeError {
+    Load {
+        file: String,
+        message: String,
+    },
+    /// The chain of files leading back to one already being included.
+    Cycle(Vec<String>),
+    MissingTarget {
+        file: String,
+        target: String,
+    },
+    Syntax {
+        file: String,
+        line: usize,
+    },
+    /// A `:lines` range ending at line 0.
+    Lines {
+        file: String,
+    },
+}
+
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IncludeError::Load { file, message } => write!(f, "cannot read {}: {}", file, message),
+            IncludeError::Cycle(chain) => write!(f, "include cycle: {}", chain.join(" -> ")),
+            IncludeError::MissingTarget { file, target } => {
+                write!(f, "no {} in {}", target, file)
+            }
+            IncludeError::Syntax { file, line } => {
+                write!(f, "{}:{}: malformed #+INCLUDE or #+SETUPFILE", file, line)
+            }
+            IncludeError::Lines { file } => write!(f, "invalid :lines range for {}", file),
+        }
+    }
+}
+
+/// An `#+INCLUDE: "file::target" kind language :minlevel 2 :lines "5-10"`
+/// directive.
+#[derive(Debug, PartialEq)]
+struct Include {
+    file: String,
+    target: Option<String>,
+    kind: Option<String>,
+    language: Option<String>,
+    minlevel: Option<usize>,
+    lines: Option<(Option<usize>, Option<usize>)>,
+}
+
+/// Expands `#+INCLUDE:` and `#+SETUPFILE:` in `source`, the contents of
+/// `file`, recursively. Setup files only contribute their `#+` keyword
+/// lines.
+pub fn expand(source: &str, file: &str, loader: &dyn FileLoader) -> Result<String, IncludeError> {
+    Expander {
+        loader,
+        stack: vec![normalise_path(file)],
+    }
+    .expand(source, file)
+}
+
+struct Expander<'a> {
+    loader: &'a dyn FileLoader,
+    stack: Vec<String>,
+}
+
+impl<'a> Expander<'a> {
+    fn expand(&mut self, source: &str, file: &str) -> Result<String, IncludeError> {
+        let directive = regex!(r"(?i)^\s*#\+(INCLUDE|SETUPFILE):\s*(.*)$");
+        let mut out = String::new();
+        let mut level = 0;
+        let mut blocks = Blocks::new(source.lines());
+
+        for (i, line) in source.lines().enumerate() {
+            let in_block = blocks.read(line) != BlockLine::Outside;
+
+            if !in_block {
+                level = heading_level(line).unwrap_or(level);
+            }
+
+            let captures = match directive.captures(line) {
+                Some(captures) if !in_block => captures,
+                _ => {
+                    out.push_str(line);
+                    out.push('\n');
+                    continue;
+                }
+            };
+
+            let syntax = || IncludeError::Syntax {
+                file: file.to_string(),
+                line: i + 1,
+            };
+
+            if captures[1].eq_ignore_ascii_case("SETUPFILE") {
+                let path = captures[2].trim().trim_matches('"');
+
+                if path.is_empty() {
+                    return Err(syntax());
+                }
+
+                let (path, contents) = self.read(file, path)?;
+
+                for line in self.expand_file(&contents, &path)?.lines() {
+                    if line.trim_start().starts_with("#+") {
+                        out.push_str(line);
+                        out.push('\n');
+                    }
+                }
+            } else {
+                let include = parse(&captures[2]).ok_or_else(syntax)?;
+                out.push_str(&self.include(file, &include, level)?);
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Reads `path` relative to `from`, refusing files that are already
+    /// being included.
+    fn read(&self, from: &str, path: &str) -> Result<(String, String), IncludeError> {
+        let path = join(from, path);
+
+        if self.stack.contains(&path) {
+            let mut chain = self.stack.clone();
+            chain.push(path);
+            return Err(IncludeError::Cycle(chain));
+        }
+
+        match self.loader.load(&path) {
+            Ok(contents) => Ok((path, contents)),
+            Err(message) => Err(IncludeError::Load {
+                file: path,
+                message,
+            }),
+        }
+    }
+
+    /// Expands `contents`, read from `path`, with `path` on the stack.
+    fn expand_file(&mut self, contents: &str, path: &str) -> Result<String, IncludeError> {
+        self.stack.push(path.to_string());
+        let result = self.expand(contents, path);
+        self.stack.pop();
+        result
+    }
+
+    fn include(
+        &mut self,
+        from: &str,
+        include: &Include,
+        level: usize,
+    ) -> Result<String, IncludeError> {
+        let (path, contents) = self.read(from, &include.file)?;
+
+        let mut contents = match include.lines {
+            Some((start, end)) => {
+                let lines: Vec<&str> = contents.lines().collect();
+                let start = start.unwrap_or(1).max(1) - 1;
+                let end = match end {
+                    Some(end) => end
+                        .checked_sub(1)
+                        .ok_or_else(|| IncludeError::Lines { file: path.clone() })?,
+                    None => lines.len(),
+                }
+                .min(lines.len());
+
+                lines
+                    .get(start..end.max(start))
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|line| format!("{}\n", line))
+                    .collect()
+            }
+            None => contents,
+        };
+
+        if let Some(target) = &include.target {
+            contents = select(&contents, target).ok_or_else(|| IncludeError::MissingTarget {
+                file: path.clone(),
+                target: target.clone(),
+            })?;
+        }
+
+        Ok(match include.kind.as_deref() {
+            Some(kind) => {
+                let name = kind.to_uppercase();
+                let mut block = format!("#+BEGIN_{}", name);
+
+                if let Some(language) = &include.language {
+                    block.push(' ');
+                    block.push_str(language);
+                }
+
+                block.push('\n');
+
+                for line in contents.lines() {
+                    block.push_str(&escape(line));
+                    block.push('\n');
+                }
+
+                block.push_str(&format!("#+END_{}\n", name));
+                block
+            }
+            // Org includes are expanded after narrowing, as Emacs does.
+            None => {
+                let minlevel = include.minlevel.unwrap_or(level + 1);
+                shift(&self.expand_file(&contents, &path)?, minlevel)
+            }
+        })
+    }
+}
+
+fn parse(arguments: &str) -> Option<Include> {
+    let include = regex!(
+        r#"^"([^"]+)"(?:\s+(src|example|export)(?:\s+([^:\s]\S*))?)?((?:\s+:\S+\s+("[^"]*"|\S+))*)\s*$"#
+    );
+    let option = regex!(r#":(\S+)\s+("[^"]*"|\S+)"#);
+    let captures = include.captures(arguments.trim())?;
+
+    let (file, target) = match captures[1].split_once("::") {
+        Some((file, target)) => (file.to_string(), Some(target.to_string())),
+        None => (captures[1].to_string(), None),
+    };
+
+    let mut result = Include {
+        file,
+        target,
+        kind: captures.get(2).map(|m| m.as_str().to_string()),
+        language: captures.get(3).map(|m| m.as_str().to_string()),
+        minlevel: None,
+        lines: None,
+    };
+
+    for option in option.captures_iter(&captures[4]) {
+        let value = option[2].trim_matches('"');
+
+        match &option[1] {
+            "minlevel" => result.minlevel = Some(value.parse().ok()?),
+            "lines" => {
+                let (start, end) = value.split_once('-')?;
+                result.lines = Some((start.parse().ok(), end.parse().ok()));
+            }
+            _ => (),
+        }
+    }
+
+    Some(result)
+}
+
+/// The subtree of the heading `*Title` or `#custom-id` in `contents`.
+fn select(contents: &str, target: &str) -> Option<String> {
+    let document = document::parse(contents);
+
+    let (_, heading) = document.all_headings().into_iter().find(|(_, heading)| {
+        match (target.strip_prefix('*'), target.strip_prefix('#')) {
+            (Some(title), _) => heading.normalised_title() == title.trim(),
+            (_, Some(id)) => heading.property("CUSTOM_ID").as_deref() == Some(id),
+            _ => heading.normalised_title() == target.trim(),
+        }
+    })?;
+
+    Some(heading.to_org())
+}
+
+/// Shifts the headings in `contents` so that the shallowest is at
+/// `minlevel`. Lines inside blocks aren't headings and stay as they are.
+fn shift(contents: &str, minlevel: usize) -> String {
+    let mut blocks = Blocks::new(contents.lines());
+    let levels: Vec<Option<usize>> = contents
+        .lines()
+        .map(|line| match blocks.read(line) {
+            BlockLine::Outside => heading_level(line),
+            _ => None,
+        })
+        .collect();
+
+    let shallowest = match levels.iter().flatten().min() {
+        Some(level) => *level,
+        None => return contents.to_string(),
+    };
+
+    let mut out = String::new();
+
+    for (line, level) in contents.lines().zip(levels) {
+        match level {
+            Some(level) => {
+                out.push_str(&"*".repeat((level + minlevel).saturating_sub(shallowest).max(1)));
+                out.push_str(&line[level..]);
+            }
+            None => out.push_str(line),
+        }
+
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Lines that would otherwise read as headings or keywords inside a block
+/// get a leading comma, as `org-escape-code-in-string` does.
+fn escape(line: &str) -> String {
+    let trimmed = line.trim_start();
+
+    if trimmed.starts_with('*')
+        || trimmed.starts_with("#+")
+        || trimmed.starts_with(",*")
+        || trimmed.starts_with(",#+")
+    {
+        let indent = line.len() - trimmed.len();
+        format!("{},{}", &line[..indent], trimmed)
+    } else {
+        line.to_string()
+    }
+}
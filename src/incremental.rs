@@ -0,0 +1,246 @@
+use crate::document;
+use crate::document::is_todo_setting;
+use crate::document::unclosed_blocks;
+use crate::document::BlockLine;
+use crate::document::Blocks;
+use crate::document::Document;
+use crate::document::Heading;
+use crate::document::Path;
+use crate::edit::EditError;
+
+/// Replaces the bytes `start..end` of the text with `text`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// What a text edit re-parsed. `changed` holds the paths, in the new
+/// document, of every heading that was parsed again; headings after them
+/// only had their line numbers moved. `section` is set when the lines
+/// before the first heading were parsed again, `full` when the whole
+/// document was.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Update {
+    pub changed: Vec<Path>,
+    pub section: bool,
+    pub full: bool,
+}
+
+/// The text of a file together with its parsed document, kept in step as
+/// the text is edited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buffer {
+    text: String,
+    document: Document,
+}
+
+impl Buffer {
+    pub fn new(text: &str) -> Buffer {
+        Buffer {
+            text: text.to_string(),
+            document: document::parse(text),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    /// Applies `edit` and re-parses the smallest subtree that contains it
+    /// and still reads the same way on its own, falling back to the whole
+    /// document when the edit changes the outline around it.
+    pub fn edit(&mut self, edit: &TextEdit) -> Result<Update, EditError> {
+        let valid = edit.start <= edit.end
+            && edit.end <= self.text.len()
+            && self.text.is_char_boundary(edit.start)
+            && self.text.is_char_boundary(edit.end);
+
+        if !valid {
+            return Err(EditError::InvalidRange(edit.start, edit.end));
+        }
+
+        let first = line_at(&self.text, edit.start);
+        let last = line_at(&self.text, edit.end);
+        let old_lines = self.text.lines().count();
+        let old_unclosed = unclosed_blocks(&self.text, 1);
+
+        self.text.replace_range(edit.start..edit.end, &edit.text);
+        let delta = self.text.lines().count() as isize - old_lines as isize;
+
+        // A block left open is closed by the next `#+END_` of its name
+        // anywhere after it, so with one in the file no part can be parsed
+        // on its own.
+        if old_unclosed.is_empty() && unclosed_blocks(&self.text, 1).is_empty() {
+            // Innermost first, so that the first one that works is the smallest.
+            let mut candidates = containing(&self.document, first, last, old_lines);
+            candidates.reverse();
+
+            for (path, start, end) in candidates {
+                if let Some(update) = self.reparse_heading(&path, start, end, delta) {
+                    return Ok(update);
+                }
+            }
+
+            let first_heading = self
+                .document
+                .headings
+                .first()
+                .map_or(old_lines + 2, |heading| heading.line);
+
+            if last < first_heading {
+                if let Some(update) = self.reparse_section(first_heading, delta) {
+                    return Ok(update);
+                }
+            }
+        }
+
+        self.document = document::parse(&self.text);
+
+        Ok(Update {
+            full: true,
+            ..Update::default()
+        })
+    }
+
+    /// Re-parses the subtree at `path`, which took up lines `start..end`
+    /// before the edit.
+    fn reparse_heading(
+        &mut self,
+        path: &[usize],
+        start: usize,
+        end: usize,
+        delta: isize,
+    ) -> Option<Update> {
+        let level = self.document.heading(path)?.level;
+        let old = self.document.heading(path)?.to_org();
+        let new = lines(&self.text, start, (end as isize + delta) as usize);
+
+        if !independent(&old) || !independent(&new) {
+            return None;
+        }
+
+        let mut fragment = document::parse_fragment(&new, start, &self.document);
+
+        let single = fragment.section.is_empty()
+            && fragment.headings.len() == 1
+            && fragment.headings[0].level == level;
+
+        if !single {
+            return None;
+        }
+
+        let heading = fragment.headings.pop().unwrap();
+        let mut changed = vec![];
+        descendants(&heading, path, &mut changed);
+
+        shift_lines(&mut self.document.headings, end, delta);
+        *self.document.heading_mut(path)? = heading;
+
+        Some(Update {
+            changed,
+            ..Update::default()
+        })
+    }
+
+    /// Re-parses the lines before the first heading, at `first_heading`
+    /// before the edit.
+    fn reparse_section(&mut self, first_heading: usize, delta: isize) -> Option<Update> {
+        let old = self.document.section.join("\n");
+        let new = lines(&self.text, 1, (first_heading as isize + delta) as usize);
+
+        if !independent(&old) || !independent(&new) {
+            return None;
+        }
+
+        let fragment = document::parse_fragment(&new, 1, &self.document);
+
+        if !fragment.headings.is_empty() {
+            return None;
+        }
+
+        shift_lines(&mut self.document.headings, first_heading, delta);
+        self.document.section = fragment.section;
+
+        Some(Update {
+            section: true,
+            ..Update::default()
+        })
+    }
+}
+
+/// Line number, counting from 1, of the byte at `offset`.
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+/// Lines `start..end` of `text`, counting from 1, with their line endings.
+fn lines(text: &str, start: usize, end: usize) -> String {
+    text.split_inclusive('\n')
+        .skip(start - 1)
+        .take(end.saturating_sub(start))
+        .collect()
+}
+
+/// The headings whose subtrees contain lines `first` to `last`, outermost
+/// first, with the lines `start..end` each subtree takes up. Subtrees at the
+/// end of the file also take up the line after it, so that typing at the
+/// very end stays within the last one.
+fn containing(
+    document: &Document,
+    first: usize,
+    last: usize,
+    line_count: usize,
+) -> Vec<(Path, usize, usize)> {
+    let headings = document.all_headings();
+    let mut result = vec![];
+
+    for (i, (path, heading)) in headings.iter().enumerate() {
+        let end = headings[i + 1..]
+            .iter()
+            .find(|(_, next)| next.level <= heading.level)
+            .map_or(line_count + 2, |(_, next)| next.line);
+
+        if heading.line <= first && last < end {
+            result.push((path.clone(), heading.line, end));
+        }
+    }
+
+    result
+}
+
+/// Whether `text` can be parsed apart from the rest of the file: it neither
+/// sets up TODO keywords nor leaves a block open that would swallow the
+/// headings after it.
+fn independent(text: &str) -> bool {
+    let mut blocks = Blocks::new(text.lines());
+
+    text.lines()
+        .all(|line| !is_todo_setting(line) && blocks.read(line) != BlockLine::Unclosed)
+}
+
+fn descendants(heading: &Heading, path: &[usize], out: &mut Vec<Path>) {
+    out.push(path.to_vec());
+
+    for (i, child) in heading.children.iter().enumerate() {
+        let mut path = path.to_vec();
+        path.push(i);
+        descendants(child, &path, out);
+    }
+}
+
+/// Moves every heading from line `from` on by `delta` lines.
+fn shift_lines(headings: &mut [Heading], from: usize, delta: isize) {
+    for heading in headings.iter_mut() {
+        if heading.line >= from {
+            heading.line = (heading.line as isize + delta) as usize;
+        }
+
+        shift_lines(&mut heading.children, from, delta);
+    }
+}
@@ -0,0 +1,1005 @@
+use crate::document::Document;
+use crate::document::Heading;
+use crate::document::Path;
+use crate::element;
+use crate::element::Element;
+use crate::element::Item;
+use crate::element::Kind;
+use crate::entities;
+use crate::entities::Entity;
+use crate::format::is_number;
+use crate::link::Link;
+use crate::link::LinkKind;
+use crate::resolve::Resolver;
+use crate::scanner;
+use crate::scanner::TokenType;
+use crate::timestamp::Timestamp;
+use std::collections::HashMap;
+
+/// How source blocks are typeset, like `org-latex-src-block-backend`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SrcBackend {
+    /// Plain `verbatim` environments.
+    Verbatim,
+    /// `lstlisting` environments from the `listings` package.
+    Listings,
+    /// `minted` environments, which need `-shell-escape`.
+    Minted,
+}
+
+/// Options for `export`. `#+LATEX_CLASS:`, `#+LATEX_CLASS_OPTIONS:` and the
+/// `H:`, `num:`, `toc:`, `title:`, `todo:`, `tags:`, `e:` and `-:` items of
+/// `#+OPTIONS:` override the matching fields.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LatexConfig {
+    pub class: String,
+    /// Options of `\documentclass`, brackets included, e.g. `[a4paper]`.
+    pub class_options: String,
+    pub src_backend: SrcBackend,
+    /// Headings deeper than this become list items.
+    pub headline_levels: usize,
+    pub section_numbers: bool,
+    pub toc: bool,
+    pub title: bool,
+    pub todo_keywords: bool,
+    pub tags: bool,
+    /// Whether entities such as `\alpha` are typeset from the entity table
+    /// rather than as written.
+    pub entities: bool,
+    /// Whether `--`, `---`, `...` and `\-` are typeset as dashes, an
+    /// ellipsis and a soft hyphen.
+    pub special_strings: bool,
+    /// Only what goes between `\begin{document}` and `\end{document}`.
+    pub body_only: bool,
+    /// Headings with these tags are left out, with their subtrees, along
+    /// with those in `#+EXCLUDE_TAGS:`.
+    pub exclude_tags: Vec<String>,
+}
+
+impl Default for LatexConfig {
+    fn default() -> Self {
+        LatexConfig {
+            class: "article".to_string(),
+            class_options: String::new(),
+            src_backend: SrcBackend::Verbatim,
+            headline_levels: 3,
+            section_numbers: true,
+            toc: true,
+            title: true,
+            todo_keywords: true,
+            tags: true,
+            entities: true,
+            special_strings: true,
+            body_only: false,
+            exclude_tags: vec!["noexport".to_string()],
+        }
+    }
+}
+
+/// The packages of `org-latex-default-packages-alist`.
+const PACKAGES: [(&str, &str); 11] = [
+    ("utf8", "inputenc"),
+    ("T1", "fontenc"),
+    ("", "graphicx"),
+    ("", "longtable"),
+    ("", "wrapfig"),
+    ("", "rotating"),
+    ("normalem", "ulem"),
+    ("", "amsmath"),
+    ("", "amssymb"),
+    ("", "capt-of"),
+    ("", "hyperref"),
+];
+
+/// Sectioning commands by class; other classes get those of `article`.
+const ARTICLE_SECTIONS: [&str; 5] = [
+    "section",
+    "subsection",
+    "subsubsection",
+    "paragraph",
+    "subparagraph",
+];
+const BOOK_SECTIONS: [&str; 5] = ["part", "chapter", "section", "subsection", "subsubsection"];
+
+/// Extensions of file links shown inline as images.
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "pdf", "eps"];
+
+/// The heading `org-footnote-section` puts definitions under. It is dropped
+/// on export, its definitions being typeset where they are referenced.
+const FOOTNOTE_SECTION: &str = "Footnotes";
+
+/// A LaTeX file, or with `body_only` just its body, for `document`.
+/// Macros are expanded first, and LaTeX fragments, entities and
+/// environments are passed through as written.
+pub fn export(document: &Document, config: &LatexConfig) -> String {
+    let document = document
+        .expand_macros()
+        .unwrap_or_else(|_| document.clone());
+    let mut exporter = Exporter::new(&document, config);
+    let body = exporter.body();
+
+    if exporter.config.body_only {
+        return body;
+    }
+
+    let mut out = exporter.preamble();
+    out.push_str("\\begin{document}\n\n");
+
+    if exporter.config.title {
+        out.push_str("\\maketitle\n");
+    }
+
+    if exporter.config.toc {
+        out.push_str("\\tableofcontents\n\n");
+    } else if exporter.config.title {
+        out.push('\n');
+    }
+
+    out.push_str(&body);
+    out.push_str("\\end{document}\n");
+    out
+}
+
+struct Exporter<'a> {
+    document: &'a Document,
+    config: LatexConfig,
+    resolver: Resolver<'a>,
+    sections: &'static [&'static str],
+    /// Labels of exported headings by line.
+    labels: HashMap<usize, String>,
+    /// Headings left out, whose subtrees links can't point into.
+    skipped: Vec<Path>,
+    /// Footnote definitions by label.
+    definitions: HashMap<String, String>,
+    /// Numbers of the footnotes typeset so far by label, so later
+    /// references reuse them.
+    footnotes: HashMap<String, usize>,
+    footnote_count: usize,
+}
+
+impl<'a> Exporter<'a> {
+    fn new(document: &'a Document, config: &LatexConfig) -> Exporter<'a> {
+        let settings = document.settings();
+        let mut config = config.clone();
+
+        for keyword in &settings.keywords {
+            match keyword.key.as_str() {
+                "LATEX_CLASS" if !keyword.value.is_empty() => config.class = keyword.value.clone(),
+                "LATEX_CLASS_OPTIONS" => config.class_options = keyword.value.clone(),
+                _ => (),
+            }
+        }
+
+        let options = &settings.options;
+        config.headline_levels = options.level("H").unwrap_or(config.headline_levels);
+        config.section_numbers = options.flag("num").unwrap_or(config.section_numbers);
+        config.toc = options.flag("toc").unwrap_or(config.toc);
+        config.title = options.flag("title").unwrap_or(config.title);
+        config.todo_keywords = options.flag("todo").unwrap_or(config.todo_keywords);
+        config.tags = options.flag("tags").unwrap_or(config.tags);
+        config.entities = options.flag("e").unwrap_or(config.entities);
+        config.special_strings = options.flag("-").unwrap_or(config.special_strings);
+        config.exclude_tags.extend(settings.exclude_tags);
+
+        let mut resolver = Resolver::new();
+        resolver.add("", document);
+
+        let mut labels = HashMap::new();
+        let mut skipped: Vec<Path> = vec![];
+        let mut elements = document.elements();
+
+        for (path, heading) in document.all_headings() {
+            elements.extend(heading.elements());
+
+            if skipped.iter().any(|skip| path.starts_with(skip)) {
+                continue;
+            }
+
+            if is_exported(heading, &config.exclude_tags) {
+                labels.insert(heading.line, heading_label(&path, heading));
+            } else {
+                skipped.push(path);
+            }
+        }
+
+        let definitions = elements.iter().filter_map(footnote_definition).collect();
+
+        let sections = match config.class.as_str() {
+            "report" | "book" | "scrreprt" | "scrbook" => &BOOK_SECTIONS,
+            _ => &ARTICLE_SECTIONS,
+        };
+
+        Exporter {
+            document,
+            config,
+            resolver,
+            sections,
+            labels,
+            skipped,
+            definitions,
+            footnotes: HashMap::new(),
+            footnote_count: 0,
+        }
+    }
+
+    fn preamble(&mut self) -> String {
+        let settings = self.document.settings();
+        let mut out = format!(
+            "\\documentclass{}{{{}}}\n",
+            self.config.class_options, self.config.class
+        );
+
+        for (options, package) in PACKAGES.iter() {
+            if options.is_empty() {
+                out.push_str(&format!("\\usepackage{{{}}}\n", package));
+            } else {
+                out.push_str(&format!("\\usepackage[{}]{{{}}}\n", options, package));
+            }
+        }
+
+        match self.config.src_backend {
+            SrcBackend::Verbatim => (),
+            SrcBackend::Listings => out.push_str("\\usepackage{listings}\n"),
+            SrcBackend::Minted => out.push_str("\\usepackage{minted}\n"),
+        }
+
+        for keyword in &settings.keywords {
+            if keyword.key == "LATEX_HEADER" || keyword.key == "LATEX_HEADER_EXTRA" {
+                out.push_str(&keyword.value);
+                out.push('\n');
+            }
+        }
+
+        let author = settings.author.as_deref().unwrap_or_default();
+        let title = settings.title.as_deref().unwrap_or_default();
+
+        out.push_str(&format!("\\author{{{}}}\n", self.inline(author)));
+        out.push_str(&match &settings.date {
+            Some(date) => format!("\\date{{{}}}\n", self.inline(date)),
+            None => "\\date{\\today}\n".to_string(),
+        });
+        out.push_str(&format!("\\title{{{}}}\n", self.inline(title)));
+        out.push_str(&format!(
+            "\\hypersetup{{\n pdfauthor={{{}}},\n pdftitle={{{}}}}}\n",
+            escape(author),
+            escape(title)
+        ));
+
+        out
+    }
+
+    fn body(&mut self) -> String {
+        let document = self.document;
+        let mut out = self.elements(&document.elements());
+        out.push_str(&self.headings(&document.headings, &[]));
+        out
+    }
+
+    /// `headings`, the children of the heading at `parent`. Runs of those
+    /// below the headline levels become a list.
+    fn headings(&mut self, headings: &[Heading], parent: &[usize]) -> String {
+        let environment = if self.config.section_numbers {
+            "enumerate"
+        } else {
+            "itemize"
+        };
+        let mut out = String::new();
+        let mut list = false;
+
+        for (i, heading) in headings.iter().enumerate() {
+            if !is_exported(heading, &self.config.exclude_tags) {
+                continue;
+            }
+
+            let deep = heading.level > self.config.headline_levels.min(self.sections.len());
+
+            if deep && !list {
+                out.push_str(&format!("\\begin{{{}}}\n", environment));
+            } else if !deep && list {
+                out.push_str(&format!("\\end{{{}}}\n\n", environment));
+            }
+
+            list = deep;
+
+            let mut path = parent.to_vec();
+            path.push(i);
+            out.push_str(&self.heading(heading, &path, deep));
+        }
+
+        if list {
+            out.push_str(&format!("\\end{{{}}}\n\n", environment));
+        }
+
+        out
+    }
+
+    fn heading(&mut self, heading: &Heading, path: &[usize], deep: bool) -> String {
+        let mut title = self.inline(&heading.title);
+
+        if let Some(keyword) = heading
+            .keyword
+            .as_ref()
+            .filter(|_| self.config.todo_keywords)
+        {
+            title = format!("\\textbf{{\\sffamily {}}} {}", escape(keyword), title);
+        }
+
+        if self.config.tags && !heading.tags.is_empty() {
+            title = format!(
+                "{}\\hfill{{}}\\textsc{{{}}}",
+                title,
+                escape(&heading.tags.join(":"))
+            );
+        }
+
+        let label = &self.labels[&heading.line];
+        let mut out = if deep {
+            format!("\\item {}\n\\label{{{}}}\n", title, label)
+        } else {
+            let star = if self.config.section_numbers { "" } else { "*" };
+            let command = self.sections[heading.level.max(1) - 1];
+            format!("\\{}{}{{{}}}\n\\label{{{}}}\n", command, star, title, label)
+        };
+
+        out.push_str(&self.elements(&heading.elements()));
+        out.push_str(&self.headings(&heading.children, path));
+        out
+    }
+
+    fn elements(&mut self, elements: &[Element]) -> String {
+        elements
+            .iter()
+            .map(|element| self.element(element))
+            .collect()
+    }
+
+    fn element(&mut self, element: &Element) -> String {
+        let body = element.body();
+
+        match &element.kind {
+            Kind::Keyword(keyword) if keyword.key == "LATEX" => format!("{}\n", keyword.value),
+            Kind::Keyword(keyword) if keyword.key == "TOC" => "\\tableofcontents\n\n".to_string(),
+            Kind::Paragraph => self.paragraph(element),
+            Kind::List(items) => format!("{}\n", self.list(items)),
+            Kind::Table => self.table(element),
+            Kind::LatexEnvironment { .. } => format!("{}\n\n", body.join("\n")),
+            Kind::Block { name, parameters } => {
+                self.block(element, name, parameters, &body[1..body.len() - 1])
+            }
+            Kind::Drawer { name } if name != "PROPERTIES" && name != "LOGBOOK" => {
+                let contents = &body[1..body.len() - 1];
+                let elements = element::parse(contents, element.line + 1, false);
+                self.elements(&elements)
+            }
+            Kind::FixedWidth => {
+                let lines: Vec<&str> = body
+                    .iter()
+                    .map(|line| line.trim_start().trim_start_matches(':'))
+                    .map(|line| line.strip_prefix(' ').unwrap_or(line))
+                    .collect();
+                verbatim(&lines)
+            }
+            Kind::HorizontalRule => "\\noindent\\rule{\\linewidth}{0.5pt}\n\n".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn paragraph(&mut self, element: &Element) -> String {
+        if footnote_definition(element).is_some() {
+            return String::new();
+        }
+
+        let body = element.body();
+
+        let text = body.join("\n");
+        let caption = self.caption(element);
+
+        if let Some(image) = Link::parse(text.trim()).and_then(|link| image(&link)) {
+            let graphic = format!("\\includegraphics[width=.9\\linewidth]{{{}}}\n", image);
+
+            return match caption {
+                Some(caption) => format!(
+                    "\\begin{{figure}}[htbp]\n\\centering\n{}{}\\end{{figure}}\n\n",
+                    graphic, caption
+                ),
+                None => format!("\\begin{{center}}\n{}\\end{{center}}\n\n", graphic),
+            };
+        }
+
+        format!("{}\n\n", self.inline(&text))
+    }
+
+    /// `\caption` and `\label` lines from `#+CAPTION:` and `#+NAME:`, if
+    /// there is a caption.
+    fn caption(&mut self, element: &Element) -> Option<String> {
+        let caption: Vec<String> = element
+            .affiliated
+            .iter()
+            .filter(|keyword| keyword.key == "CAPTION")
+            .map(|keyword| keyword.value.clone())
+            .collect();
+
+        if caption.is_empty() {
+            return None;
+        }
+
+        let mut out = format!("\\caption{{{}}}\n", self.inline(&caption.join(" ")));
+
+        if let Some(name) = element.name() {
+            out.push_str(&format!("\\label{{{}}}\n", label(name)));
+        }
+
+        Some(out)
+    }
+
+    fn list(&mut self, items: &[Item]) -> String {
+        let base = items[0].indent;
+        let first = item_text(&items[0]);
+        let environment = if items[0].bullet.starts_with(|c: char| c.is_ascii_digit()) {
+            "enumerate"
+        } else if first.contains(" :: ") {
+            "description"
+        } else {
+            "itemize"
+        };
+
+        let mut out = format!("\\begin{{{}}}\n", environment);
+        let mut i = 0;
+
+        while i < items.len() {
+            let item = &items[i];
+            let end = (i + 1..items.len())
+                .find(|j| items[*j].indent <= base)
+                .unwrap_or(items.len());
+            let text = item_text(item);
+
+            let (term, text) = match text
+                .split_once(" :: ")
+                .filter(|_| environment == "description")
+            {
+                Some((term, text)) => (Some(self.inline(term)), text.to_string()),
+                None => (None, text),
+            };
+
+            let checkbox = item.checkbox.map(|c| match c {
+                ' ' => "$\\square$",
+                '-' => "$\\boxminus$",
+                _ => "$\\boxtimes$",
+            });
+
+            out.push_str(&match (term, checkbox) {
+                (Some(term), _) => format!("\\item[{{{}}}] ", term),
+                (None, Some(checkbox)) => format!("\\item[{{{}}}] ", checkbox),
+                (None, None) => "\\item ".to_string(),
+            });
+            out.push_str(&self.inline(&text));
+            out.push('\n');
+
+            if end > i + 1 {
+                out.push_str(&self.list(&items[i + 1..end]));
+            }
+
+            i = end;
+        }
+
+        out.push_str(&format!("\\end{{{}}}\n", environment));
+        out
+    }
+
+    fn table(&mut self, element: &Element) -> String {
+        let cookie = regex!(r"^<[lcr]?\d*>$");
+        let mut rows: Vec<Option<Vec<String>>> = vec![];
+        let mut cookies: Vec<Option<char>> = vec![];
+
+        for line in element.body() {
+            let line = line.trim();
+
+            if line.starts_with("|-") {
+                rows.push(None);
+            } else if line.starts_with('|') {
+                let line = line.trim_start_matches('|');
+                let line = line.strip_suffix('|').unwrap_or(line);
+                let cells: Vec<String> = line.split('|').map(|c| c.trim().to_string()).collect();
+
+                let is_cookies = cells.iter().any(|c| !c.is_empty())
+                    && cells.iter().all(|c| c.is_empty() || cookie.is_match(c));
+
+                if is_cookies {
+                    cookies = cells.iter().map(|c| c.chars().nth(1)).collect();
+                } else {
+                    rows.push(Some(cells));
+                }
+            }
+        }
+
+        let columns = rows.iter().flatten().map(Vec::len).max().unwrap_or(0);
+        let alignment: String = (0..columns)
+            .map(|i| match cookies.get(i).copied().flatten() {
+                Some(c) if "lcr".contains(c) => c,
+                _ => {
+                    let cells: Vec<&String> = rows
+                        .iter()
+                        .flatten()
+                        .filter_map(|row| row.get(i))
+                        .filter(|cell| !cell.is_empty())
+                        .collect();
+                    let numbers = cells.iter().filter(|cell| is_number(cell)).count();
+
+                    if !cells.is_empty() && numbers * 2 > cells.len() {
+                        'r'
+                    } else {
+                        'l'
+                    }
+                }
+            })
+            .collect();
+
+        let mut tabular = format!("\\begin{{tabular}}{{{}}}\n", alignment);
+
+        for row in &rows {
+            match row {
+                Some(cells) => {
+                    let cells: Vec<String> = cells.iter().map(|cell| self.inline(cell)).collect();
+                    tabular.push_str(&format!("{} \\\\\n", cells.join(" & ")));
+                }
+                None => tabular.push_str("\\hline\n"),
+            }
+        }
+
+        tabular.push_str("\\end{tabular}\n");
+
+        match self.caption(element) {
+            Some(caption) => format!(
+                "\\begin{{table}}[htbp]\n{}\\centering\n{}\\end{{table}}\n\n",
+                caption, tabular
+            ),
+            None => format!("\\begin{{center}}\n{}\\end{{center}}\n\n", tabular),
+        }
+    }
+
+    fn block(
+        &mut self,
+        element: &Element,
+        name: &str,
+        parameters: &str,
+        contents: &[String],
+    ) -> String {
+        let lines: Vec<&str> = contents.iter().map(|line| unescape(line)).collect();
+        let first_word = parameters.split_whitespace().next().unwrap_or_default();
+
+        match name {
+            "SRC" => {
+                let language =
+                    Some(first_word).filter(|w| !w.is_empty() && !w.starts_with([':', '-']));
+                self.src_block(element, language, &lines)
+            }
+            "EXAMPLE" => verbatim(&lines),
+            "EXPORT" if first_word.eq_ignore_ascii_case("latex") => {
+                lines.iter().map(|line| format!("{}\n", line)).collect()
+            }
+            "EXPORT" | "COMMENT" => String::new(),
+            "VERSE" => {
+                let lines: Vec<String> = contents
+                    .iter()
+                    .map(|line| {
+                        if line.trim().is_empty() {
+                            "\\vspace*{1em}".to_string()
+                        } else {
+                            format!("{}\\\\", self.inline(line.trim()))
+                        }
+                    })
+                    .collect();
+                format!("\\begin{{verse}}\n{}\n\\end{{verse}}\n\n", lines.join("\n"))
+            }
+            _ => {
+                let first_line = element.line + element.affiliated.len() + 1;
+                let inner = self.elements(&element::parse(contents, first_line, false));
+                let environment = name.to_lowercase();
+
+                format!(
+                    "\\begin{{{}}}\n{}\\end{{{}}}\n\n",
+                    environment,
+                    inner.trim_end_matches('\n').to_string() + "\n",
+                    environment
+                )
+            }
+        }
+    }
+
+    fn src_block(&mut self, element: &Element, language: Option<&str>, lines: &[&str]) -> String {
+        let code: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        let caption = self.caption(element);
+
+        match self.config.src_backend {
+            SrcBackend::Verbatim => {
+                let block = verbatim(lines);
+
+                match caption {
+                    Some(caption) => format!(
+                        "\\begin{{figure}}[htbp]\n{}{}\\end{{figure}}\n\n",
+                        block.trim_end_matches('\n').to_string() + "\n",
+                        caption
+                    ),
+                    None => block,
+                }
+            }
+            SrcBackend::Listings => {
+                let mut options = vec![];
+
+                if let Some(language) = language {
+                    options.push(format!("language={}", listings_language(language)));
+                }
+
+                if let Some(name) = element.name() {
+                    options.push(format!("label={}", label(name)));
+                }
+
+                let captions: Vec<&str> = element
+                    .affiliated
+                    .iter()
+                    .filter(|keyword| keyword.key == "CAPTION")
+                    .map(|keyword| keyword.value.as_str())
+                    .collect();
+
+                if !captions.is_empty() {
+                    options.push(format!("caption={{{}}}", self.inline(&captions.join(" "))));
+                }
+
+                let options = if options.is_empty() {
+                    String::new()
+                } else {
+                    format!("[{}]", options.join(","))
+                };
+
+                format!(
+                    "\\begin{{lstlisting}}{}\n{}\\end{{lstlisting}}\n\n",
+                    options, code
+                )
+            }
+            SrcBackend::Minted => {
+                let language = minted_language(language.unwrap_or("text"));
+                let block = format!(
+                    "\\begin{{minted}}{{{}}}\n{}\\end{{minted}}\n",
+                    language, code
+                );
+
+                match caption {
+                    Some(caption) => format!(
+                        "\\begin{{listing}}[htbp]\n{}{}\\end{{listing}}\n\n",
+                        block, caption
+                    ),
+                    None => format!("{}\n", block),
+                }
+            }
+        }
+    }
+
+    /// `text` with its markup turned into LaTeX and everything else
+    /// escaped, except for LaTeX fragments.
+    fn inline(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        let mut tokens = scanner::tokens(text);
+        let mut gap = 0;
+
+        while let Some(token) = tokens.next() {
+            let span = tokens.span();
+            let lexeme = &text[span.clone()];
+            let inner = || &lexeme[1..lexeme.len() - 1];
+
+            let latex = match token.token_type {
+                TokenType::Bold => format!("\\textbf{{{}}}", self.inline(inner())),
+                TokenType::Italic => format!("\\emph{{{}}}", self.inline(inner())),
+                TokenType::Underline => format!("\\uline{{{}}}", self.inline(inner())),
+                TokenType::Strikethrough => format!("\\sout{{{}}}", self.inline(inner())),
+                TokenType::Verbatim => format!("\\texttt{{{}}}", escape(inner())),
+                TokenType::Code => verb(inner()),
+                TokenType::LatexFragment => lexeme.to_string(),
+                TokenType::Entity => match Entity::parse(lexeme) {
+                    Some(entity) if self.config.entities => latex_entity(&entity),
+                    _ => escape(lexeme),
+                },
+                TokenType::Link => self.link(lexeme),
+                TokenType::Target => {
+                    let name = lexeme.trim_matches(|c| c == '<' || c == '>');
+                    format!("\\label{{{}}}", target_label(name))
+                }
+                TokenType::RadioTarget => {
+                    let name = lexeme.trim_matches(|c| c == '<' || c == '>');
+                    format!("\\label{{{}}}{}", target_label(name), self.inline(name))
+                }
+                _ if lexeme == "\\\\" => lexeme.to_string(),
+                _ if lexeme.starts_with("[fn:") => match self.footnote(lexeme) {
+                    Some(footnote) => footnote,
+                    None => continue,
+                },
+                _ if lexeme.starts_with(['<', '[']) && Timestamp::parse(lexeme).is_some() => {
+                    format!("\\textit{{{}}}", escape(lexeme))
+                }
+                _ => continue,
+            };
+
+            out.push_str(&self.text(&text[gap..span.start]));
+            out.push_str(&latex);
+            gap = span.end;
+        }
+
+        out.push_str(&self.text(&text[gap..]));
+        out
+    }
+
+    /// Escaped plain text, with special strings typeset if they are on.
+    fn text(&self, text: &str) -> String {
+        if !self.config.special_strings {
+            return escape(text);
+        }
+
+        let mut out = String::new();
+        let mut at = 0;
+
+        for (range, entity) in entities::special_strings(text) {
+            out.push_str(&escape(&text[at..range.start]));
+            out.push_str(&latex_entity(&entity));
+            at = range.end;
+        }
+
+        out.push_str(&escape(&text[at..]));
+        out
+    }
+
+    /// A `[fn:label]`, `[fn::text]` or `[fn:label:text]` reference.
+    fn footnote(&mut self, lexeme: &str) -> Option<String> {
+        let captures = regex!(r"^\[fn:([^:\]]*)(?::(.*))?\]$").captures(lexeme)?;
+        let label = captures[1].to_string();
+
+        if let Some(number) = self.footnotes.get(&label).filter(|_| !label.is_empty()) {
+            return Some(format!("\\footnotemark[{}]", number));
+        }
+
+        let definition = match captures.get(2) {
+            Some(text) => text.as_str().trim().to_string(),
+            None => self.definitions.get(&label)?.clone(),
+        };
+
+        self.footnote_count += 1;
+
+        if !label.is_empty() {
+            self.footnotes.insert(label, self.footnote_count);
+        }
+
+        Some(format!("\\footnote{{{}}}", self.inline(&definition)))
+    }
+
+    fn link(&mut self, lexeme: &str) -> String {
+        let link = match Link::parse(lexeme) {
+            Some(link) => link,
+            None => return escape(lexeme),
+        };
+
+        let (target, description) = if lexeme.starts_with("[[") {
+            let inner = &lexeme[2..lexeme.len() - 2];
+
+            match inner.split_once("][") {
+                Some((target, description)) => (target, Some(self.inline(description))),
+                None => (inner, None),
+            }
+        } else {
+            (lexeme.trim_start_matches('<').trim_end_matches('>'), None)
+        };
+
+        match &link.kind {
+            LinkKind::Heading | LinkKind::CustomId | LinkKind::Id | LinkKind::Fuzzy => {
+                let location = self.resolver.resolve("", &link).filter(|location| {
+                    !self
+                        .skipped
+                        .iter()
+                        .any(|skip| location.path.starts_with(skip))
+                });
+                let label = location.map(|location| {
+                    self.labels
+                        .get(&location.line)
+                        .cloned()
+                        .unwrap_or_else(|| target_label(&link.path))
+                });
+
+                match (label, description) {
+                    (Some(label), Some(description)) => {
+                        format!("\\hyperref[{}]{{{}}}", label, description)
+                    }
+                    (Some(label), None) => format!("\\ref{{{}}}", label),
+                    (None, description) => description.unwrap_or_else(|| escape(&link.path)),
+                }
+            }
+            LinkKind::File if description.is_none() && image(&link).is_some() => format!(
+                "\\includegraphics[width=.9\\linewidth]{{{}}}",
+                image(&link).unwrap()
+            ),
+            _ => {
+                let url = match &link.kind {
+                    LinkKind::Doi => format!("https://doi.org/{}", link.path),
+                    LinkKind::File => link.path.clone(),
+                    _ => target.to_string(),
+                };
+
+                match description {
+                    Some(description) => {
+                        format!("\\href{{{}}}{{{}}}", escape_url(&url), description)
+                    }
+                    None => format!("\\url{{{}}}", escape_url(&url)),
+                }
+            }
+        }
+    }
+}
+
+/// `[fn:label] definition` paragraphs, as a label and the definition.
+fn footnote_definition(element: &Element) -> Option<(String, String)> {
+    if element.kind != Kind::Paragraph {
+        return None;
+    }
+
+    let body = element.body();
+    let captures = regex!(r"^\[fn:([^:\]\s]+)\]\s*(.*)$").captures(&body[0])?;
+    let mut definition = vec![captures[2].to_string()];
+    definition.extend(body[1..].iter().map(|line| line.trim().to_string()));
+
+    Some((
+        captures[1].to_string(),
+        definition.join("\n").trim().to_string(),
+    ))
+}
+
+/// Whether `heading` and its subtree are exported: it isn't commented
+/// out, tagged with one of `exclude_tags` or the footnote section.
+fn is_exported(heading: &Heading, exclude_tags: &[String]) -> bool {
+    let comment = heading.title == "COMMENT" || heading.title.starts_with("COMMENT ");
+    let excluded = heading.tags.iter().any(|tag| exclude_tags.contains(tag));
+
+    !comment && !excluded && heading.title != FOOTNOTE_SECTION
+}
+
+/// The `\label` of a heading: its `CUSTOM_ID` or else its position.
+fn heading_label(path: &Path, heading: &Heading) -> String {
+    match heading.property("CUSTOM_ID") {
+        Some(id) => label(&id),
+        None => {
+            let numbers: Vec<String> = path.iter().map(|i| (i + 1).to_string()).collect();
+            format!("sec:{}", numbers.join("-"))
+        }
+    }
+}
+
+fn target_label(name: &str) -> String {
+    format!("target:{}", label(&name.to_lowercase()))
+}
+
+/// `name` with characters that don't belong in a `\label` replaced.
+fn label(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ':' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// The path of a link to an image file.
+fn image(link: &Link) -> Option<String> {
+    let extension = link.path.rsplit_once('.')?.1.to_lowercase();
+
+    if link.kind == LinkKind::File && IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+        Some(link.path.clone())
+    } else {
+        None
+    }
+}
+
+fn item_text(item: &Item) -> String {
+    let bullet = regex!(r"^\s*(?:[-+*]|\d+[.)])(?:\s+|$)(?:\[[ xX-]\]\s)?");
+    let first = bullet.replace(&item.lines[0], "").to_string();
+    let mut lines = vec![first];
+
+    lines.extend(
+        item.lines[1..]
+            .iter()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty()),
+    );
+
+    lines.join("\n")
+}
+
+/// A line of a block without the comma that protects `*` and `#+` lines.
+fn unescape(line: &str) -> &str {
+    let trimmed = line.trim_start();
+
+    match trimmed.strip_prefix(',') {
+        Some(rest) if rest.starts_with('*') || rest.starts_with("#+") => rest,
+        _ => line,
+    }
+}
+
+fn verbatim(lines: &[&str]) -> String {
+    let code: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    format!("\\begin{{verbatim}}\n{}\\end{{verbatim}}\n\n", code)
+}
+
+/// `\verb` with a delimiter that isn't in `code`.
+fn verb(code: &str) -> String {
+    let delimiter = "!|=~+-/,.;:@#"
+        .chars()
+        .find(|c| !code.contains(*c))
+        .unwrap_or('!');
+
+    format!("\\verb{}{}{}", delimiter, code, delimiter)
+}
+
+/// Names `listings` knows languages by, after `org-latex-listings-langs`.
+fn listings_language(language: &str) -> &str {
+    match language {
+        "emacs-lisp" | "lisp" | "clojure" => "Lisp",
+        "c" => "C",
+        "cc" | "cpp" => "C++",
+        "fortran" => "fortran",
+        "perl" | "cperl" => "Perl",
+        "python" => "Python",
+        "ruby" => "Ruby",
+        "html" => "HTML",
+        "xml" => "XML",
+        "tex" => "TeX",
+        "latex" => "[LaTeX]TeX",
+        "sh" | "shell" | "shell-script" | "bash" => "bash",
+        "gnuplot" => "Gnuplot",
+        "ocaml" => "[Objective]Caml",
+        "caml" => "Caml",
+        "sql" | "sqlite" => "SQL",
+        "makefile" => "make",
+        "R" => "r",
+        _ => language,
+    }
+}
+
+/// Names `minted` knows languages by, after `org-latex-minted-langs`.
+fn minted_language(language: &str) -> String {
+    match language {
+        "emacs-lisp" | "elisp" => "common-lisp".to_string(),
+        "sh" | "shell" | "shell-script" => "bash".to_string(),
+        _ => language.to_lowercase(),
+    }
+}
+
+/// How `entity` is written in running text.
+fn latex_entity(entity: &Entity) -> String {
+    if entity.math {
+        format!("${}$", entity.latex)
+    } else {
+        entity.latex.to_string()
+    }
+}
+
+/// `text` with LaTeX's special characters escaped.
+pub fn escape(text: &str) -> String {
+    let mut out = String::new();
+
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+
+    out
+}
+
+fn escape_url(url: &str) -> String {
+    url.replace('%', "\\%").replace('#', "\\#")
+}
@@ -1,5 +1,38 @@
-mod parser;
-mod scanner;
+/// A regex compiled the first time it is used and kept for the lifetime of
+/// the program.
+macro_rules! regex {
+    ($pattern:expr) => {{
+        static REGEX: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+        REGEX.get_or_init(|| regex::Regex::new($pattern).unwrap())
+    }};
+}
+
+pub mod diagnostic;
+pub mod document;
+pub mod edit;
+pub mod element;
+pub mod entities;
+pub mod events;
+pub mod format;
+pub mod graph;
+pub mod highlight;
+pub mod icalendar;
+pub mod include;
+pub mod incremental;
+pub mod latex;
+pub mod link;
+pub mod lint;
+pub mod lsp;
+pub mod macros;
+pub mod outline;
+pub mod parser;
+pub mod query;
+pub mod resolve;
+pub mod scanner;
+pub mod settings;
+pub mod tags;
+pub mod timestamp;
+pub mod visit;
 
 #[macro_use]
 extern crate serde_derive;
@@ -8,41 +41,546 @@ use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
 pub fn parse(input: &str) -> JsValue {
-    let result = scanner::scan(input.to_string());
+    let result = parser::parse(scanner::tokens(input));
+
+    #[allow(deprecated)]
+    JsValue::from_serde(&result).unwrap()
+}
+
+/// The outline of `input` without section contents. Ranges are byte
+/// offsets into the UTF-8 text.
+#[wasm_bindgen]
+pub fn outline(input: &str) -> JsValue {
+    let result = outline::outline(input);
+
+    #[allow(deprecated)]
+    JsValue::from_serde(&result).unwrap()
+}
+
+/// Syntax problems in `input`; spans are byte offsets into the UTF-8 text.
+#[wasm_bindgen]
+pub fn diagnostics(input: &str) -> JsValue {
+    let result = diagnostic::check(input);
+
+    #[allow(deprecated)]
+    JsValue::from_serde(&result).unwrap()
+}
+
+/// Lints `input`, leaving out the rules whose codes are in `disabled`.
+#[wasm_bindgen]
+pub fn lint(input: &str, disabled: Vec<String>) -> Result<JsValue, JsValue> {
+    let mut config = lint::LintConfig::default();
+
+    for code in disabled {
+        let rule = lint::Rule::from_code(&code)
+            .ok_or_else(|| JsValue::from_str(&format!("unknown rule {}", code)))?;
+        config.disabled.push(rule);
+    }
+
+    let result = lint::lint(input, &config);
+
+    #[allow(deprecated)]
+    Ok(JsValue::from_serde(&result).unwrap())
+}
+
+/// `input` in canonical form, with tags aligned at `tags_column`.
+#[wasm_bindgen]
+pub fn format(input: &str, tags_column: i32) -> String {
+    let config = format::FormatConfig {
+        tags_column: tags_column as isize,
+        ..format::FormatConfig::default()
+    };
+
+    format::format(input, &config)
+}
+
+/// Semantic tokens for highlighting the part of `input` from `start` to
+/// `end`. Offsets, in the arguments and the spans returned, are in UTF-16
+/// code units, as JavaScript counts them.
+#[wasm_bindgen]
+pub fn highlight(input: &str, start: usize, end: usize) -> JsValue {
+    let range = byte_offset(input, start)..byte_offset(input, end);
+    let mut result = highlight::highlight_range(input, range);
+
+    // Tokens come in order, so offsets can be converted in one pass.
+    let mut units = 0;
+    let mut at = 0;
+    let mut convert = |offset: usize| {
+        units += input[at..offset].encode_utf16().count();
+        at = offset;
+        units
+    };
+
+    for token in &mut result {
+        token.span = convert(token.span.start)..convert(token.span.end);
+    }
+
+    #[allow(deprecated)]
+    JsValue::from_serde(&result).unwrap()
+}
+
+/// `input` as an iCalendar file with the default options, stamped with
+/// `now` in seconds since 1970-01-01 UTC.
+#[wasm_bindgen]
+pub fn icalendar(input: &str, now: f64) -> String {
+    let document = document::parse(input);
+    let config = icalendar::IcalConfig::default();
+
+    icalendar::export(&[("", &document)], &config, now as i64)
+}
+
+/// Org text with a top-level heading for each event and to-do of the
+/// iCalendar file `input`. UTC times are moved `utc_offset` minutes east,
+/// which is `-new Date().getTimezoneOffset()` for the local zone.
+#[wasm_bindgen]
+pub fn import_icalendar(input: &str, utc_offset: f64) -> Result<String, JsValue> {
+    let headings = icalendar::import(input, 1, utc_offset as i64)
+        .map_err(|error| JsValue::from_str(&error.to_string()))?;
+
+    Ok(headings.iter().map(|heading| heading.to_org()).collect())
+}
+
+/// The entity called `name`, like `alpha`, with its LaTeX, HTML, ASCII
+/// and UTF-8 forms, or `null`.
+#[wasm_bindgen]
+pub fn entity(name: &str) -> JsValue {
+    let result = entities::entity(name);
+
+    #[allow(deprecated)]
+    JsValue::from_serde(&result).unwrap()
+}
+
+/// `input` as a LaTeX file with the default options.
+#[wasm_bindgen]
+pub fn latex(input: &str) -> String {
+    let document = document::parse(input);
+
+    latex::export(&document, &latex::LatexConfig::default())
+}
+
+#[wasm_bindgen]
+pub fn parse_link(input: &str) -> JsValue {
+    let result = link::Link::parse(input);
+
+    #[allow(deprecated)]
+    JsValue::from_serde(&result).unwrap()
+}
+
+#[wasm_bindgen]
+pub fn broken_links(input: &str) -> JsValue {
+    let document = document::parse(input);
+
+    let mut resolver = resolve::Resolver::new();
+    resolver.add("", &document);
+
+    #[allow(deprecated)]
+    JsValue::from_serde(&resolver.broken_links()).unwrap()
+}
+
+#[wasm_bindgen]
+extern "C" {
+    /// Any JS object with a `load(path)` method returning the file contents
+    /// as a string, or throwing if it can't be read.
+    pub type FileLoader;
+
+    #[wasm_bindgen(method, catch)]
+    fn load(this: &FileLoader, path: &str) -> Result<JsValue, JsValue>;
+}
+
+impl include::FileLoader for FileLoader {
+    fn load(&self, path: &str) -> Result<String, String> {
+        match FileLoader::load(self, path) {
+            Ok(contents) => contents
+                .as_string()
+                .ok_or_else(|| "loader did not return a string".to_string()),
+            Err(error) => Err(error
+                .as_string()
+                .unwrap_or_else(|| "loader failed".to_string())),
+        }
+    }
+}
+
+/// `input`, the contents of `file`, with `#+INCLUDE:` and `#+SETUPFILE:`
+/// expanded through `loader`.
+#[wasm_bindgen]
+pub fn expand_includes(input: &str, file: &str, loader: &FileLoader) -> Result<String, JsValue> {
+    include::expand(input, file, loader).map_err(|error| JsValue::from_str(&error.to_string()))
+}
+
+/// `input` with `{{{macro}}}` references replaced.
+#[wasm_bindgen]
+pub fn expand_macros(input: &str) -> Result<String, JsValue> {
+    match document::parse(input).expand_macros() {
+        Ok(document) => Ok(document.to_org()),
+        Err(error) => Err(JsValue::from_str(&error.to_string())),
+    }
+}
+
+#[wasm_bindgen]
+pub fn settings(input: &str) -> JsValue {
+    let result = document::parse(input).settings();
+
+    #[allow(deprecated)]
+    JsValue::from_serde(&result).unwrap()
+}
 
-    let result = parser::parse(result);
+/// Local and inherited tags of the heading at `path`, using the default
+/// inheritance settings.
+#[wasm_bindgen]
+pub fn heading_tags(input: &str, path: Vec<usize>) -> JsValue {
+    let document = document::parse(input);
+    let config = tags::TagConfig::default();
+
+    let result = (
+        document.local_tags(&path),
+        document.inherited_tags(&path, &config),
+    );
 
+    #[allow(deprecated)]
     JsValue::from_serde(&result).unwrap()
 }
 
+/// Headings matching an Org match string such as `+work-boss/TODO`.
+/// `today` (`2019-09-25`) is what `<today>` and relative dates refer to.
+#[wasm_bindgen]
+pub fn query(input: &str, match_string: &str, today: &str) -> Result<JsValue, JsValue> {
+    let document = document::parse(input);
+    let today = timestamp::Date::parse(today).ok_or_else(|| JsValue::from_str("invalid date"))?;
+
+    let query =
+        query::Query::parse(match_string).map_err(|error| JsValue::from_str(&error.to_string()))?;
+    let result = query.search(&document, &query::QueryOptions::new(today));
+
+    #[allow(deprecated)]
+    Ok(JsValue::from_serde(&result).unwrap())
+}
+
+/// A link graph kept alive on the JS side, updated one file at a time.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct LinkGraph {
+    graph: graph::LinkGraph,
+}
+
+#[wasm_bindgen]
+impl LinkGraph {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> LinkGraph {
+        LinkGraph::default()
+    }
+
+    pub fn update(&mut self, file: &str, input: &str) {
+        self.graph.update(file, document::parse(input));
+    }
+
+    pub fn remove(&mut self, file: &str) {
+        self.graph.remove(file);
+    }
+
+    pub fn backlinks(&self, file: &str, path: Vec<usize>) -> JsValue {
+        #[allow(deprecated)]
+        JsValue::from_serde(&self.graph.backlinks(file, &path)).unwrap()
+    }
+
+    pub fn file_backlinks(&self, file: &str) -> JsValue {
+        #[allow(deprecated)]
+        JsValue::from_serde(&self.graph.file_backlinks(file)).unwrap()
+    }
+
+    pub fn to_json(&self) -> String {
+        self.graph.to_json()
+    }
+
+    pub fn to_dot(&self) -> String {
+        self.graph.to_dot()
+    }
+}
+
+/// A document kept alive on the JS side for structural edits. Paths are
+/// arrays of child indices, as in `heading_tags`.
+#[wasm_bindgen]
+pub struct Document {
+    document: document::Document,
+}
+
+fn edit_error(error: edit::EditError) -> JsValue {
+    JsValue::from_str(&error.to_string())
+}
+
+fn timestamp(text: Option<String>) -> Result<Option<timestamp::Timestamp>, JsValue> {
+    match text {
+        Some(text) => timestamp::Timestamp::parse(&text)
+            .map(Some)
+            .ok_or_else(|| JsValue::from_str("invalid timestamp")),
+        None => Ok(None),
+    }
+}
+
+#[wasm_bindgen]
+impl Document {
+    #[wasm_bindgen(constructor)]
+    pub fn new(input: &str) -> Document {
+        Document {
+            document: document::parse(input),
+        }
+    }
+
+    pub fn to_org(&self) -> String {
+        self.document.to_org()
+    }
+
+    /// Inserts the first heading of `input`, with its subtree.
+    pub fn insert_subtree(
+        &mut self,
+        parent: Vec<usize>,
+        index: usize,
+        input: &str,
+    ) -> Result<Vec<usize>, JsValue> {
+        let heading = document::parse(input)
+            .headings
+            .into_iter()
+            .next()
+            .ok_or_else(|| JsValue::from_str("no heading to insert"))?;
+
+        self.document
+            .insert_subtree(&parent, index, heading)
+            .map_err(edit_error)
+    }
+
+    /// Removes the subtree at `path` and returns it as Org text.
+    pub fn remove_subtree(&mut self, path: Vec<usize>) -> Result<String, JsValue> {
+        self.document
+            .remove_subtree(&path)
+            .map(|heading| heading.to_org())
+            .map_err(edit_error)
+    }
+
+    pub fn move_subtree(
+        &mut self,
+        from: Vec<usize>,
+        parent: Vec<usize>,
+        index: usize,
+    ) -> Result<Vec<usize>, JsValue> {
+        self.document
+            .move_subtree(&from, &parent, index)
+            .map_err(edit_error)
+    }
+
+    pub fn promote(&mut self, path: Vec<usize>) -> Result<Vec<usize>, JsValue> {
+        self.document.promote(&path).map_err(edit_error)
+    }
+
+    pub fn demote(&mut self, path: Vec<usize>) -> Result<Vec<usize>, JsValue> {
+        self.document.demote(&path).map_err(edit_error)
+    }
+
+    pub fn set_keyword(
+        &mut self,
+        path: Vec<usize>,
+        keyword: Option<String>,
+    ) -> Result<(), JsValue> {
+        self.document
+            .set_keyword(&path, keyword.as_deref())
+            .map_err(edit_error)
+    }
+
+    pub fn set_priority(
+        &mut self,
+        path: Vec<usize>,
+        priority: Option<char>,
+    ) -> Result<(), JsValue> {
+        self.document
+            .set_priority(&path, priority)
+            .map_err(edit_error)
+    }
+
+    pub fn add_tag(&mut self, path: Vec<usize>, tag: &str) -> Result<(), JsValue> {
+        self.document.add_tag(&path, tag).map_err(edit_error)
+    }
+
+    pub fn remove_tag(&mut self, path: Vec<usize>, tag: &str) -> Result<(), JsValue> {
+        self.document.remove_tag(&path, tag).map_err(edit_error)
+    }
+
+    pub fn set_property(
+        &mut self,
+        path: Vec<usize>,
+        key: &str,
+        value: &str,
+    ) -> Result<(), JsValue> {
+        self.document
+            .set_property(&path, key, value)
+            .map_err(edit_error)
+    }
+
+    /// `timestamp` is Org syntax, e.g. `<2019-09-25 Wed>`.
+    pub fn set_scheduled(
+        &mut self,
+        path: Vec<usize>,
+        timestamp: Option<String>,
+    ) -> Result<(), JsValue> {
+        let timestamp = crate::timestamp(timestamp)?;
+        self.document
+            .set_scheduled(&path, timestamp)
+            .map_err(edit_error)
+    }
+
+    pub fn set_deadline(
+        &mut self,
+        path: Vec<usize>,
+        timestamp: Option<String>,
+    ) -> Result<(), JsValue> {
+        let timestamp = crate::timestamp(timestamp)?;
+        self.document
+            .set_deadline(&path, timestamp)
+            .map_err(edit_error)
+    }
+
+    pub fn add_note(&mut self, path: Vec<usize>, note: &str, time: &str) -> Result<(), JsValue> {
+        let time = crate::timestamp(Some(time.to_string()))?.unwrap();
+        self.document
+            .add_note(&path, note, &time)
+            .map_err(edit_error)
+    }
+}
+
+/// Text being edited in the browser, re-parsed as it changes.
+#[wasm_bindgen]
+pub struct Buffer {
+    buffer: incremental::Buffer,
+}
+
+/// Byte offset of the UTF-16 code unit `offset` in `text`, as JS counts
+/// string positions.
+fn byte_offset(text: &str, offset: usize) -> usize {
+    let mut units = 0;
+
+    for (i, c) in text.char_indices() {
+        if units >= offset {
+            return i;
+        }
+
+        units += c.len_utf16();
+    }
+
+    text.len()
+}
+
+#[wasm_bindgen]
+impl Buffer {
+    #[wasm_bindgen(constructor)]
+    pub fn new(input: &str) -> Buffer {
+        Buffer {
+            buffer: incremental::Buffer::new(input),
+        }
+    }
+
+    /// Replaces `start..end`, in UTF-16 code units, with `text` and returns
+    /// what was re-parsed.
+    pub fn edit(&mut self, start: usize, end: usize, text: &str) -> Result<JsValue, JsValue> {
+        let edit = incremental::TextEdit {
+            start: byte_offset(self.buffer.text(), start),
+            end: byte_offset(self.buffer.text(), end),
+            text: text.to_string(),
+        };
+        let result = self.buffer.edit(&edit).map_err(edit_error)?;
+
+        #[allow(deprecated)]
+        Ok(JsValue::from_serde(&result).unwrap())
+    }
+
+    pub fn document(&self) -> JsValue {
+        #[allow(deprecated)]
+        JsValue::from_serde(self.buffer.document()).unwrap()
+    }
+
+    /// The heading at `path`, e.g. one of those an edit changed.
+    pub fn heading(&self, path: Vec<usize>) -> JsValue {
+        #[allow(deprecated)]
+        JsValue::from_serde(&self.buffer.document().heading(&path)).unwrap()
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
+    use crate::diagnostic::Severity;
+    use crate::document;
+    use crate::edit::EditError;
+    use crate::element::Keyword;
+    use crate::element::Kind;
+    use crate::entities;
+    use crate::entities::Entity;
+    use crate::events;
+    use crate::events::Event;
+    use crate::format;
+    use crate::format::FormatConfig;
+    use crate::graph::LinkGraph;
+    use crate::highlight;
+    use crate::highlight::Modifier;
+    use crate::highlight::SemanticType;
+    use crate::icalendar;
+    use crate::icalendar::IcalConfig;
+    use crate::icalendar::ImportError;
+    use crate::icalendar::IncludeTodo;
+    use crate::icalendar::Usage;
+    use crate::include;
+    use crate::include::FileLoader;
+    use crate::include::IncludeError;
+    use crate::incremental::Buffer;
+    use crate::incremental::TextEdit;
+    use crate::incremental::Update;
+    use crate::latex;
+    use crate::latex::LatexConfig;
+    use crate::latex::SrcBackend;
+    use crate::link::Link;
+    use crate::link::LinkKind;
+    use crate::lint;
+    use crate::lint::LintConfig;
+    use crate::lint::Rule;
+    use crate::lsp::Server;
+    use crate::macros::MacroError;
+    use crate::macros::MacroReference;
+    use crate::outline;
     use crate::parser;
-    use crate::parser::Node;
+    use crate::query::Query;
+    use crate::query::QueryOptions;
+    use crate::resolve::Location;
+    use crate::resolve::Resolver;
     use crate::scanner;
     use crate::scanner::Token;
     use crate::scanner::TokenType;
+    use crate::settings::SubSuperscripts;
+    use crate::tags::TagConfig;
+    use crate::tags::TagGroup;
+    use crate::timestamp::Date;
+    use crate::timestamp::Timestamp;
+    use crate::visit;
+    use crate::visit::Visitor;
+    use crate::visit::VisitorMut;
+    use proptest::prelude::*;
+    use serde_json::json;
+    use serde_json::Value;
     use std::collections::HashMap;
 
     #[test]
     fn doc_title() {
-        let result = scanner::scan("#+TITLE: LifeRepo".to_string());
+        let result = scanner::scan("#+TITLE: LifeRepo");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Title,
-                lexeme: "#+TITLE:".to_string(),
+                lexeme: "#+TITLE:".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::String,
-                lexeme: "LifeRepo".to_string(),
+                lexeme: "LifeRepo".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
@@ -52,22 +590,22 @@ mod tests {
 
     #[test]
     fn heading() {
-        let result = scanner::scan("*** projects".to_string());
+        let result = scanner::scan("*** projects");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Asterisk,
-                lexeme: "***".to_string(),
+                lexeme: "***".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::String,
-                lexeme: "projects".to_string(),
+                lexeme: "projects".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
@@ -77,17 +615,17 @@ mod tests {
 
     #[test]
     fn date() {
-        let result = scanner::scan("<2019-09-25 Wed>".to_string());
+        let result = scanner::scan("<2019-09-25 Wed>");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Date,
-                lexeme: "<2019-09-25 Wed>".to_string(),
+                lexeme: "<2019-09-25 Wed>".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
@@ -97,17 +635,17 @@ mod tests {
 
     #[test]
     fn bold() {
-        let result = scanner::scan("*hotdogs*".to_string());
+        let result = scanner::scan("*hotdogs*");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Bold,
-                lexeme: "*hotdogs*".to_string(),
+                lexeme: "*hotdogs*".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
@@ -117,17 +655,17 @@ mod tests {
 
     #[test]
     fn timestamp() {
-        let result = scanner::scan("[2019-09-19 Thu 10:40]".to_string());
+        let result = scanner::scan("[2019-09-19 Thu 10:40]");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Timestamp,
-                lexeme: "[2019-09-19 Thu 10:40]".to_string(),
+                lexeme: "[2019-09-19 Thu 10:40]".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
@@ -137,40 +675,44 @@ mod tests {
 
     #[test]
     fn link() {
-        let result = scanner::scan(
-            "[[https://orgmode.org/worg/dev/org-syntax.html][org-mode syntax]]".to_string(),
-        );
+        let result =
+            scanner::scan("[[https://orgmode.org/worg/dev/org-syntax.html][org-mode syntax]]");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Link,
-                lexeme: "[[https://orgmode.org/worg/dev/org-syntax.html][org-mode syntax]]"
-                    .to_string(),
+                lexeme: "[[https://orgmode.org/worg/dev/org-syntax.html][org-mode syntax]]".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
 
         assert_eq!(result, expected);
+
+        // A description may go on to the next line, but not past a heading.
+        let result = scanner::scan("[[id:1][two\nlines]] [a\n* B]");
+        assert_eq!(result[0].lexeme, "[[id:1][two\nlines]]");
+        assert_eq!(result[1].lexeme, "[");
+        assert_eq!(result[3].token_type, TokenType::Asterisk);
     }
 
     #[test]
     fn italic() {
-        let result = scanner::scan("/italic/".to_string());
+        let result = scanner::scan("/italic/");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Italic,
-                lexeme: "/italic/".to_string(),
+                lexeme: "/italic/".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
@@ -180,17 +722,17 @@ mod tests {
 
     #[test]
     fn underline() {
-        let result = scanner::scan("_underline_".to_string());
+        let result = scanner::scan("_underline_");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Underline,
-                lexeme: "_underline_".to_string(),
+                lexeme: "_underline_".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
@@ -200,17 +742,17 @@ mod tests {
 
     #[test]
     fn strikethrough() {
-        let result = scanner::scan("+strikethrough+".to_string());
+        let result = scanner::scan("+strikethrough+");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Strikethrough,
-                lexeme: "+strikethrough+".to_string(),
+                lexeme: "+strikethrough+".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
@@ -220,22 +762,22 @@ mod tests {
 
     #[test]
     fn underlined_title() {
-        let result = scanner::scan("*** _Agenda_".to_string());
+        let result = scanner::scan("*** _Agenda_");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Asterisk,
-                lexeme: "***".to_string(),
+                lexeme: "***".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::Underline,
-                lexeme: "_Agenda_".to_string(),
+                lexeme: "_Agenda_".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 1,
             },
         ];
@@ -249,84 +791,83 @@ mod tests {
             ":LOGBOOK:
     CLOCK: [2019-09-21 Sat 17:11]--[2019-09-21 Sat 18:24] =>  1:13
     CLOCK: [2019-09-21 Sat 16:26]--[2019-09-21 Sat 16:58] =>  0:32
-    :END:"
-                .to_string(),
+    :END:",
         );
 
         let expected = vec![
             Token {
                 token_type: TokenType::LogBook,
-                lexeme: ":LOGBOOK:".to_string(),
+                lexeme: ":LOGBOOK:".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::Clock,
-                lexeme: "CLOCK:".to_string(),
+                lexeme: "CLOCK:".into(),
                 line: 2,
             },
             Token {
                 token_type: TokenType::Timestamp,
-                lexeme: "[2019-09-21 Sat 17:11]".to_string(),
+                lexeme: "[2019-09-21 Sat 17:11]".into(),
                 line: 2,
             },
             Token {
                 token_type: TokenType::String,
-                lexeme: "--".to_string(),
+                lexeme: "--".into(),
                 line: 2,
             },
             Token {
                 token_type: TokenType::Timestamp,
-                lexeme: "[2019-09-21 Sat 18:24]".to_string(),
+                lexeme: "[2019-09-21 Sat 18:24]".into(),
                 line: 2,
             },
             Token {
                 token_type: TokenType::String,
-                lexeme: "=>".to_string(),
+                lexeme: "=>".into(),
                 line: 2,
             },
             Token {
                 token_type: TokenType::Duration,
-                lexeme: "1:13".to_string(),
+                lexeme: "1:13".into(),
                 line: 2,
             },
             Token {
                 token_type: TokenType::Clock,
-                lexeme: "CLOCK:".to_string(),
+                lexeme: "CLOCK:".into(),
                 line: 3,
             },
             Token {
                 token_type: TokenType::Timestamp,
-                lexeme: "[2019-09-21 Sat 16:26]".to_string(),
+                lexeme: "[2019-09-21 Sat 16:26]".into(),
                 line: 3,
             },
             Token {
                 token_type: TokenType::String,
-                lexeme: "--".to_string(),
+                lexeme: "--".into(),
                 line: 3,
             },
             Token {
                 token_type: TokenType::Timestamp,
-                lexeme: "[2019-09-21 Sat 16:58]".to_string(),
+                lexeme: "[2019-09-21 Sat 16:58]".into(),
                 line: 3,
             },
             Token {
                 token_type: TokenType::String,
-                lexeme: "=>".to_string(),
+                lexeme: "=>".into(),
                 line: 3,
             },
             Token {
                 token_type: TokenType::Duration,
-                lexeme: "0:32".to_string(),
+                lexeme: "0:32".into(),
                 line: 3,
             },
             Token {
                 token_type: TokenType::End,
-                lexeme: ":END:".to_string(),
+                lexeme: ":END:".into(),
                 line: 4,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 4,
             },
         ];
@@ -336,41 +877,2085 @@ mod tests {
 
     #[test]
     fn multi_line() {
-        let result = scanner::scan("** TODO Futurice \n *** brainstorming ".to_string());
+        let result = scanner::scan("** TODO Futurice \n *** brainstorming ");
 
         let expected = vec![
             Token {
                 token_type: TokenType::Asterisk,
-                lexeme: "**".to_string(),
+                lexeme: "**".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::Todo,
-                lexeme: "TODO".to_string(),
+                lexeme: "TODO".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::String,
-                lexeme: "Futurice".to_string(),
+                lexeme: "Futurice".into(),
                 line: 1,
             },
             Token {
                 token_type: TokenType::Asterisk,
-                lexeme: "***".to_string(),
+                lexeme: "***".into(),
                 line: 2,
             },
             Token {
                 token_type: TokenType::String,
-                lexeme: "brainstorming".to_string(),
+                lexeme: "brainstorming".into(),
                 line: 2,
             },
             Token {
                 token_type: TokenType::EOF,
-                lexeme: "".to_string(),
+                lexeme: "".into(),
                 line: 2,
             },
         ];
 
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn internal_link() {
+        let result = scanner::scan("[[*Heading title]]");
+
+        assert_eq!(result[0].token_type, TokenType::Link);
+
+        let link = Link::from_token(&result[0]).unwrap();
+
+        assert_eq!(link.kind, LinkKind::Heading);
+        assert_eq!(link.path, "Heading title");
+    }
+
+    #[test]
+    fn link_description() {
+        let link =
+            Link::parse("[[https://orgmode.org/worg/dev/org-syntax.html][org-mode *syntax*]]")
+                .unwrap();
+
+        let expected = Link {
+            kind: LinkKind::Https,
+            path: "//orgmode.org/worg/dev/org-syntax.html".to_string(),
+            search_option: None,
+            description: vec![
+                Token {
+                    token_type: TokenType::String,
+                    lexeme: "org-mode".into(),
+                    line: 1,
+                },
+                Token {
+                    token_type: TokenType::Bold,
+                    lexeme: "*syntax*".into(),
+                    line: 1,
+                },
+            ],
+        };
+
+        assert_eq!(link, expected);
+    }
+
+    #[test]
+    fn file_link_search_option() {
+        let link = Link::parse("[[file:notes.org::*Meeting notes]]").unwrap();
+
+        assert_eq!(link.kind, LinkKind::File);
+        assert_eq!(link.path, "notes.org");
+        assert_eq!(link.search_option, Some("*Meeting notes".to_string()));
+
+        let link = Link::parse("[[file:projects.org::123]]").unwrap();
+
+        assert_eq!(link.search_option, Some("123".to_string()));
+    }
+
+    #[test]
+    fn plain_and_angle_links() {
+        let result = scanner::scan("see https://orgmode.org/manual, or <mailto:me@jskjott.com>");
+
+        let links: Vec<Link> = result.iter().filter_map(Link::from_token).collect();
+
+        assert_eq!(result[1].lexeme, "https://orgmode.org/manual");
+        assert_eq!(links.len(), 2);
+        assert_eq!(links[0].kind, LinkKind::Https);
+        assert_eq!(links[1].kind, LinkKind::Mailto);
+        assert_eq!(links[1].path, "me@jskjott.com");
+
+        let result = scanner::scan("<https://orgmode.org> and more");
+
+        assert_eq!(result[0].token_type, TokenType::Link);
+        assert_eq!(result[0].lexeme, "<https://orgmode.org>");
+    }
+
+    #[test]
+    fn link_kinds() {
+        let kinds: Vec<LinkKind> = vec![
+            "[[id:6a5b5e3c-2f3e-4c7e-9b9f-1b1f8e0d2a11]]",
+            "[[#custom-id]]",
+            "[[doi:10.1000/182]]",
+            "[[some target]]",
+            "[[gopher://example.com]]",
+        ]
+        .into_iter()
+        .map(|lexeme| Link::parse(lexeme).unwrap().kind)
+        .collect();
+
+        let expected = vec![
+            LinkKind::Id,
+            LinkKind::CustomId,
+            LinkKind::Doi,
+            LinkKind::Fuzzy,
+            LinkKind::Other("gopher".to_string()),
+        ];
+
+        assert_eq!(kinds, expected);
+    }
+
+    #[test]
+    fn document_outline() {
+        let source = "#+TITLE: LifeRepo
+* TODO [#A] Futurice :work:
+** brainstorming
+#+BEGIN_SRC org
+* not a heading
+#+END_SRC
+* projects
+";
+        let document = document::parse(source);
+
+        assert_eq!(document.section, vec!["#+TITLE: LifeRepo"]);
+        assert_eq!(document.headings.len(), 2);
+
+        let heading = &document.headings[0];
+
+        assert_eq!(heading.keyword, Some("TODO".to_string()));
+        assert_eq!(heading.priority, Some('A'));
+        assert_eq!(heading.title, "Futurice");
+        assert_eq!(heading.tags, vec!["work"]);
+        assert_eq!(heading.children[0].title, "brainstorming");
+        assert_eq!(heading.children[0].section.len(), 3);
+        assert_eq!(document.heading(&[1]).unwrap().line, 7);
+        assert_eq!(document.to_org(), source);
+    }
+
+    #[test]
+    fn resolve_internal_links() {
+        let document = document::parse(
+            "* Projects
+:PROPERTIES:
+:CUSTOM_ID: projects
+:ID: 2b9a1c4e-0b7d-4f43-9a55-2e0a3c8d1f00
+:END:
+** Reading list
+Some <<books>> and <<<radio target>>>.
+* Links
+[[*Reading list]] [[#projects]] [[books]] [[radio target]]
+[[id:2b9a1c4e-0b7d-4f43-9a55-2e0a3c8d1f00]] [[*Missing]]",
+        );
+
+        let mut resolver = Resolver::new();
+        resolver.add("index.org", &document);
+
+        let links = document.headings[1].links();
+        let resolved: Vec<Option<Location>> = links
+            .iter()
+            .map(|(_, link)| resolver.resolve("index.org", link))
+            .collect();
+
+        let location = |path: Vec<usize>, line: usize| {
+            Some(Location {
+                file: "index.org".to_string(),
+                path,
+                line,
+            })
+        };
+
+        assert_eq!(resolved[0], location(vec![0, 0], 6));
+        assert_eq!(resolved[1], location(vec![0], 1));
+        assert_eq!(resolved[2], location(vec![0, 0], 7));
+        assert_eq!(resolved[3], location(vec![0, 0], 7));
+        assert_eq!(resolved[4], location(vec![0], 1));
+        assert_eq!(resolved[5], None);
+
+        let diagnostics = resolver.broken_links();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].code, "broken-link");
+        assert_eq!(diagnostics[0].line, 10);
+    }
+
+    #[test]
+    fn resolve_file_links() {
+        let index = document::parse("See [[file:notes/meetings.org::*Standup]]");
+        let meetings = document::parse("* Retro\n* Standup");
+
+        let mut resolver = Resolver::new();
+        resolver.add("index.org", &index);
+        resolver.add("./notes/meetings.org", &meetings);
+
+        let (_, link) = &index.links()[0];
+
+        assert_eq!(
+            resolver.resolve("index.org", link),
+            Some(Location {
+                file: "notes/meetings.org".to_string(),
+                path: vec![1],
+                line: 2,
+            })
+        );
+        assert!(resolver.broken_links().is_empty());
+    }
+
+    #[test]
+    fn link_graph() {
+        let mut graph = LinkGraph::new();
+
+        graph.update(
+            "zettel/a.org",
+            document::parse("* Alpha\n:PROPERTIES:\n:ID: alpha\n:END:\nSee [[file:b.org::*Beta]]"),
+        );
+        graph.update(
+            "zettel/b.org",
+            document::parse("* Beta\nBack to [[id:alpha][Alpha]] and [[id:gamma]]"),
+        );
+
+        assert_eq!(graph.backlinks("zettel/a.org", &[0]).len(), 1);
+        assert_eq!(graph.backlinks("zettel/b.org", &[0]).len(), 1);
+        assert_eq!(
+            graph.file_backlinks("zettel/a.org")[0].source.file,
+            "zettel/b.org"
+        );
+
+        graph.update(
+            "zettel/c.org",
+            document::parse("* Gamma\n:PROPERTIES:\n:ID: gamma\n:END:"),
+        );
+
+        assert_eq!(graph.backlinks("zettel/c.org", &[0]).len(), 1);
+
+        graph.update("zettel/b.org", document::parse("* Intro\n* Beta"));
+
+        let backlinks = graph.backlinks("zettel/b.org", &[1]);
+
+        assert_eq!(backlinks.len(), 1);
+        assert_eq!(backlinks[0].source.line, 5);
+        assert!(graph.backlinks("zettel/a.org", &[0]).is_empty());
+
+        assert_eq!(
+            graph.to_dot(),
+            "digraph links {
+  \"zettel/a.org::0\" [label=\"Alpha\"];
+  \"zettel/b.org::1\" [label=\"Beta\"];
+  \"zettel/a.org::0\" -> \"zettel/b.org::1\";
+}
+"
+        );
+        assert!(graph
+            .to_json()
+            .starts_with("{\"nodes\":[{\"id\":\"zettel/a.org::0\""));
+    }
+
+    #[test]
+    fn heading_tags() {
+        let result = scanner::scan("* Task :work:urgent:\n:PROPERTIES:");
+
+        let expected = vec![
+            Token {
+                token_type: TokenType::Asterisk,
+                lexeme: "*".into(),
+                line: 1,
+            },
+            Token {
+                token_type: TokenType::String,
+                lexeme: "Task".into(),
+                line: 1,
+            },
+            Token {
+                token_type: TokenType::Tags,
+                lexeme: ":work:urgent:".into(),
+                line: 1,
+            },
+            Token {
+                token_type: TokenType::String,
+                lexeme: ":PROPERTIES:".into(),
+                line: 2,
+            },
+            Token {
+                token_type: TokenType::EOF,
+                lexeme: "".into(),
+                line: 2,
+            },
+        ];
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn tag_inheritance() {
+        let document = document::parse(
+            "#+FILETAGS: :project:
+* Futurice :work:crypt:
+** Planning :@office:
+*** TODO Slides :work:",
+        );
+
+        assert_eq!(document.local_tags(&[0, 0, 0]), vec!["work"]);
+        assert_eq!(
+            document.inherited_tags(&[0, 0, 0], &TagConfig::default()),
+            vec!["project", "crypt", "@office"]
+        );
+
+        let config = TagConfig {
+            inheritance: true,
+            exclude_from_inheritance: vec!["crypt".to_string()],
+        };
+
+        assert_eq!(
+            document.all_tags(&[0, 0], &config),
+            vec!["project", "work", "@office"]
+        );
+    }
+
+    #[test]
+    fn tag_groups() {
+        let document = document::parse(
+            "#+TAGS: { @work : @office @home } laptop(l)
+#+TAGS: [ Context : @work errand ] { urgent(u) later(x) }",
+        );
+
+        let definitions = document.tag_definitions();
+
+        assert_eq!(
+            definitions.groups[0],
+            TagGroup {
+                name: Some("@work".to_string()),
+                tags: vec!["@office".to_string(), "@home".to_string()],
+                exclusive: true,
+            }
+        );
+        assert_eq!(definitions.tags[3].key, Some('l'));
+        assert_eq!(
+            definitions.expand("Context"),
+            vec!["Context", "@work", "errand", "@office", "@home"]
+        );
+        assert_eq!(
+            definitions.conflicts(&["urgent".to_string(), "later".to_string()]),
+            vec![("urgent".to_string(), "later".to_string())]
+        );
+    }
+
+    #[test]
+    fn timestamps() {
+        let timestamp = Timestamp::parse("<2019-09-25 Wed 10:00-11:30 +1w -2d>").unwrap();
+
+        assert!(timestamp.active);
+        assert_eq!(timestamp.date, Date::new(2019, 9, 25).unwrap());
+        assert_eq!(timestamp.end_time.unwrap().minutes(), 690);
+        assert_eq!(timestamp.repeater.as_ref().unwrap().mark, "+");
+        assert_eq!(timestamp.warning.as_ref().unwrap().value, 2);
+        assert_eq!(timestamp.to_org(), "<2019-09-25 Wed 10:00-11:30 +1w -2d>");
+
+        assert_eq!(Date::new(2020, 2, 29).unwrap().add_days(1).weekday(), "Sun");
+        assert_eq!(Timestamp::parse("[2019-13-40 Foo]"), None);
+        assert_eq!(Timestamp::parse("<2019-09-25 Wed 24:59>"), None);
+    }
+
+    #[test]
+    fn match_query() {
+        let document = document::parse(
+            "#+TODO: TODO NEXT | DONE
+* Work :work:
+** NEXT [#A] Report :boss:
+** TODO [#A] Budget
+DEADLINE: <2019-09-20 Fri>
+:PROPERTIES:
+:Effort: 0:30
+:END:
+** TODO Slides
+SCHEDULED: <2019-09-30 Mon>
+:PROPERTIES:
+:Effort: 2:00
+:END:
+* Home",
+        );
+        let options = QueryOptions::new(Date::new(2019, 9, 25).unwrap());
+
+        let search = |match_string: &str| -> Vec<Vec<usize>> {
+            Query::parse(match_string)
+                .unwrap()
+                .search(&document, &options)
+                .into_iter()
+                .map(|m| m.path)
+                .collect()
+        };
+
+        assert_eq!(search("+work-boss+PRIORITY=\"A\""), vec![vec![0, 1]]);
+        assert_eq!(search("PRIORITY=\"A\"+TODO=\"NEXT\""), vec![vec![0, 0]]);
+        assert_eq!(
+            search("+work+Effort<\"1:00\"|LEVEL<2-work"),
+            vec![vec![0, 1], vec![1]]
+        );
+        assert_eq!(search("DEADLINE<\"<today>\""), vec![vec![0, 1]]);
+        assert_eq!(search("SCHEDULED>=\"<+3d>\"/TODO"), vec![vec![0, 2]]);
+        assert_eq!(search("{^bo}|ITEM={Sli}"), vec![vec![0, 0], vec![0, 2]]);
+        assert_eq!(search("work/-TODO"), vec![vec![0], vec![0, 0]]);
+        assert!(search("SCHEDULED<\"<\"").is_empty());
+        assert!(search("SCHEDULED<\"<é\"").is_empty());
+
+        let result = Query::parse("work").unwrap().search(&document, &options);
+
+        assert_eq!(result[1].titles, vec!["Work", "Report"]);
+        assert_eq!(Query::parse("Effort<").unwrap_err().position, 7);
+    }
+
+    #[test]
+    fn generic_keyword() {
+        let result = scanner::scan("#+OPTIONS: toc:2");
+
+        assert_eq!(result[0].token_type, TokenType::Keyword);
+        assert_eq!(result[0].lexeme, "#+OPTIONS:");
+    }
+
+    #[test]
+    fn document_settings() {
+        let document = document::parse(
+            "#+TITLE: Life
+#+title: Repo
+#+AUTHOR: jskjott
+#+EMAIL: jonathan@jskjott.com
+#+LANGUAGE: nb
+#+OPTIONS: toc:2 num:nil ^:{}
+#+OPTIONS: tags:nil
+#+STARTUP: overview hidestars
+#+EXCLUDE_TAGS: noexport draft
+* Heading",
+        );
+
+        let settings = document.settings();
+
+        assert_eq!(settings.title, Some("Life Repo".to_string()));
+        assert_eq!(settings.author, Some("jskjott".to_string()));
+        assert_eq!(settings.email, Some("jonathan@jskjott.com".to_string()));
+        assert_eq!(settings.language, Some("nb".to_string()));
+        assert_eq!(settings.options.level("toc"), Some(2));
+        assert_eq!(settings.options.flag("num"), Some(false));
+        assert_eq!(settings.options.flag("tags"), Some(false));
+        assert_eq!(settings.options.sub_superscripts(), SubSuperscripts::Braces);
+        assert_eq!(settings.startup, vec!["overview", "hidestars"]);
+        assert_eq!(settings.exclude_tags, vec!["noexport", "draft"]);
+        assert_eq!(settings.keywords.len(), 9);
+    }
+
+    #[test]
+    fn affiliated_keywords() {
+        let document = document::parse(
+            "* Results
+#+NAME: scores
+#+CAPTION[Short]: Scores per round
+#+ATTR_HTML: :class striped
+| a | 1 |
+| b | 2 |
+#+TBLFM: $2=$1
+
+#+NAME: dangling
+
+- [X] one
+- two
+  continued
+",
+        );
+
+        let elements = document.headings[0].elements();
+        let kinds: Vec<&Kind> = elements.iter().map(|element| &element.kind).collect();
+
+        assert_eq!(kinds[0], &Kind::Table);
+        assert_eq!(elements[0].name(), Some("scores"));
+        assert_eq!(
+            elements[0].affiliated[1].optional,
+            Some("Short".to_string())
+        );
+        assert_eq!(elements[0].attributes("html"), vec![":class striped"]);
+        assert_eq!(elements[0].body().len(), 3);
+        assert_eq!(elements[0].line, 2);
+        assert_eq!(
+            kinds[2],
+            &Kind::Keyword(Keyword {
+                key: "NAME".to_string(),
+                optional: None,
+                value: "dangling".to_string(),
+                line: 9,
+            })
+        );
+
+        match kinds[4] {
+            Kind::List(items) => {
+                assert_eq!(items.len(), 2);
+                assert_eq!(items[0].checkbox, Some('X'));
+                assert_eq!(items[1].lines, vec!["- two", "  continued"]);
+            }
+            kind => panic!("expected a list, got {:?}", kind),
+        }
+    }
+
+    struct Files(HashMap<&'static str, &'static str>);
+
+    impl FileLoader for Files {
+        fn load(&self, path: &str) -> Result<String, String> {
+            self.0
+                .get(path)
+                .map(|contents| contents.to_string())
+                .ok_or_else(|| "no such file".to_string())
+        }
+    }
+
+    #[test]
+    fn includes() {
+        let mut files = HashMap::new();
+        files.insert(
+            "book/chapter1.org",
+            "* Intro\nHello\n** Details\n* Outro\n#+INCLUDE: \"../setup/snippet.py\" src python",
+        );
+        files.insert("setup/snippet.py", "#+NAME: demo\nprint('hi')");
+        files.insert("book/theme.setup", "#+OPTIONS: toc:nil\nignored text");
+        files.insert(
+            "book/listing.org",
+            "* Top\n#+BEGIN_EXAMPLE\n* inside\n#+END_EXAMPLE\n** Sub",
+        );
+        let files = Files(files);
+
+        let result = include::expand(
+            "#+SETUPFILE: \"theme.setup\"
+* Handbook
+#+INCLUDE: \"chapter1.org::*Intro\"
+#+INCLUDE: \"chapter1.org\" :minlevel 3 :lines \"3-6\"
+#+INCLUDE: \"chapter1.org\" example :lines \"2-3\"",
+            "book/handbook.org",
+            &files,
+        )
+        .unwrap();
+
+        assert_eq!(
+            result,
+            "#+OPTIONS: toc:nil
+* Handbook
+** Intro
+Hello
+*** Details
+**** Details
+*** Outro
+#+BEGIN_SRC python
+,#+NAME: demo
+print('hi')
+#+END_SRC
+#+BEGIN_EXAMPLE
+Hello
+#+END_EXAMPLE
+"
+        );
+
+        // Lines in blocks keep their stars.
+        assert_eq!(
+            include::expand(
+                "#+INCLUDE: \"listing.org\" :minlevel 2",
+                "book/handbook.org",
+                &files
+            ),
+            Ok("** Top\n#+BEGIN_EXAMPLE\n* inside\n#+END_EXAMPLE\n*** Sub\n".to_string())
+        );
+    }
+
+    #[test]
+    fn include_errors() {
+        let mut files = HashMap::new();
+        files.insert("a.org", "#+INCLUDE: \"b.org\"");
+        files.insert("b.org", "#+INCLUDE: \"./a.org\"");
+        files.insert("c.org", "* Only");
+        let files = Files(files);
+
+        assert_eq!(
+            include::expand("#+INCLUDE: \"b.org\"", "a.org", &files),
+            Err(IncludeError::Cycle(vec![
+                "a.org".to_string(),
+                "b.org".to_string(),
+                "a.org".to_string()
+            ]))
+        );
+        assert_eq!(
+            include::expand("#+INCLUDE: \"c.org::*Missing\"", "x.org", &files)
+                .unwrap_err()
+                .to_string(),
+            "no *Missing in c.org"
+        );
+        assert_eq!(
+            include::expand("\n#+INCLUDE: c.org", "x.org", &files),
+            Err(IncludeError::Syntax {
+                file: "x.org".to_string(),
+                line: 2
+            })
+        );
+        assert_eq!(
+            include::expand("#+INCLUDE: \"c.org\" :lines \"3-0\"", "x.org", &files),
+            Err(IncludeError::Lines {
+                file: "c.org".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn macro_reference() {
+        let result = scanner::scan("Version {{{version}}} and {{{kbd(C-c\\, C-c, x)}}}");
+
+        assert_eq!(result[1].token_type, TokenType::Macro);
+        assert_eq!(result[1].lexeme, "{{{version}}}");
+        assert_eq!(result[3].token_type, TokenType::Macro);
+
+        assert_eq!(
+            MacroReference::parse(&result[3].lexeme),
+            Some(MacroReference {
+                name: "kbd".to_string(),
+                arguments: vec!["C-c, C-c".to_string(), " x".to_string()],
+            })
+        );
+        assert_eq!(MacroReference::parse("{{{version}}"), None);
+    }
+
+    #[test]
+    fn expand_macros() {
+        let document = document::parse(
+            "#+TITLE: Release notes
+#+MACRO: version 2.1
+#+MACRO: greet Hello, $1 and $2!
+Notes for {{{title}}} {{{version}}}
+* Changes {{{n}}}
+:PROPERTIES:
+:OWNER: jskjott
+:CUSTOM_ID: changes
+:END:
+{{{greet(you\\, too,me)}}} by {{{property(OWNER)}}}
+#+BEGIN_SRC org
+{{{version}}}
+#+END_SRC
+* Fixes {{{n}}}
+Owner of changes: {{{property(OWNER,#changes)}}}",
+        );
+
+        let result = document.expand_macros().unwrap().to_org();
+
+        assert_eq!(
+            result,
+            "#+TITLE: Release notes
+#+MACRO: version 2.1
+#+MACRO: greet Hello, $1 and $2!
+Notes for Release notes 2.1
+* Changes 1
+:PROPERTIES:
+:OWNER: jskjott
+:CUSTOM_ID: changes
+:END:
+Hello, you, too and me! by jskjott
+#+BEGIN_SRC org
+{{{version}}}
+#+END_SRC
+* Fixes 2
+Owner of changes: jskjott
+"
+        );
+
+        let undefined = document::parse("* A\nSee {{{missing(1)}}}").expand_macros();
+        assert_eq!(
+            undefined,
+            Err(MacroError::Undefined {
+                name: "missing".to_string(),
+                line: 2,
+            })
+        );
+
+        let recursive = document::parse("#+MACRO: loop {{{loop}}}\n{{{loop}}}").expand_macros();
+        assert!(matches!(recursive, Err(MacroError::Recursive { .. })));
+    }
+
+    #[test]
+    fn visitor() {
+        #[derive(Default)]
+        struct Counter {
+            words: usize,
+            headings: Vec<Vec<usize>>,
+            items: usize,
+            blocks: usize,
+            links: Vec<String>,
+        }
+
+        impl Visitor for Counter {
+            fn enter_heading(&mut self, path: &[usize], _heading: &document::Heading) {
+                self.headings.push(path.to_vec());
+            }
+
+            fn enter_item(&mut self, _item: &crate::element::Item) {
+                self.items += 1;
+            }
+
+            fn enter_block(&mut self, _element: &crate::element::Element) {
+                self.blocks += 1;
+            }
+
+            fn visit_token(&mut self, token: &Token) {
+                match token.token_type {
+                    TokenType::Link => self.links.push(token.lexeme.to_string()),
+                    _ => self.words += 1,
+                }
+            }
+        }
+
+        let source = "Intro text here
+* Eat *hotdogs*
+See [[https://orgmode.org][the site]]
+- one
+- two words
+** Nested
+#+BEGIN_SRC sh
+not counted
+#+END_SRC
+";
+
+        let mut counter = Counter::default();
+        visit::walk(&document::parse(source), &mut counter);
+
+        assert_eq!(counter.headings, vec![vec![0], vec![0, 0]]);
+        assert_eq!(counter.items, 2);
+        assert_eq!(counter.blocks, 1);
+        assert_eq!(counter.links, vec!["[[https://orgmode.org][the site]]"]);
+        assert_eq!(counter.words, 12);
+
+        struct Shout;
+
+        impl VisitorMut for Shout {
+            fn visit_token(&mut self, token: &mut Token) {
+                if token.token_type == TokenType::Bold {
+                    token.lexeme = token.lexeme.to_uppercase().into();
+                }
+            }
+        }
+
+        let mut document = document::parse("* Eat *hotdogs*\n- with  *mustard* please\n");
+        visit::walk_mut(&mut document, &mut Shout);
+
+        assert_eq!(
+            document.to_org(),
+            "* Eat *HOTDOGS*\n- with  *MUSTARD* please\n"
+        );
+    }
+
+    #[test]
+    fn edit_document() {
+        let mut document = document::parse(
+            "#+TODO: TODO NEXT | DONE
+* Inbox
+** TODO Call mum :phone:
+SCHEDULED: <2019-09-25 Wed>
+Before dinner.
+** Buy milk
+* Projects
+",
+        );
+
+        let path = document
+            .insert_subtree(
+                &[1],
+                0,
+                document::parse("*** Garden\n**** Seeds").headings.remove(0),
+            )
+            .unwrap();
+        assert_eq!(path, vec![1, 0]);
+
+        assert_eq!(document.move_subtree(&[0, 1], &[1], 1), Ok(vec![1, 1]));
+        document.set_keyword(&[0, 0], Some("NEXT")).unwrap();
+        document.set_priority(&[0, 0], Some('A')).unwrap();
+        document.add_tag(&[0, 0], "family").unwrap();
+        document.remove_tag(&[0, 0], "phone").unwrap();
+        document
+            .set_deadline(&[0, 0], Timestamp::parse("<2019-09-27 Fri>"))
+            .unwrap();
+        document.set_property(&[0, 0], "EFFORT", "0:15").unwrap();
+        document.set_property(&[0, 0], "effort", "0:30").unwrap();
+        document
+            .add_note(
+                &[0, 0],
+                "Left a message.",
+                &Timestamp::parse("<2019-09-25 Wed 18:05>").unwrap(),
+            )
+            .unwrap();
+        document
+            .set_scheduled(&[1, 1], Timestamp::parse("<2019-09-28 Sat>"))
+            .unwrap();
+
+        assert_eq!(
+            document.to_org(),
+            "#+TODO: TODO NEXT | DONE
+* Inbox
+** NEXT [#A] Call mum :family:
+DEADLINE: <2019-09-27 Fri> SCHEDULED: <2019-09-25 Wed>
+:PROPERTIES:
+:EFFORT: 0:30
+:END:
+:LOGBOOK:
+- Note taken on [2019-09-25 Wed 18:05] \\\\
+  Left a message.
+:END:
+Before dinner.
+* Projects
+** Garden
+*** Seeds
+** Buy milk
+SCHEDULED: <2019-09-28 Sat>
+"
+        );
+        assert_eq!(document.heading(&[1, 1]).unwrap().line, 16);
+
+        // Promoting Garden makes the later Buy milk its child.
+        assert_eq!(document.promote(&[1, 0]), Ok(vec![2]));
+        assert_eq!(document.heading(&[2, 1]).unwrap().title, "Buy milk");
+        assert_eq!(document.demote(&[2]), Ok(vec![1, 0]));
+        assert_eq!(document.heading(&[1, 0, 1]).unwrap().level, 3);
+
+        let removed = document.remove_subtree(&[1]).unwrap();
+        assert_eq!(removed.title, "Projects");
+        assert_eq!(document.headings.len(), 1);
+    }
+
+    #[test]
+    fn edit_errors() {
+        let mut document = document::parse("* A\n** B\n");
+        let before = document.clone();
+
+        assert_eq!(
+            document.set_keyword(&[0], Some("WAITING")),
+            Err(EditError::UnknownKeyword("WAITING".to_string()))
+        );
+        assert_eq!(
+            document.set_priority(&[0], Some('a')),
+            Err(EditError::InvalidPriority('a'))
+        );
+        assert_eq!(
+            document.add_tag(&[0], "two words"),
+            Err(EditError::InvalidTag("two words".to_string()))
+        );
+        assert_eq!(
+            document.move_subtree(&[0], &[0, 0], 0),
+            Err(EditError::MoveIntoItself(vec![0]))
+        );
+        assert_eq!(
+            document.promote(&[0]),
+            Err(EditError::InvalidLevel(vec![0]))
+        );
+        assert_eq!(
+            document.remove_subtree(&[3]),
+            Err(EditError::NoSuchHeading(vec![3]))
+        );
+        assert_eq!(
+            document.insert_subtree(&[0], 5, document::Heading::new("C")),
+            Err(EditError::InvalidIndex(5))
+        );
+        assert_eq!(
+            document.insert_subtree(&[], 0, document::Heading::new("C\nD")),
+            Err(EditError::InvalidText("C\nD".to_string()))
+        );
+        assert_eq!(document, before);
+    }
+
+    #[test]
+    fn incremental_reparse() {
+        let mut buffer = Buffer::new(
+            "#+TITLE: Notes
+* One
+text
+** One.a
+* Two
+#+BEGIN_SRC sh
+echo
+#+END_SRC
+* Three
+",
+        );
+
+        let edit = |buffer: &mut Buffer, at: &str, len: usize, text: &str| {
+            let start = buffer.text().find(at).unwrap();
+            let update = buffer
+                .edit(&TextEdit {
+                    start,
+                    end: start + len,
+                    text: text.to_string(),
+                })
+                .unwrap();
+
+            assert_eq!(buffer.document(), &document::parse(buffer.text()));
+            update
+        };
+
+        assert_eq!(
+            edit(&mut buffer, "text", 4, "more\nlines"),
+            Update {
+                changed: vec![vec![0], vec![0, 0]],
+                ..Update::default()
+            }
+        );
+        assert_eq!(
+            edit(&mut buffer, "One.a", 5, "Child"),
+            Update {
+                changed: vec![vec![0, 0]],
+                ..Update::default()
+            }
+        );
+        assert_eq!(buffer.document().heading(&[2]).unwrap().line, 10);
+
+        // A new top-level heading changes the outline around it.
+        assert!(edit(&mut buffer, "Child", 0, "\n* New\n").full);
+        assert!(edit(&mut buffer, "Notes", 5, "Journal").section);
+        assert!(edit(&mut buffer, "#+TITLE", 0, "#+TODO: NEXT | DONE\n").full);
+        assert_eq!(buffer.document().todo_keywords, vec!["NEXT"]);
+
+        let end = buffer.text().len();
+        let update = buffer
+            .edit(&TextEdit {
+                start: end,
+                end,
+                text: "** NEXT Last".to_string(),
+            })
+            .unwrap();
+        assert_eq!(update.changed, vec![vec![3], vec![3, 0]]);
+        assert_eq!(buffer.document(), &document::parse(buffer.text()));
+
+        // An unclosed block swallows the headings after it.
+        assert!(edit(&mut buffer, "#+END_SRC", 9, "").full);
+
+        // A blank line at the end of a subtree stays in its section.
+        let mut buffer = Buffer::new("* A\ntext\n* B\n");
+        assert_eq!(edit(&mut buffer, "\n* B", 0, "\n").changed, vec![vec![0]]);
+        assert_eq!(buffer.document().to_org(), "* A\ntext\n\n* B\n");
+
+        // TODO settings apply to the headings before them as well.
+        let mut buffer = Buffer::new("* NEXT A\n* B\n");
+        assert!(edit(&mut buffer, "* B", 0, "#+TODO: NEXT\n").full);
+        assert_eq!(
+            buffer.document().headings[0].keyword.as_deref(),
+            Some("NEXT")
+        );
+
+        assert_eq!(
+            buffer.edit(&TextEdit {
+                start: 5,
+                end: 2,
+                text: String::new(),
+            }),
+            Err(EditError::InvalidRange(5, 2))
+        );
+    }
+
+    #[test]
+    fn scan_unicode() {
+        let result = scanner::scan("* Café ☕ :tag:\n<<ziel>> über");
+
+        let lexemes: Vec<&str> = result.iter().map(|token| &*token.lexeme).collect();
+        assert_eq!(
+            lexemes,
+            vec!["*", "Café", "☕", ":tag:", "<<ziel>>", "über", ""]
+        );
+        assert_eq!(result[3].token_type, TokenType::Tags);
+    }
+
+    #[test]
+    fn unicode_emphasis() {
+        let lexemes = |source: &str| -> Vec<(TokenType, String)> {
+            let mut tokens = scanner::scan(source);
+            tokens.pop();
+            tokens
+                .into_iter()
+                .map(|token| (token.token_type, token.lexeme.to_string()))
+                .collect()
+        };
+
+        assert_eq!(
+            lexemes("*** Møte /ære/ _ünder_"),
+            vec![
+                (TokenType::Asterisk, "***".to_string()),
+                (TokenType::String, "Møte".to_string()),
+                (TokenType::Italic, "/ære/".to_string()),
+                (TokenType::Underline, "_ünder_".to_string()),
+            ]
+        );
+        assert_eq!(
+            lexemes("日本語*強調*です、+削除+。"),
+            vec![
+                (TokenType::String, "日本語".to_string()),
+                (TokenType::Bold, "*強調*".to_string()),
+                (TokenType::String, "です".to_string()),
+                (TokenType::String, "、".to_string()),
+                (TokenType::Strikethrough, "+削除+".to_string()),
+                (TokenType::String, "。".to_string()),
+            ]
+        );
+        assert_eq!(
+            lexemes("(*bold text*) snake_case_name 🎉"),
+            vec![
+                (TokenType::String, "(".to_string()),
+                (TokenType::Bold, "*bold text*".to_string()),
+                (TokenType::String, ")".to_string()),
+                (TokenType::String, "snake_case_name".to_string()),
+                (TokenType::String, "🎉".to_string()),
+            ]
+        );
+        assert_eq!(
+            lexemes("[unclosed <2019-09-25"),
+            vec![
+                (TokenType::String, "[".to_string()),
+                (TokenType::String, "unclosed".to_string()),
+                (TokenType::String, "<2019-09-25".to_string()),
+            ]
+        );
+
+        let document = document::parse("* Møte 会議 :ärende:\n** 🎉 Fest");
+        assert_eq!(document.headings[0].title, "Møte 会議");
+        assert_eq!(document.headings[0].tags, vec!["ärende"]);
+        assert_eq!(document.headings[0].children[0].title, "🎉 Fest");
+    }
+
+    #[test]
+    fn outline_only() {
+        let source = "#+TODO: NEXT | DONE
+* NEXT [#A] One :a:
+text
+#+BEGIN_SRC org
+* not a heading
+#+END_SRC
+** Child
+more
+* Two
+";
+        let outline = outline::outline(source);
+        let document = document::parse(source);
+
+        assert_eq!(outline.todo_keywords, vec!["NEXT"]);
+        assert_eq!(outline.headings.len(), 2);
+
+        let one = &outline.headings[0];
+        assert_eq!(one.keyword.as_deref(), Some("NEXT"));
+        assert_eq!(one.priority, Some('A'));
+        assert_eq!(one.tags, vec!["a"]);
+        assert_eq!(one.line, 2);
+        assert_eq!(&source[one.range.clone()], "* NEXT [#A] One :a:\ntext\n#+BEGIN_SRC org\n* not a heading\n#+END_SRC\n** Child\nmore\n");
+        assert_eq!(
+            &source[one.section.clone()],
+            "text\n#+BEGIN_SRC org\n* not a heading\n#+END_SRC\n"
+        );
+
+        let child = outline.heading(&[0, 0]).unwrap();
+        assert_eq!(child.title, "Child");
+        assert_eq!(&source[child.section.clone()], "more\n");
+        assert_eq!(
+            outline.heading(&[1]).unwrap().section,
+            source.len()..source.len()
+        );
+
+        for (path, heading) in document.all_headings() {
+            assert_eq!(outline.parse_heading(source, &path).as_ref(), Some(heading));
+        }
+        assert_eq!(outline.parse_heading(source, &[2]), None);
+
+        // TODO settings after a heading apply to it, and only an `#+END_`
+        // of its own name ends a block.
+        let source = "* WAIT [#B] One :a:\n#+begin_quote\n#+END_SRC\n* not a heading\n#+END_QUOTE\n#+seq_todo: WAIT | DONE\n";
+        let outline = outline::outline(source);
+        assert_eq!(outline.headings.len(), 1);
+        assert_eq!(outline.headings[0].keyword.as_deref(), Some("WAIT"));
+        assert_eq!(outline.headings[0].title, "One");
+        assert_eq!(outline.todo_keywords, document::parse(source).todo_keywords);
+    }
+
+    #[test]
+    fn recover_with_diagnostics() {
+        let source = "* One
+:LOGBOOK:
+CLOCK: [2019-09-21 Sat 17:11]
+* Two [2019-13-40 Foo]
+#+BEGIN_SRC sh
+echo
+* Three
+:PROPERTIES:
+:ID: x
+:END:
+bell\u{7} [[https://orgmode.org";
+
+        let (document, diagnostics) = document::parse_with_diagnostics(source);
+
+        // The open block doesn't swallow the headings after it.
+        let titles: Vec<String> = document
+            .all_headings()
+            .iter()
+            .map(|(_, heading)| heading.title.clone())
+            .collect();
+        assert_eq!(titles, vec!["One", "Two [2019-13-40 Foo]", "Three"]);
+        assert_eq!(outline::outline(source).headings.len(), 3);
+
+        let codes: Vec<(&str, usize)> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code.as_str(), diagnostic.line))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("unterminated-drawer", 2),
+                ("malformed-timestamp", 4),
+                ("unclosed-block", 5),
+                ("stray-character", 11),
+                ("unclosed-link", 11),
+            ]
+        );
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            &source[diagnostics[1].span.clone().unwrap()],
+            "[2019-13-40 Foo]"
+        );
+        assert_eq!(&source[diagnostics[3].span.clone().unwrap()], "\u{7}");
+
+        let links: Vec<usize> = crate::diagnostic::check("[[a\n]] [[b\n\n]]")
+            .iter()
+            .map(|diagnostic| diagnostic.line)
+            .collect();
+        assert_eq!(links, vec![2]);
+
+        // Only an `#+END_` line with its name closes a block, so an open
+        // block doesn't take the end of the next one.
+        let source = "#+BEGIN_SRC sh\n* A\n#+begin_quote\n* quoted\n#+end_QUOTE\n* B";
+        let (document, diagnostics) = document::parse_with_diagnostics(source);
+        let titles: Vec<String> = document
+            .all_headings()
+            .iter()
+            .map(|(_, heading)| heading.title.clone())
+            .collect();
+        assert_eq!(titles, vec!["A", "B"]);
+        assert_eq!(document::unclosed_blocks(source, 1), vec![1]);
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| (diagnostic.code.as_str(), diagnostic.line))
+                .collect::<Vec<_>>(),
+            vec![("unclosed-block", 1)]
+        );
+
+        // Brackets running off the end of the input are just text.
+        for source in ["[", "[[a", "a [b", "<2019-09-21", "* a :b"] {
+            document::parse_with_diagnostics(source);
+            scanner::scan(source);
+        }
+    }
+
+    #[test]
+    fn lint_rules() {
+        let source = "* One\t
+:PROPERTIES:
+:CUSTOM_ID: a
+:END:
+SCHEDULED: <2019-09-25 Wed>
+:END:
+:LOGBOOK:
+CLOCK: [2019-09-21 Sat 17:11]--[2019-09-21 Sat 18:24] =>  1:10
+CLOCK: [2019-09-21 Sat 17:11]
+:END:
+* Two
+:PROPERTIES:
+:CUSTOM_ID: a
+:END:
+#+BEGIN_SRC brainfudge
+#+END_SRC
+#+BEGIN_LATEX
+#+END_LATEX
+:NOTES:
+| a | b |
+#+TBLFM: $3=$1+$2
+[[*Three]]
+";
+
+        let diagnostics = lint::lint(source, &LintConfig::default());
+        let codes: Vec<(&str, usize)> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.code.as_str(), diagnostic.line))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("trailing-whitespace", 1),
+                ("misplaced-planning", 5),
+                ("orphaned-end", 6),
+                ("invalid-clock", 8),
+                ("duplicate-custom-id", 13),
+                ("unknown-src-language", 15),
+                ("deprecated-block", 17),
+                ("missing-drawer-end", 19),
+                ("invalid-table-formula", 21),
+                ("broken-link", 22),
+            ]
+        );
+
+        assert_eq!(&source[diagnostics[0].span.clone().unwrap()], "\t");
+        assert_eq!(&source[diagnostics[3].span.clone().unwrap()], "1:10");
+        assert_eq!(diagnostics[3].message, "CLOCK duration 1:10 should be 1:13");
+        assert_eq!(diagnostics[4].severity, Severity::Error);
+
+        let config = LintConfig {
+            disabled: vec![Rule::TrailingWhitespace, Rule::InvalidClock],
+            languages: vec!["brainfudge".to_string()],
+        };
+        assert_eq!(lint::lint(source, &config).len(), diagnostics.len() - 3);
+        assert_eq!(Rule::from_code("orphaned-end"), Some(Rule::OrphanedEnd));
+
+        // `$0` is the current column, `@<` and `@>` count from the ends and
+        // `@-1` is relative; only references past the table are wrong.
+        let source = "| a | b |\n|---+---|\n| 1 | 2 |\n#+TBLFM: $0=$1+$2::@>$2=@<$1::@2$1=@-1$+1::@>>>$1=1\n";
+        let diagnostics = lint::lint(source, &LintConfig::default());
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["formula refers to row >>> but the table has 2"]
+        );
+    }
+
+    #[test]
+    fn format_document() {
+        let source = "#+TITLE: Test
+
+
+* TODO [#A] Heading <2019-09-25 Mon> :work:urgent:
+SCHEDULED: <2019-09-25 Mon>
+:PROPERTIES:
+:Zeta: 1
+:alpha: 2
+:END:
+:LOGBOOK:
+CLOCK: [2019-09-21 Sat 17:11]--[2019-09-21 Sat 18:24] =>  0:10
+:END:
++ first
+  continued
+  * nested
+    more
++ second
+    1) one
+    3) two
+
+
+
+** Child :x:
+| name | n |
+|-+-|
+| apple | 10 |
+| b | 2 |
+#+TBLFM: $2=1
+#+BEGIN_SRC sh
++ not a list
+#+END_SRC
+";
+
+        let expected = "#+TITLE: Test
+* TODO [#A] Heading <2019-09-25 Wed>                            :work:urgent:
+SCHEDULED: <2019-09-25 Wed>
+:PROPERTIES:
+:alpha: 2
+:Zeta: 1
+:END:
+:LOGBOOK:
+CLOCK: [2019-09-21 Sat 17:11]--[2019-09-21 Sat 18:24] =>  1:13
+:END:
+- first
+  continued
+  - nested
+    more
+- second
+  1) one
+  2) two
+** Child                                                                  :x:
+| name  |  n |
+|-------+----|
+| apple | 10 |
+| b     |  2 |
+#+TBLFM: $2=1
+#+BEGIN_SRC sh
++ not a list
+#+END_SRC
+";
+
+        let formatted = format::format(source, &FormatConfig::default());
+        assert_eq!(formatted, expected);
+        assert_eq!(
+            format::format(&formatted, &FormatConfig::default()),
+            formatted
+        );
+
+        let config = FormatConfig {
+            tags_column: 20,
+            blank_lines: 1,
+        };
+        assert_eq!(
+            format::format("* A :a:\n** B\ntext\n\n\n* C\n", &config),
+            "* A                 :a:\n\n** B\ntext\n\n* C\n"
+        );
+    }
+
+    #[test]
+    fn streaming() {
+        let source = "intro
+* TODO One :a:
+text
+** Child
+#+BEGIN_SRC org
+* not a heading
+#+END_SRC
+* Two
+";
+
+        let mut tokens = scanner::tokens(source);
+        assert_eq!(
+            tokens.next(),
+            Some(Token {
+                token_type: TokenType::String,
+                lexeme: "intro".into(),
+                line: 1,
+            })
+        );
+        assert_eq!(tokens.count(), scanner::scan(source).len() - 1);
+
+        let tree = parser::parse(scanner::tokens(source));
+        assert_eq!(tree.data[0].lexeme, "intro");
+        let one = &tree.children["One"];
+        assert_eq!(one.children["text"].data.len(), 1);
+        assert_eq!(one.children["Child"].children.len(), 1);
+        assert!(tree.children.contains_key("Two"));
+
+        let outline: Vec<String> = events::events(source)
+            .map(|event| match event {
+                Event::Start(heading) => format!("start {}", heading.title),
+                Event::Text { line, text } => format!("{}: {}", line, text),
+                Event::End(heading) => format!("end {}", heading.title),
+            })
+            .collect();
+        assert_eq!(
+            outline,
+            vec![
+                "1: intro",
+                "start One",
+                "3: text",
+                "start Child",
+                "5: #+BEGIN_SRC org",
+                "6: * not a heading",
+                "7: #+END_SRC",
+                "end Child",
+                "end One",
+                "start Two",
+                "end Two",
+            ]
+        );
+
+        let mut events = events::events(source);
+        match events.nth(1) {
+            Some(Event::Start(heading)) => {
+                assert_eq!(heading.keyword.as_deref(), Some("TODO"));
+                assert_eq!(heading.tags, vec!["a"]);
+            }
+            event => panic!("{:?}", event),
+        }
+        assert_eq!(events.open().len(), 1);
+
+        // The `#+END_` of another block doesn't end the one open.
+        let source = "#+BEGIN_SRC org\n#+END_EXAMPLE\n* not a heading\n#+END_SRC\n* A";
+        let titles: Vec<String> = events::events(source)
+            .filter_map(|event| match event {
+                Event::Start(heading) => Some(heading.title),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(titles, vec!["A"]);
+    }
+
+    #[test]
+    fn language_server() {
+        let mut server = Server::new(Date::new(2019, 9, 23).unwrap());
+        let uri = "file:///notes/a.org";
+        let source = "#+TAGS: home
+* TODO Plan :work:
+SCHEDULED: <2019-09-25 Wed>
+:PROPERTIES:
+:ID: plan
+:END:
+See [[*Plan]] and [fn:1].
+** DONE Child
+#+BEGIN_SRC sh
+ls
+#+END_SRC
+* Next
+:LOGBOOK:
+[fn:1] A note.
+";
+
+        let reply = server
+            .handle(&json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }));
+        assert_eq!(reply[0]["result"]["capabilities"]["textDocumentSync"], 1);
+
+        let reply = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "languageId": "org", "version": 1, "text": source } },
+        }));
+        let diagnostics = &reply[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["code"], "unterminated-drawer");
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 12);
+
+        let request = |method: &str, params: Value| {
+            let mut params = params;
+            params["textDocument"] = json!({ "uri": uri });
+            json!({ "jsonrpc": "2.0", "id": 2, "method": method, "params": params })
+        };
+        let at = |line: usize, character: usize| json!({ "position": { "line": line, "character": character } });
+
+        let symbols =
+            &server.handle(&request("textDocument/documentSymbol", json!({})))[0]["result"];
+        assert_eq!(symbols[0]["name"], "Plan");
+        assert_eq!(symbols[0]["detail"], "TODO :work:");
+        assert_eq!(symbols[0]["range"]["end"]["line"], 10);
+        assert_eq!(symbols[0]["children"][0]["name"], "Child");
+        assert_eq!(symbols[1]["name"], "Next");
+
+        let folds = &server.handle(&request("textDocument/foldingRange", json!({})))[0]["result"];
+        let folds: Vec<(u64, u64)> = folds
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|fold| {
+                (
+                    fold["startLine"].as_u64().unwrap(),
+                    fold["endLine"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(folds, vec![(1, 10), (3, 5), (7, 10), (8, 10), (11, 13)]);
+
+        let definition = &server.handle(&request("textDocument/definition", at(6, 8)))[0]["result"];
+        assert_eq!(definition["uri"], uri);
+        assert_eq!(definition["range"]["start"]["line"], 1);
+        let definition =
+            &server.handle(&request("textDocument/definition", at(6, 20)))[0]["result"];
+        assert_eq!(definition["range"]["start"]["line"], 13);
+        let definition = &server.handle(&request("textDocument/definition", at(6, 0)))[0]["result"];
+        assert!(definition.is_null());
+
+        // Escapes and Windows drives in URIs still find the file linked to.
+        for (uri, text) in [
+            ("file:///C:/My%20Notes/b%C3%A6r.org", "* Target"),
+            (
+                "file:///c%3A/My%20Notes/main.org",
+                "[[file:bær.org::*Target]]",
+            ),
+        ] {
+            server.handle(&json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "org", "version": 1, "text": text } },
+            }));
+        }
+        let params = json!({
+            "textDocument": { "uri": "file:///c%3A/My%20Notes/main.org" },
+            "position": { "line": 0, "character": 4 },
+        });
+        let reply = server.handle(
+            &json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/definition", "params": params }),
+        );
+        assert_eq!(
+            reply[0]["result"]["uri"],
+            "file:///C:/My%20Notes/b%C3%A6r.org"
+        );
+
+        let labels = |reply: Vec<Value>| -> Vec<String> {
+            reply[0]["result"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["label"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(
+            labels(server.handle(&request("textDocument/completion", at(7, 3)))),
+            vec!["TODO", "DONE"]
+        );
+        assert_eq!(
+            labels(server.handle(&request("textDocument/completion", at(1, 16)))),
+            vec!["home", "work"]
+        );
+        let keywords = labels(server.handle(&request("textDocument/completion", at(0, 2))));
+        assert!(keywords.contains(&"TITLE".to_string()));
+        assert!(labels(server.handle(&request("textDocument/completion", at(6, 3)))).is_empty());
+
+        let hover = &server.handle(&request("textDocument/hover", at(2, 15)))[0]["result"];
+        assert_eq!(hover["contents"]["value"], "Wed 2019-09-25, in 2 days");
+        assert!(server.handle(&request("textDocument/hover", at(2, 3)))[0]["result"].is_null());
+
+        let reply = server.handle(
+            &json!({ "jsonrpc": "2.0", "id": 3, "method": "workspace/symbol", "params": {} }),
+        );
+        assert_eq!(reply[0]["error"]["code"], -32601);
+
+        server.handle(&json!({ "jsonrpc": "2.0", "id": 4, "method": "shutdown" }));
+        assert_eq!(server.exit_code(), None);
+        server.handle(&json!({ "jsonrpc": "2.0", "method": "exit" }));
+        assert_eq!(server.exit_code(), Some(0));
+    }
+
+    #[test]
+    fn semantic_tokens() {
+        let source = "#+TODO: NEXT | DONE
+* NEXT [#A] Write *the* report :work:home:
+  SCHEDULED: <2019-09-25 Wed>
+:PROPERTIES:
+:ID: 42
+:END:
+Some =code= and ~more~ with a [[https://orgmode.org][link]], [2019-09-26 Thu] and {{{m(x)}}}.
+# a comment
+#+BEGIN_SRC org
+* not a heading /or italic/
+#+END_SRC
+** DONE Done
+";
+
+        let tokens = highlight::highlight(source);
+        let described: Vec<(SemanticType, &str, Vec<Modifier>, usize)> = tokens
+            .iter()
+            .map(|token| {
+                (
+                    token.kind,
+                    &source[token.span.clone()],
+                    token.modifiers.clone(),
+                    token.level,
+                )
+            })
+            .collect();
+
+        use Modifier::*;
+        use SemanticType::*;
+        assert_eq!(
+            described,
+            vec![
+                (Keyword, "#+TODO:", vec![], 0),
+                (Stars, "*", vec![], 1),
+                (TodoKeyword, "NEXT", vec![], 1),
+                (Priority, "[#A]", vec![], 1),
+                (Headline, "Write", vec![], 1),
+                (Emphasis, "*the*", vec![Bold], 1),
+                (Headline, "report", vec![], 1),
+                (Tag, "work", vec![], 1),
+                (Tag, "home", vec![], 1),
+                (Planning, "SCHEDULED:", vec![], 0),
+                (Timestamp, "<2019-09-25 Wed>", vec![Active], 0),
+                (Drawer, ":PROPERTIES:", vec![], 0),
+                (Property, ":ID:", vec![], 0),
+                (Drawer, ":END:", vec![], 0),
+                (Verbatim, "=code=", vec![], 0),
+                (Verbatim, "~more~", vec![Code], 0),
+                (Link, "[[https://orgmode.org][link]]", vec![], 0),
+                (Timestamp, "[2019-09-26 Thu]", vec![Inactive], 0),
+                (Macro, "{{{m(x)}}}", vec![], 0),
+                (Comment, "# a comment", vec![], 0),
+                (BlockDelimiter, "#+BEGIN_SRC org", vec![], 0),
+                (BlockDelimiter, "#+END_SRC", vec![], 0),
+                (Stars, "**", vec![], 2),
+                (TodoKeyword, "DONE", vec![Done], 2),
+                (Headline, "Done", vec![], 2),
+            ]
+        );
+        assert_eq!(tokens[9].line, 3);
+
+        // A range gets the tokens that overlap it, with state carried over
+        // from the lines before.
+        let start = source.find("* not").unwrap();
+        let end = source.find("** DONE").unwrap() + 4;
+        let kinds: Vec<SemanticType> = highlight::highlight_range(source, start..end)
+            .iter()
+            .map(|token| token.kind)
+            .collect();
+        assert_eq!(kinds, vec![BlockDelimiter, Stars, TodoKeyword]);
+
+        // Another block's `#+END_` line is part of the block.
+        let source = "#+BEGIN_EXAMPLE\n#+END_SRC\n* no\n#+end_example\n* Yes";
+        let kinds: Vec<(SemanticType, &str)> = highlight::highlight(source)
+            .iter()
+            .map(|token| (token.kind, &source[token.span.clone()]))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (BlockDelimiter, "#+BEGIN_EXAMPLE"),
+                (BlockDelimiter, "#+end_example"),
+                (Stars, "*"),
+                (Headline, "Yes"),
+            ]
+        );
+    }
+
+    #[test]
+    fn icalendar_export() {
+        let source = "#+EXCLUDE_TAGS: private
+* Meeting <2019-09-25 Wed 10:00-11:30 +1w -2d> :team:
+Talk about this, that; and more.
+* TODO [#A] Report :work:
+DEADLINE: <2019-09-27 Fri> SCHEDULED: <2019-09-23 Mon>
+:PROPERTIES:
+:ID: 1234-abcd
+:END:
+* Holiday
+<2019-10-01 Tue>--<2019-10-03 Thu>
+* Diary :private:
+<2019-09-25 Wed>
+* DONE Filed
+DEADLINE: <2019-09-20 Fri 09:00>
+";
+        let document = document::parse(source);
+        let config = IcalConfig {
+            include_todo: IncludeTodo::All,
+            use_deadline: vec![Usage::EventIfTodo, Usage::Todo],
+            alarm_time: 10,
+            ..IcalConfig::default()
+        };
+        let calendar = icalendar::export(&[("notes/plans.org", &document)], &config, 1_569_000_000);
+
+        let lines: Vec<&str> = calendar.split("\r\n").collect();
+        let events: Vec<&[&str]> = lines
+            .split(|line| line.starts_with("BEGIN:V") && *line != "BEGIN:VALARM")
+            .skip(2)
+            .collect();
+        assert_eq!(events.len(), 6);
+
+        assert_eq!(
+            &events[0][1..3],
+            ["DTSTAMP:20190920T172000Z", "DTSTART:20190925T100000"]
+        );
+        assert!(events[0][0].starts_with("UID:TS-"));
+        assert_eq!(
+            &events[0][3..],
+            [
+                "DTEND:20190925T113000",
+                "RRULE:FREQ=WEEKLY;INTERVAL=1",
+                "SUMMARY:Meeting",
+                r"DESCRIPTION:Talk about this\, that\; and more.",
+                "CATEGORIES:team,plans",
+                "BEGIN:VALARM",
+                "ACTION:DISPLAY",
+                "DESCRIPTION:Meeting",
+                "TRIGGER:-P2D",
+                "END:VALARM",
+                "END:VEVENT",
+            ]
+        );
+
+        assert_eq!(
+            events[1],
+            [
+                "UID:DL-1234-abcd",
+                "DTSTAMP:20190920T172000Z",
+                "DTSTART;VALUE=DATE:20190927",
+                "DTEND;VALUE=DATE:20190928",
+                "SUMMARY:DL: Report",
+                "CATEGORIES:work,plans",
+                "END:VEVENT",
+            ]
+        );
+        assert_eq!(
+            &events[2][2..],
+            [
+                "DTSTART;VALUE=DATE:20190923",
+                "DUE;VALUE=DATE:20190927",
+                "SUMMARY:Report",
+                "CATEGORIES:work,plans",
+                "STATUS:NEEDS-ACTION",
+                "PRIORITY:1",
+                "END:VTODO",
+            ]
+        );
+        assert_eq!(events[2][0], "UID:TODO-1234-abcd");
+        assert_eq!(
+            &events[3][2..4],
+            ["DTSTART;VALUE=DATE:20191001", "DTEND;VALUE=DATE:20191004"]
+        );
+
+        // A done heading still gets a deadline event with its alarm, and a
+        // completed to-do.
+        assert!(events[4].contains(&"DTSTART:20190920T090000"));
+        assert!(events[4].contains(&"TRIGGER:-PT10M"));
+        assert!(events[5].contains(&"STATUS:COMPLETED"));
+        assert!(!calendar.contains("Diary"));
+
+        // UIDs without an ID are the same on every export.
+        assert_eq!(
+            calendar,
+            icalendar::export(&[("notes/plans.org", &document)], &config, 1_569_000_000)
+        );
+
+        // Long lines are folded.
+        let document = document::parse(&format!("* {} <2019-09-25 Wed>\n", "x".repeat(100)));
+        let calendar = icalendar::export(&[("", &document)], &IcalConfig::default(), 0);
+        assert!(calendar.contains(&format!(
+            "SUMMARY:{}\r\n {}\r\n",
+            "x".repeat(67),
+            "x".repeat(33)
+        )));
+        assert!(calendar.split("\r\n").all(|line| line.len() <= 75));
+    }
+
+    #[test]
+    fn icalendar_import() {
+        let source = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:abc@example.com\r
+DTSTART;TZID=\"Europe/Berlin\":20191002T090000\r
+DURATION:PT1H30M\r
+RRULE:FREQ=WEEKLY;INTERVAL=2\r
+SUMMARY:Stand-up\r
+LOCATION:Room 4\\, second floor\r
+DESCRIPTION:Agenda:\\n* updates\\n\\nMore to come\r
+CATEGORIES:Work,Team Meetings\r
+BEGIN:VALARM\r
+TRIGGER:-P1D\r
+ACTION:DISPLAY\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:trip\r
+DTSTART;VALUE=DATE:20191010\r
+DTEND;VALUE=DATE:20191013\r
+RRULE:FREQ=YEARLY;COUNT=3\r
+SUMMARY:Trip to the\r
+  coast\r
+END:VEVENT\r
+BEGIN:VTODO\r
+DTSTART:20191001T080000Z\r
+DUE;VALUE=DATE:20191004\r
+SUMMARY:Pack\r
+PRIORITY:1\r
+STATUS:COMPLETED\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+
+        let headings = icalendar::import(source, 2, 120).unwrap();
+        let org: String = headings.iter().map(|heading| heading.to_org()).collect();
+
+        assert_eq!(
+            org,
+            "** Stand-up :Work:Team_Meetings:
+:PROPERTIES:
+:ID: abc@example.com
+:LOCATION: Room 4, second floor
+:TIMEZONE: Europe/Berlin
+:END:
+<2019-10-02 Wed 09:00-10:30 +2w -1d>
+Agenda:
+ * updates
+
+More to come
+** Trip to the coast
+:PROPERTIES:
+:ID: trip
+:RRULE: FREQ=YEARLY;COUNT=3
+:END:
+<2019-10-10 Thu>--<2019-10-12 Sat>
+** DONE [#A] Pack
+DEADLINE: <2019-10-04 Fri> SCHEDULED: <2019-10-01 Tue 10:00>
+:PROPERTIES:
+:TIMEZONE: UTC
+:END:
+"
+        );
+
+        // UTC times can move to the day before.
+        let calendar =
+            "BEGIN:VEVENT\nDTSTART:20191001T080000Z\nDTEND:20191001T093000Z\nEND:VEVENT\n";
+        let headings = icalendar::import(calendar, 1, -600).unwrap();
+        assert_eq!(
+            headings[0].section.last().unwrap(),
+            "<2019-09-30 Mon 22:00-23:30>"
+        );
+
+        // The headings read back as they were built.
+        let document = document::parse(&org);
+        assert_eq!(document.headings.len(), 3);
+        assert_eq!(
+            document.headings[0].property("LOCATION").as_deref(),
+            Some("Room 4, second floor")
+        );
+        assert_eq!(
+            document.headings[2].planning().deadline.unwrap().date,
+            Date::new(2019, 10, 4).unwrap()
+        );
+
+        // What `export` writes comes back in.
+        let document = document::parse("* Review <2019-09-25 Wed 10:00 +1m>\n");
+        let calendar = icalendar::export(&[("", &document)], &IcalConfig::default(), 0);
+        let headings = icalendar::import(&calendar, 1, 0).unwrap();
+        assert_eq!(
+            headings[0].section.last().unwrap(),
+            "<2019-09-25 Wed 10:00 +1m>"
+        );
+
+        assert_eq!(
+            icalendar::import("BEGIN:VCALENDAR\nBEGIN:VEVENT\nEND:VCALENDAR\n", 1, 0),
+            Err(ImportError::Unbalanced {
+                line: 3,
+                component: "VCALENDAR".to_string(),
+            })
+        );
+        assert_eq!(
+            icalendar::import("BEGIN:VEVENT\nDTSTART:2019-10-01\nEND:VEVENT\n", 1, 0),
+            Err(ImportError::InvalidDate {
+                line: 2,
+                value: "2019-10-01".to_string(),
+            })
+        );
+        assert_eq!(
+            icalendar::import("BEGIN:VEVENT\nnonsense\n", 1, 0),
+            Err(ImportError::Malformed(2))
+        );
+    }
+
+    #[test]
+    fn latex_export() {
+        let source = "#+TITLE: Notes\n#+LATEX_CLASS: report\n#+LATEX_HEADER: \\usepackage{booktabs}\n#+OPTIONS: toc:nil\n* Intro\n  :PROPERTIES:\n  :CUSTOM_ID: intro\n  :END:\n*Bold* and =a_b= cost 5% with $x^2$ and \\alpha.[fn:1] See [[*Data][the data]] and [[#intro]].\n- [X] done\n- plain\n* Data :noexport:\nHidden.\n* Results\n#+CAPTION: Numbers\n#+NAME: tab:n\n| <l> |     |\n| a   | 10  |\n| b   | 2.5 |\n#+BEGIN_SRC emacs-lisp\n(message \"hi\")\n#+END_SRC\n* Footnotes\n[fn:1] See \\cite{knuth}.\n";
+        let document = document::parse(source);
+        let config = LatexConfig {
+            src_backend: SrcBackend::Listings,
+            ..LatexConfig::default()
+        };
+        let latex = latex::export(&document, &config);
+
+        assert!(latex.starts_with("\\documentclass{report}\n"));
+        assert!(latex.contains("\\usepackage{listings}\n\\usepackage{booktabs}\n"));
+        assert!(latex.contains("\\title{Notes}\n"));
+        assert!(!latex.contains("\\tableofcontents"));
+        assert!(latex.contains("\\part{Intro}\n\\label{intro}\n"));
+        assert!(latex.contains(
+            "\\textbf{Bold} and \\texttt{a\\_b} cost 5\\% with $x^2$ and $\\alpha$.\\footnote{See \\cite{knuth}.} See the data and \\ref{intro}.\n"
+        ));
+        assert!(latex.contains("\\item[{$\\boxtimes$}] done\n\\item plain\n"));
+        assert!(!latex.contains("Hidden"));
+        assert!(!latex.contains("Footnotes"));
+        assert!(latex.contains(
+            "\\begin{table}[htbp]\n\\caption{Numbers}\n\\label{tab:n}\n\\centering\n\\begin{tabular}{lr}\na & 10 \\\\\nb & 2.5 \\\\\n\\end{tabular}\n\\end{table}\n"
+        ));
+        assert!(latex
+            .contains("\\begin{lstlisting}[language=Lisp]\n(message \"hi\")\n\\end{lstlisting}\n"));
+        assert!(latex.ends_with("\\end{document}\n"));
+
+        let body = LatexConfig {
+            body_only: true,
+            ..LatexConfig::default()
+        };
+        assert_eq!(
+            latex::export(&document::parse("* A\n** B\nSee [[*A]].\n"), &body),
+            "\\section{A}\n\\label{sec:1}\n\\subsection{B}\n\\label{sec:1-1}\nSee \\ref{sec:1}.\n\n"
+        );
+    }
+
+    #[test]
+    fn latex_fragments() {
+        let fragments: Vec<(TokenType, String)> = scanner::scan(
+            "\\(a^2\\) and $x$, $$y_1$$ \\[ z\n\\] \\frac{a}{b^{2}} \\alpha{} \\beta. $5 or $6 \\\\",
+        )
+        .into_iter()
+        .filter(|token| {
+            token.token_type == TokenType::LatexFragment || token.token_type == TokenType::Entity
+        })
+        .map(|token| (token.token_type, token.lexeme.to_string()))
+        .collect();
+
+        assert_eq!(
+            fragments,
+            vec![
+                (TokenType::LatexFragment, "\\(a^2\\)".to_string()),
+                (TokenType::LatexFragment, "$x$".to_string()),
+                (TokenType::LatexFragment, "$$y_1$$".to_string()),
+                (TokenType::LatexFragment, "\\[ z\n\\]".to_string()),
+                (TokenType::LatexFragment, "\\frac{a}{b^{2}}".to_string()),
+                (TokenType::Entity, "\\alpha{}".to_string()),
+                (TokenType::Entity, "\\beta".to_string()),
+            ]
+        );
+
+        // A fragment ends with its paragraph.
+        let result = scanner::scan("\\( a\n\n\\) $b\n* c$");
+        assert!(result
+            .iter()
+            .all(|token| token.token_type != TokenType::LatexFragment));
+
+        let source = "\\begin{align*}\nx_1 &= *y*\n\n\\end{align*}\n\\begin{unclosed}\n";
+        let kinds: Vec<Kind> = document::parse(source)
+            .elements()
+            .into_iter()
+            .map(|element| element.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                Kind::LatexEnvironment {
+                    name: "align*".to_string()
+                },
+                Kind::Paragraph
+            ]
+        );
+
+        let highlighted: Vec<(SemanticType, usize)> = highlight::highlight(source)
+            .into_iter()
+            .map(|token| (token.kind, token.line))
+            .collect();
+        assert_eq!(
+            highlighted,
+            vec![
+                (SemanticType::Latex, 1),
+                (SemanticType::Latex, 2),
+                (SemanticType::Latex, 4),
+                (SemanticType::Latex, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn entities_and_special_strings() {
+        let alpha = entities::entity("alpha").unwrap();
+        assert_eq!((alpha.latex, alpha.math), ("\\alpha", true));
+        assert_eq!(
+            (alpha.html, alpha.ascii, alpha.utf8),
+            ("&alpha;", "alpha", "α")
+        );
+        assert_eq!(entities::entity("nope"), None);
+        assert_eq!(Entity::parse("\\rarr{}").map(|e| e.utf8), Some("→"));
+
+        let kinds: Vec<TokenType> = scanner::scan("\\nbsp{} \\newpage \\rarr")
+            .into_iter()
+            .map(|token| token.token_type)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Entity,
+                TokenType::LatexFragment,
+                TokenType::Entity,
+                TokenType::EOF
+            ]
+        );
+
+        let ranges: Vec<(std::ops::Range<usize>, &str)> =
+            entities::special_strings("a---b--c... \\-d \\\\")
+                .into_iter()
+                .map(|(range, entity)| (range, entity.name))
+                .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                (1..4, "mdash"),
+                (5..7, "ndash"),
+                (8..11, "hellip"),
+                (12..14, "shy")
+            ]
+        );
+        assert_eq!(entities::utf8("1--2 \\to \\alpha{}x..."), "1–2 → αx…");
+
+        let body = LatexConfig {
+            body_only: true,
+            ..LatexConfig::default()
+        };
+        let source = "Pay \\euro{}5 -- \\alpha\\nbsp{}b...\n";
+        assert_eq!(
+            latex::export(&document::parse(source), &body),
+            "Pay \\texteuro{}5 -- $\\alpha$~b\\dots{}\n\n"
+        );
+        let source = format!("#+OPTIONS: e:nil -:nil\n{}", source);
+        assert_eq!(
+            latex::export(&document::parse(&source), &body),
+            "Pay \\textbackslash{}euro\\{\\}5 -- \\textbackslash{}alpha\\textbackslash{}nbsp\\{\\}b...\n\n"
+        );
+
+        let document = document::parse("* Talk \\to Berlin <2019-10-01 Tue>\nSlides -- \\alpha.\n");
+        let calendar = icalendar::export(&[("", &document)], &IcalConfig::default(), 0);
+        assert!(calendar.contains("SUMMARY:Talk → Berlin\r\n"));
+        assert!(calendar.contains("DESCRIPTION:Slides – α.\r\n"));
+    }
+
+    proptest! {
+        #[test]
+        fn parse_arbitrary_text(source in "(\\PC|[\n\t*/_+<>\\[\\]{}:#|-]|[0-9]{4}-[0-9]{2}-[0-9]{2})*") {
+            let tokens = scanner::scan(&source);
+
+            // Lexemes are slices of the input, in order.
+            let mut rest = source.as_str();
+            for token in &tokens {
+                let start = rest.find(&*token.lexeme);
+                prop_assert!(start.is_some(), "{:?} not in {:?}", token.lexeme, rest);
+                rest = &rest[start.unwrap() + token.lexeme.len()..];
+            }
+
+            let mut scanned = scanner::tokens(&source);
+            while let Some(token) = scanned.next() {
+                prop_assert_eq!(&source[scanned.span()], &*token.lexeme);
+            }
+
+            // Semantic tokens come in order without overlapping.
+            let mut end = 0;
+            for token in highlight::highlight(&source) {
+                prop_assert!(token.span.start >= end && token.span.end > token.span.start);
+                prop_assert!(!source[token.span.clone()].contains('\n'));
+                end = token.span.end;
+            }
+
+            let document = document::parse(&source);
+            let org = document.to_org();
+            prop_assert_eq!(document::parse(&org).to_org(), org);
+
+            let outline = outline::outline(&source);
+            prop_assert_eq!(outline.headings.len(), document.headings.len());
+            document::parse_with_diagnostics(&source);
+            lint::lint(&source, &LintConfig::default());
+            icalendar::export(&[("", &document)], &IcalConfig::default(), 0);
+            let _ = icalendar::import(&source, 1, 0);
+            latex::export(&document, &LatexConfig::default());
+
+            let config = FormatConfig { blank_lines: 1, ..FormatConfig::default() };
+            let formatted = format::format(&source, &config);
+            prop_assert_eq!(format::format(&formatted, &config), formatted);
+
+            document.settings();
+            document.links();
+            for (path, heading) in document.all_headings() {
+                document.all_tags(&path, &TagConfig::default());
+                heading.elements();
+                heading.links();
+                heading.planning();
+            }
+        }
+
+        #[test]
+        fn edit_arbitrary_text(
+            source in "(\\PC|[\n*#+:]|BEGIN_|END_)*",
+            start in 0..64usize,
+            len in 0..8usize,
+            text in "(\\PC|[\n*#+:]|BEGIN_|END_)*",
+        ) {
+            let boundaries: Vec<usize> = source
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain(Some(source.len()))
+                .collect();
+            let first = start % boundaries.len();
+            let start = boundaries[first];
+            let end = boundaries[(first + len).min(boundaries.len() - 1)];
+
+            let mut buffer = Buffer::new(&source);
+            buffer.edit(&TextEdit { start, end, text }).unwrap();
+            prop_assert_eq!(buffer.document(), &document::parse(buffer.text()));
+        }
+    }
 }
@@ -0,0 +1,142 @@
+use crate::scanner;
+use crate::scanner::Token;
+use crate::scanner::TokenType;
+
+/// Link types recognised in plain (`https://foo`) and angle (`<https://foo>`)
+/// links. Bracket links accept any protocol.
+pub const LINK_TYPES: [&str; 12] = [
+    "http", "https", "ftp", "mailto", "file", "doi", "id", "news", "shell", "elisp", "info", "help",
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum LinkKind {
+    Http,
+    Https,
+    File,
+    Id,
+    CustomId,
+    Heading,
+    Fuzzy,
+    Mailto,
+    Doi,
+    Other(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Link {
+    pub kind: LinkKind,
+    pub path: String,
+    pub search_option: Option<String>,
+    pub description: Vec<Token<'static>>,
+}
+
+impl Link {
+    /// Parses the lexeme of a `TokenType::Link` token: a bracket link
+    /// (`[[path][description]]`), an angle link (`<https://foo>`) or a
+    /// plain link (`https://foo`).
+    pub fn parse(lexeme: &str) -> Option<Link> {
+        if lexeme.starts_with("[[") && lexeme.ends_with("]]") {
+            let inner = &lexeme[2..lexeme.len() - 2];
+
+            let (target, description) = match inner.find("][") {
+                Some(i) => (&inner[..i], Some(&inner[i + 2..])),
+                None => (inner, None),
+            };
+
+            if target.is_empty() {
+                return None;
+            }
+
+            let mut link = Link::from_target(&unescape(target));
+
+            if let Some(description) = description {
+                link.description = inline(description);
+            }
+
+            Some(link)
+        } else if lexeme.starts_with('<') && lexeme.ends_with('>') {
+            let target = &lexeme[1..lexeme.len() - 1];
+
+            target.find(':').map(|_| Link::from_target(target))
+        } else {
+            match lexeme.find(':') {
+                Some(i) if LINK_TYPES.contains(&&lexeme[..i]) => Some(Link::from_target(lexeme)),
+                _ => None,
+            }
+        }
+    }
+
+    pub fn from_token(token: &Token) -> Option<Link> {
+        if token.token_type == TokenType::Link {
+            Link::parse(&token.lexeme)
+        } else {
+            None
+        }
+    }
+
+    fn from_target(target: &str) -> Link {
+        let mut link = Link {
+            kind: LinkKind::Fuzzy,
+            path: target.to_string(),
+            search_option: None,
+            description: vec![],
+        };
+
+        if let Some(heading) = target.strip_prefix('*') {
+            link.kind = LinkKind::Heading;
+            link.path = normalise(heading);
+        } else if let Some(custom_id) = target.strip_prefix('#') {
+            link.kind = LinkKind::CustomId;
+            link.path = custom_id.to_string();
+        } else if target.starts_with('/') || target.starts_with("./") || target.starts_with("~/") {
+            link.kind = LinkKind::File;
+            link.split_search_option();
+        } else if let Some(i) = target.find(':') {
+            let (protocol, path) = (&target[..i], &target[i + 1..]);
+
+            link.path = path.to_string();
+            link.kind = match protocol {
+                "http" => LinkKind::Http,
+                "https" => LinkKind::Https,
+                "file" => LinkKind::File,
+                "id" => LinkKind::Id,
+                "mailto" => LinkKind::Mailto,
+                "doi" => LinkKind::Doi,
+                _ => LinkKind::Other(protocol.to_string()),
+            };
+
+            if link.kind == LinkKind::File {
+                link.split_search_option();
+            }
+        } else {
+            link.path = normalise(target);
+        }
+
+        link
+    }
+
+    fn split_search_option(&mut self) {
+        if let Some(i) = self.path.find("::") {
+            self.search_option = Some(self.path[i + 2..].to_string());
+            self.path.truncate(i);
+        }
+    }
+}
+
+fn inline(text: &str) -> Vec<Token<'static>> {
+    let mut tokens = scanner::scan(text);
+    tokens.pop();
+    tokens.into_iter().map(Token::into_owned).collect()
+}
+
+/// Link paths may escape brackets with a backslash, e.g. `[[foo\]bar]]`.
+fn unescape(path: &str) -> String {
+    path.replace("\\[", "[")
+        .replace("\\]", "]")
+        .replace("\\\\", "\\")
+}
+
+/// Internal links match regardless of the whitespace used inside them.
+fn normalise(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
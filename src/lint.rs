@@ -0,0 +1,462 @@
+use crate::diagnostic::Diagnostic;
+use crate::diagnostic::Severity;
+use crate::document;
+use crate::document::Document;
+use crate::element::drawer_start;
+use crate::element::is_planning;
+use crate::element::Element;
+use crate::element::Kind;
+use crate::resolve::Resolver;
+use crate::timestamp::Timestamp;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A check made by `lint`, after those of `org-lint`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    DuplicateCustomId,
+    BrokenLink,
+    MisplacedPlanning,
+    InvalidClock,
+    OrphanedEnd,
+    UnknownSrcLanguage,
+    DeprecatedBlock,
+    TrailingWhitespace,
+    MissingDrawerEnd,
+    InvalidTableFormula,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 10] = [
+        Rule::DuplicateCustomId,
+        Rule::BrokenLink,
+        Rule::MisplacedPlanning,
+        Rule::InvalidClock,
+        Rule::OrphanedEnd,
+        Rule::UnknownSrcLanguage,
+        Rule::DeprecatedBlock,
+        Rule::TrailingWhitespace,
+        Rule::MissingDrawerEnd,
+        Rule::InvalidTableFormula,
+    ];
+
+    /// The name of the rule, used as the code of its diagnostics.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Rule::DuplicateCustomId => "duplicate-custom-id",
+            Rule::BrokenLink => "broken-link",
+            Rule::MisplacedPlanning => "misplaced-planning",
+            Rule::InvalidClock => "invalid-clock",
+            Rule::OrphanedEnd => "orphaned-end",
+            Rule::UnknownSrcLanguage => "unknown-src-language",
+            Rule::DeprecatedBlock => "deprecated-block",
+            Rule::TrailingWhitespace => "trailing-whitespace",
+            Rule::MissingDrawerEnd => "missing-drawer-end",
+            Rule::InvalidTableFormula => "invalid-table-formula",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Rule> {
+        Rule::ALL.iter().copied().find(|rule| rule.code() == code)
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Rule::DuplicateCustomId | Rule::MissingDrawerEnd => Severity::Error,
+            Rule::TrailingWhitespace => Severity::Info,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+/// Which rules run, and source block languages to accept besides the
+/// built-in ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct LintConfig {
+    pub disabled: Vec<Rule>,
+    pub languages: Vec<String>,
+}
+
+impl LintConfig {
+    pub fn enabled(&self, rule: Rule) -> bool {
+        !self.disabled.contains(&rule)
+    }
+}
+
+/// Languages with Babel support or a major mode commonly used in source
+/// blocks.
+const LANGUAGES: [&str; 72] = [
+    "C",
+    "C++",
+    "D",
+    "R",
+    "S",
+    "asymptote",
+    "awk",
+    "bash",
+    "c",
+    "calc",
+    "clojure",
+    "conf",
+    "cpp",
+    "csharp",
+    "css",
+    "diff",
+    "ditaa",
+    "dockerfile",
+    "dot",
+    "elisp",
+    "elixir",
+    "emacs-lisp",
+    "erlang",
+    "eshell",
+    "fish",
+    "fortran",
+    "gnuplot",
+    "go",
+    "groovy",
+    "haskell",
+    "html",
+    "java",
+    "javascript",
+    "js",
+    "json",
+    "julia",
+    "kotlin",
+    "latex",
+    "ledger",
+    "lilypond",
+    "lisp",
+    "lua",
+    "makefile",
+    "matlab",
+    "maxima",
+    "nix",
+    "ocaml",
+    "octave",
+    "org",
+    "perl",
+    "php",
+    "plantuml",
+    "powershell",
+    "processing",
+    "python",
+    "racket",
+    "ruby",
+    "rust",
+    "sass",
+    "scala",
+    "scheme",
+    "screen",
+    "sed",
+    "sh",
+    "shell",
+    "sql",
+    "sqlite",
+    "swift",
+    "text",
+    "toml",
+    "typescript",
+    "yaml",
+];
+
+/// Export blocks from before Org 9, replaced by `#+BEGIN_EXPORT backend`.
+const DEPRECATED_BLOCKS: [&str; 8] = [
+    "ASCII", "BEAMER", "HTML", "LATEX", "MAN", "MD", "ODT", "TEXINFO",
+];
+
+/// Checks `source` with the rules `config` enables. Diagnostics are in line
+/// order and spans are byte ranges of `source`.
+pub fn lint(source: &str, config: &LintConfig) -> Vec<Diagnostic> {
+    let document = document::parse(source);
+    let mut linter = Linter {
+        config,
+        lines: source.split_inclusive('\n').collect(),
+        starts: vec![],
+        diagnostics: vec![],
+    };
+
+    let mut offset = 0;
+    for line in &linter.lines {
+        linter.starts.push(offset);
+        offset += line.len();
+    }
+
+    linter.custom_ids(&document);
+    linter.links(&document);
+
+    linter.section(&document.elements());
+
+    for (_, heading) in document.all_headings() {
+        linter.trailing_whitespace(heading.line);
+        linter.section(&heading.elements());
+    }
+
+    let mut diagnostics = linter.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+    diagnostics
+}
+
+struct Linter<'a> {
+    config: &'a LintConfig,
+    lines: Vec<&'a str>,
+    /// Byte offset of each line.
+    starts: Vec<usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    /// Text of `line`, counting from 1, without its line break.
+    fn line(&self, line: usize) -> &'a str {
+        let text = self.lines.get(line - 1).copied().unwrap_or_default();
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        text.strip_suffix('\r').unwrap_or(text)
+    }
+
+    /// Reports `rule` on `line`, covering the bytes `columns` of it or, if
+    /// none are given, the whole line.
+    fn report(&mut self, rule: Rule, line: usize, message: String, columns: Option<Range<usize>>) {
+        if !self.config.enabled(rule) {
+            return;
+        }
+
+        let start = self.starts.get(line - 1).copied().unwrap_or_default();
+        let columns = columns.unwrap_or(0..self.line(line).len());
+
+        self.diagnostics.push(
+            Diagnostic::new(rule.severity(), rule.code(), message, line)
+                .with_span(start + columns.start..start + columns.end),
+        );
+    }
+
+    fn custom_ids(&mut self, document: &Document) {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+
+        for (_, heading) in document.all_headings() {
+            let id = match heading.property("CUSTOM_ID") {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let offset = heading
+                .section
+                .iter()
+                .position(|line| line.trim_start().to_uppercase().starts_with(":CUSTOM_ID:"));
+            let line = heading.line + 1 + offset.unwrap_or_default();
+
+            match seen.get(&id) {
+                Some(first) => {
+                    let message = format!("CUSTOM_ID {} is already used on line {}", id, first);
+                    self.report(Rule::DuplicateCustomId, line, message, None);
+                }
+                None => {
+                    seen.insert(id, line);
+                }
+            }
+        }
+    }
+
+    fn links(&mut self, document: &Document) {
+        let mut resolver = Resolver::new();
+        resolver.add("", document);
+
+        for diagnostic in resolver.broken_links() {
+            self.report(Rule::BrokenLink, diagnostic.line, diagnostic.message, None);
+        }
+    }
+
+    fn trailing_whitespace(&mut self, line: usize) {
+        let text = self.line(line);
+        let trimmed = text.trim_end().len();
+
+        if trimmed < text.len() {
+            let message = "heading has trailing whitespace".to_string();
+            self.report(
+                Rule::TrailingWhitespace,
+                line,
+                message,
+                Some(trimmed..text.len()),
+            );
+        }
+    }
+
+    fn section(&mut self, elements: &[Element]) {
+        for element in elements {
+            let first_line = element.line + element.affiliated.len();
+
+            match &element.kind {
+                Kind::Block { name, parameters } => {
+                    self.block(name, parameters, first_line);
+                    continue;
+                }
+                Kind::Paragraph => self.paragraph(element, first_line),
+                Kind::Table => self.table(element, first_line),
+                _ => (),
+            }
+
+            for (j, text) in element.body().iter().enumerate() {
+                if text.trim_start().starts_with("CLOCK:") {
+                    self.clock(text, first_line + j);
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, name: &str, parameters: &str, line: usize) {
+        if DEPRECATED_BLOCKS.contains(&name) {
+            let message = format!(
+                "#+BEGIN_{} is deprecated, use #+BEGIN_EXPORT {}",
+                name,
+                name.to_lowercase()
+            );
+            self.report(Rule::DeprecatedBlock, line, message, None);
+        }
+
+        if name != "SRC" {
+            return;
+        }
+
+        let language = parameters.split_whitespace().next().unwrap_or_default();
+        let known =
+            LANGUAGES.contains(&language) || self.config.languages.iter().any(|l| l == language);
+
+        if language.is_empty() {
+            let message = "source block has no language".to_string();
+            self.report(Rule::UnknownSrcLanguage, line, message, None);
+        } else if !known {
+            let message = format!("unknown source block language {}", language);
+            self.report(Rule::UnknownSrcLanguage, line, message, None);
+        }
+    }
+
+    /// Paragraphs are where lines that failed to form an element end up. A
+    /// planning line directly below a heading forms its own element, so any
+    /// found here is misplaced.
+    fn paragraph(&mut self, element: &Element, first_line: usize) {
+        for (i, text) in element.body().iter().enumerate() {
+            let trimmed = text.trim();
+            let line = first_line + i;
+
+            if i == 0 {
+                if let Some(name) = drawer_start(trimmed) {
+                    let message = format!("drawer :{}: has no :END:", name);
+                    self.report(Rule::MissingDrawerEnd, line, message, None);
+                }
+            }
+
+            if trimmed.eq_ignore_ascii_case(":END:") {
+                let message = ":END: does not close a drawer".to_string();
+                self.report(Rule::OrphanedEnd, line, message, None);
+            }
+
+            if is_planning(trimmed) {
+                let message = "planning line must directly follow the heading".to_string();
+                self.report(Rule::MisplacedPlanning, line, message, None);
+            }
+        }
+    }
+
+    /// Checks that the clock's timestamps parse and that the duration after
+    /// `=>` is the time between them.
+    fn clock(&mut self, text: &str, line: usize) {
+        let clock =
+            regex!(r"^\s*CLOCK:\s*(\[[^\]]*\])(?:--(\[[^\]]*\])\s*=>\s*(\d+):(\d{2}))?\s*$");
+
+        let captures = match clock.captures(text) {
+            Some(captures) => captures,
+            None => {
+                let message = "malformed CLOCK line".to_string();
+                return self.report(Rule::InvalidClock, line, message, None);
+            }
+        };
+
+        let minutes = |text: &str| Timestamp::parse(text).and_then(|timestamp| timestamp.minutes());
+
+        let start = minutes(&captures[1]);
+        let end = captures.get(2).map(|end| minutes(end.as_str()));
+
+        let (start, end) = match (start, end) {
+            (Some(start), Some(Some(end))) => (start, end),
+            (Some(_), None) => return,
+            _ => {
+                let message = "CLOCK timestamp is not a valid date and time".to_string();
+                return self.report(Rule::InvalidClock, line, message, None);
+            }
+        };
+
+        if end < start {
+            let message = "CLOCK ends before it starts".to_string();
+            return self.report(Rule::InvalidClock, line, message, None);
+        }
+
+        let hours: i64 = captures[3].parse().unwrap_or_default();
+        let written = hours * 60 + captures[4].parse::<i64>().unwrap_or_default();
+
+        if written != end - start {
+            let duration = captures.get(3).unwrap().start()..captures.get(4).unwrap().end();
+            let message = format!(
+                "CLOCK duration {} should be {}:{:02}",
+                &text[duration.clone()],
+                (end - start) / 60,
+                (end - start) % 60
+            );
+            self.report(Rule::InvalidClock, line, message, Some(duration));
+        }
+    }
+
+    /// Checks that `@ROW` and `$COLUMN` references in `#+TBLFM:` lines fall
+    /// within the table. Rows are counted without horizontal rules; `@0` and
+    /// `$0` are the current field, `@<<` and `$>` count from either end, and
+    /// relative references such as `@-1` aren't checked.
+    fn table(&mut self, element: &Element, first_line: usize) {
+        let rows: Vec<&String> = element
+            .body()
+            .iter()
+            .filter(|line| line.trim_start().starts_with('|'))
+            .collect();
+        let data: Vec<&&String> = rows
+            .iter()
+            .filter(|line| !line.trim_start().starts_with("|-"))
+            .collect();
+        let columns = data
+            .iter()
+            .map(|line| line.trim().trim_matches('|').split('|').count())
+            .max()
+            .unwrap_or_default();
+
+        let reference = regex!(r"([@$])([<>]+|[-+]?\d+)");
+
+        for (i, text) in element.body().iter().enumerate().skip(rows.len()) {
+            let line = first_line + i;
+
+            for captures in reference.captures_iter(text) {
+                let target = &captures[2];
+                let n = if target.starts_with(['-', '+']) {
+                    continue;
+                } else if target.starts_with(['<', '>']) {
+                    target.len()
+                } else {
+                    target.parse().unwrap_or(usize::MAX)
+                };
+                let (kind, count) = match &captures[1] {
+                    "@" => ("row", data.len()),
+                    _ => ("column", columns),
+                };
+
+                if n > count {
+                    let found = captures.get(0).unwrap();
+                    let message = format!(
+                        "formula refers to {} {} but the table has {}",
+                        kind, target, count
+                    );
+                    self.report(
+                        Rule::InvalidTableFormula,
+                        line,
+                        message,
+                        Some(found.range()),
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,583 @@
+use crate::diagnostic;
+use crate::diagnostic::Diagnostic;
+use crate::diagnostic::Severity;
+use crate::document;
+use crate::document::Document;
+use crate::element::Element;
+use crate::element::Kind;
+use crate::link::Link;
+use crate::lint;
+use crate::lint::LintConfig;
+use crate::lint::Rule;
+use crate::outline;
+use crate::outline::OutlineHeading;
+use crate::resolve::Resolver;
+use crate::timestamp::Date;
+use crate::timestamp::Timestamp;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Keywords offered after `#+`. Those that take a value end in a colon.
+const KEYWORDS: [&str; 34] = [
+    "TITLE:",
+    "AUTHOR:",
+    "DATE:",
+    "EMAIL:",
+    "LANGUAGE:",
+    "DESCRIPTION:",
+    "KEYWORDS:",
+    "OPTIONS:",
+    "STARTUP:",
+    "FILETAGS:",
+    "TAGS:",
+    "TODO:",
+    "SEQ_TODO:",
+    "TYP_TODO:",
+    "PROPERTY:",
+    "SETUPFILE:",
+    "INCLUDE:",
+    "MACRO:",
+    "SELECT_TAGS:",
+    "EXCLUDE_TAGS:",
+    "NAME:",
+    "CAPTION:",
+    "HEADER:",
+    "RESULTS:",
+    "TBLFM:",
+    "CALL:",
+    "BEGIN_SRC",
+    "BEGIN_EXAMPLE",
+    "BEGIN_QUOTE",
+    "BEGIN_CENTER",
+    "BEGIN_VERSE",
+    "BEGIN_COMMENT",
+    "BEGIN_EXPORT",
+    "END_",
+];
+
+// Constants from the protocol.
+const FULL_SYNC: u8 = 1;
+const SYMBOL_STRING: u8 = 15;
+const COMPLETION_KEYWORD: u8 = 14;
+const COMPLETION_VALUE: u8 = 12;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+/// A language server for Org files. It takes in JSON-RPC messages and gives
+/// back those to send in reply, leaving the transport to the caller.
+/// Documents are synced in full and positions count UTF-16 code units, as
+/// the protocol does by default.
+pub struct Server {
+    /// Open documents by URI, kept in order so that results are stable.
+    documents: BTreeMap<String, String>,
+    today: Date,
+    exit: Option<i32>,
+    shutting_down: bool,
+}
+
+impl Server {
+    /// A server that shows timestamps relative to `today`.
+    pub fn new(today: Date) -> Server {
+        Server {
+            documents: BTreeMap::new(),
+            today,
+            exit: None,
+            shutting_down: false,
+        }
+    }
+
+    /// The status to exit with, once the client has sent `exit`.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
+    /// Handles one message, returning the responses and notifications it
+    /// calls for.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let id = message.get("id").cloned();
+
+        let result = match method {
+            "initialize" => Some(capabilities()),
+            "shutdown" => {
+                self.shutting_down = true;
+                Some(Value::Null)
+            }
+            "exit" => {
+                self.exit = Some(if self.shutting_down { 0 } else { 1 });
+                None
+            }
+            "textDocument/didOpen" => {
+                let uri = text(&params["textDocument"]["uri"]);
+                let source = text(&params["textDocument"]["text"]);
+                self.documents.insert(uri.clone(), source);
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didChange" => {
+                let uri = text(&params["textDocument"]["uri"]);
+                let changes = params["contentChanges"].as_array();
+
+                if let Some(change) = changes.and_then(|changes| changes.last()) {
+                    self.documents.insert(uri.clone(), text(&change["text"]));
+                }
+
+                return vec![self.diagnostics(&uri)];
+            }
+            "textDocument/didClose" => {
+                let uri = text(&params["textDocument"]["uri"]);
+                self.documents.remove(&uri);
+                return vec![notification(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )];
+            }
+            "textDocument/documentSymbol" => self.source(params).map(symbols),
+            "textDocument/foldingRange" => self.source(params).map(folding_ranges),
+            "textDocument/definition" => Some(self.definition(params)),
+            "textDocument/completion" => Some(self.completion(params)),
+            "textDocument/hover" => Some(self.hover(params)),
+            _ if id.is_some() => {
+                return vec![json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unknown method {}", method),
+                    },
+                })];
+            }
+            _ => None,
+        };
+
+        match id {
+            Some(id) => vec![json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": result.unwrap_or(Value::Null),
+            })],
+            None => vec![],
+        }
+    }
+
+    fn source(&self, params: &Value) -> Option<&str> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        self.documents.get(uri).map(String::as_str)
+    }
+
+    /// The text of the line under the cursor, and the cursor's byte offset
+    /// within it.
+    fn cursor(&self, params: &Value) -> Option<(&str, usize)> {
+        let source = self.source(params)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+
+        let text = source.lines().nth(line).unwrap_or_default();
+        Some((text, byte_column(text, character)))
+    }
+
+    /// Diagnostics from the parser and the linter. An unterminated drawer
+    /// is reported by both, so the linter's rule is left out.
+    fn diagnostics(&self, uri: &str) -> Value {
+        let source = self.documents.get(uri).map_or("", String::as_str);
+        let config = LintConfig {
+            disabled: vec![Rule::MissingDrawerEnd],
+            ..LintConfig::default()
+        };
+
+        let mut diagnostics = diagnostic::check(source);
+        diagnostics.extend(lint::lint(source, &config));
+        diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+
+        let diagnostics: Vec<Value> = diagnostics
+            .iter()
+            .map(|diagnostic| lsp_diagnostic(source, diagnostic))
+            .collect();
+
+        notification(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    /// Where the link or footnote reference under the cursor points.
+    fn definition(&self, params: &Value) -> Value {
+        let uri = text(&params["textDocument"]["uri"]);
+
+        let (line, column) = match self.cursor(params) {
+            Some(cursor) => cursor,
+            None => return Value::Null,
+        };
+
+        let footnote = regex!(r"\[fn:([^:\]\s]+)(?::[^\]]*)?\]");
+
+        if let Some(captures) = at(footnote, line, column) {
+            let definition = format!("[fn:{}]", &captures[1]);
+            let source = self.documents.get(&uri).map_or("", String::as_str);
+
+            return source
+                .lines()
+                .position(|line| line.starts_with(&definition))
+                .map_or(Value::Null, |i| location(&uri, i, i));
+        }
+
+        let bracket = regex!(r"\[\[(?:[^\]\[]|\\\[|\\\])+\](?:\[[^\]]*\])?\]");
+
+        let link = match at(bracket, line, column).and_then(|captures| Link::parse(&captures[0])) {
+            Some(link) => link,
+            None => return Value::Null,
+        };
+
+        let documents: Vec<(String, Document)> = self
+            .documents
+            .iter()
+            .map(|(uri, source)| (path(uri), document::parse(source)))
+            .collect();
+
+        let mut resolver = Resolver::new();
+
+        for (path, document) in &documents {
+            resolver.add(path, document);
+        }
+
+        match resolver.resolve(&path(&uri), &link) {
+            Some(found) => {
+                let uri = self
+                    .documents
+                    .keys()
+                    .find(|uri| path(uri) == found.file)
+                    .map_or_else(|| format!("file://{}", found.file), String::clone);
+                location(&uri, found.line - 1, found.line - 1)
+            }
+            None => Value::Null,
+        }
+    }
+
+    /// TODO keywords at the start of a heading, tags after a colon at the
+    /// end of one, and keywords after `#+`.
+    fn completion(&self, params: &Value) -> Value {
+        let (line, column) = match self.cursor(params) {
+            Some(cursor) => cursor,
+            None => return json!([]),
+        };
+        let before = &line[..column];
+        let document = document::parse(self.source(params).unwrap_or_default());
+
+        let items: Vec<Value> = if regex!(r"^\*+\s+\S*$").is_match(before) {
+            document
+                .todo_keywords
+                .iter()
+                .chain(document.done_keywords.iter())
+                .map(|keyword| json!({ "label": keyword, "kind": COMPLETION_KEYWORD }))
+                .collect()
+        } else if regex!(r"^\*+\s.*\s:[\w@#%:]*$").is_match(before) {
+            self.tags()
+                .into_iter()
+                .map(|tag| json!({ "label": tag, "kind": COMPLETION_VALUE }))
+                .collect()
+        } else if regex!(r"^\s*#\+\w*$").is_match(before) {
+            KEYWORDS
+                .iter()
+                .map(|keyword| {
+                    json!({
+                        "label": keyword.trim_end_matches(':'),
+                        "kind": COMPLETION_KEYWORD,
+                        "insertText": if keyword.ends_with(':') {
+                            format!("{} ", keyword)
+                        } else {
+                            keyword.to_string()
+                        },
+                    })
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+
+        json!(items)
+    }
+
+    /// Tags used or declared in any open document, sorted.
+    fn tags(&self) -> Vec<String> {
+        let mut tags = vec![];
+
+        for source in self.documents.values() {
+            let document = document::parse(source);
+
+            tags.extend(document.filetags());
+            tags.extend(
+                document
+                    .tag_definitions()
+                    .tags
+                    .into_iter()
+                    .map(|tag| tag.name),
+            );
+
+            for (_, heading) in document.all_headings() {
+                tags.extend(heading.tags.iter().cloned());
+            }
+        }
+
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    /// The date of the timestamp under the cursor, relative to today.
+    fn hover(&self, params: &Value) -> Value {
+        let (line, column) = match self.cursor(params) {
+            Some(cursor) => cursor,
+            None => return Value::Null,
+        };
+
+        let candidate = regex!(r"[<\[]\d{4}-\d{2}-\d{2}[^\[\]<>]*[\]>]");
+
+        let timestamp =
+            match at(candidate, line, column).and_then(|captures| Timestamp::parse(&captures[0])) {
+                Some(timestamp) => timestamp,
+                None => return Value::Null,
+            };
+
+        let days = timestamp.date.days() - self.today.days();
+        let relative = match days {
+            0 => "today".to_string(),
+            1 => "tomorrow".to_string(),
+            -1 => "yesterday".to_string(),
+            days if days > 0 => format!("in {} days", days),
+            days => format!("{} days ago", -days),
+        };
+
+        let date = timestamp.date;
+        let value = format!(
+            "{} {:04}-{:02}-{:02}, {}",
+            date.weekday(),
+            date.year,
+            date.month,
+            date.day,
+            relative
+        );
+
+        json!({ "contents": { "kind": "plaintext", "value": value } })
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            "textDocumentSync": FULL_SYNC,
+            "documentSymbolProvider": true,
+            "foldingRangeProvider": true,
+            "definitionProvider": true,
+            "hoverProvider": true,
+            "completionProvider": { "triggerCharacters": ["+", ":"] },
+        },
+        "serverInfo": {
+            "name": "org-parser-lsp",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+    })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+/// The file path of a `file://` URI, with `%XX` escapes decoded. A Windows
+/// path such as `file:///c:/notes` loses the slash before its drive, and
+/// the drive letter is upper-cased since editors send either.
+fn path(uri: &str) -> String {
+    let mut rest = uri.strip_prefix("file://").unwrap_or(uri).as_bytes();
+    let mut bytes = vec![];
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        match (byte, tail) {
+            (b'%', [high, low, tail @ ..])
+                if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() =>
+            {
+                let digit = |c: &u8| (*c as char).to_digit(16).unwrap() as u8;
+                bytes.push(digit(high) << 4 | digit(low));
+                rest = tail;
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    let path = String::from_utf8_lossy(&bytes);
+
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => {
+            format!("{}{}", drive.to_ascii_uppercase() as char, &path[2..])
+        }
+        _ => path.into_owned(),
+    }
+}
+
+/// The match of `regex` in `line` that contains byte `column`.
+fn at<'t>(regex: &regex::Regex, line: &'t str, column: usize) -> Option<regex::Captures<'t>> {
+    regex.captures_iter(line).find(|captures| {
+        let found = captures.get(0).unwrap();
+        found.start() <= column && column <= found.end()
+    })
+}
+
+fn location(uri: &str, start: usize, end: usize) -> Value {
+    json!({
+        "uri": uri,
+        "range": {
+            "start": { "line": start, "character": 0 },
+            "end": { "line": end, "character": 0 },
+        },
+    })
+}
+
+/// The byte offset in `line` of UTF-16 column `character`.
+fn byte_column(line: &str, character: usize) -> usize {
+    let mut units = 0;
+
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+
+        units += c.len_utf16();
+    }
+
+    line.len()
+}
+
+/// The protocol position of byte `offset` of `source`.
+fn position(source: &str, offset: usize) -> Value {
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+fn range(source: &str, start: usize, end: usize) -> Value {
+    json!({ "start": position(source, start), "end": position(source, end) })
+}
+
+/// The end of the text in `start..end`, leaving out trailing line breaks.
+fn trim_end(source: &str, start: usize, end: usize) -> usize {
+    start + source[start..end].trim_end_matches(['\n', '\r']).len()
+}
+
+fn lsp_diagnostic(source: &str, diagnostic: &Diagnostic) -> Value {
+    let (start, end) = match &diagnostic.span {
+        Some(span) => (span.start, span.end),
+        None => {
+            let start: usize = source
+                .split_inclusive('\n')
+                .take(diagnostic.line - 1)
+                .map(str::len)
+                .sum();
+            (start, start)
+        }
+    };
+
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Info => 3,
+    };
+
+    json!({
+        "range": range(source, start, end),
+        "severity": severity,
+        "code": diagnostic.code,
+        "source": "org",
+        "message": diagnostic.message,
+    })
+}
+
+/// The outline as nested document symbols.
+fn symbols(source: &str) -> Value {
+    fn symbol(source: &str, heading: &OutlineHeading) -> Value {
+        let line_end = trim_end(source, heading.range.start, heading.section.start);
+        let end = trim_end(source, heading.range.start, heading.range.end);
+
+        let mut detail: Vec<String> = heading.keyword.iter().cloned().collect();
+        if !heading.tags.is_empty() {
+            detail.push(format!(":{}:", heading.tags.join(":")));
+        }
+
+        let name = match heading.title.as_str() {
+            "" => "*".repeat(heading.level),
+            title => title.to_string(),
+        };
+
+        let children: Vec<Value> = heading
+            .children
+            .iter()
+            .map(|child| symbol(source, child))
+            .collect();
+
+        json!({
+            "name": name,
+            "detail": detail.join(" "),
+            "kind": SYMBOL_STRING,
+            "range": range(source, heading.range.start, end),
+            "selectionRange": range(source, heading.range.start, line_end),
+            "children": children,
+        })
+    }
+
+    let outline = outline::outline(source);
+    let symbols: Vec<Value> = outline
+        .headings
+        .iter()
+        .map(|heading| symbol(source, heading))
+        .collect();
+
+    json!(symbols)
+}
+
+/// Folds for heading subtrees, drawers and blocks.
+fn folding_ranges(source: &str) -> Value {
+    let mut ranges = vec![];
+    let document = document::parse(source);
+
+    let mut fold = |start: usize, end: usize| {
+        if end > start {
+            ranges.push(json!({ "startLine": start, "endLine": end }));
+        }
+    };
+
+    let outline = outline::outline(source);
+    let mut headings: Vec<&OutlineHeading> = outline.headings.iter().collect();
+
+    while let Some(heading) = headings.pop() {
+        let end = trim_end(source, heading.range.start, heading.range.end);
+        let end_line = source[..end].matches('\n').count();
+        fold(heading.line - 1, end_line);
+        headings.extend(heading.children.iter());
+    }
+
+    let mut elements: Vec<Element> = document.elements();
+
+    for (_, heading) in document.all_headings() {
+        elements.extend(heading.elements());
+    }
+
+    for element in elements {
+        if let Kind::Drawer { .. } | Kind::Block { .. } | Kind::LatexEnvironment { .. } =
+            element.kind
+        {
+            let start = element.line + element.affiliated.len() - 1;
+            fold(start, element.line + element.lines.len() - 2);
+        }
+    }
+
+    ranges.sort_by_key(|range| range["startLine"].as_u64());
+    json!(ranges)
+}
@@ -0,0 +1,329 @@
+use crate::document::BlockLine;
+use crate::document::Blocks;
+use crate::document::Document;
+use crate::document::Heading;
+use crate::document::Path;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How deep macros may expand into other macros before giving up.
+const MAX_DEPTH: usize = 16;
+
+/// Blocks whose contents are verbatim, so macros in them stay as written.
+const VERBATIM_BLOCKS: [&str; 4] = ["SRC", "EXAMPLE", "EXPORT", "COMMENT"];
+
+/// A `{{{name(arg1, arg2)}}}` reference.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MacroReference {
+    pub name: String,
+    pub arguments: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacroError {
+    Undefined {
+        name: String,
+        line: usize,
+    },
+    /// The macro kept expanding into further macros.
+    Recursive {
+        name: String,
+        line: usize,
+    },
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MacroError::Undefined { name, line } => {
+                write!(f, "{}: undefined macro {}", line, name)
+            }
+            MacroError::Recursive { name, line } => {
+                write!(f, "{}: macro {} expands into itself", line, name)
+            }
+        }
+    }
+}
+
+fn reference_regex() -> &'static Regex {
+    regex!(r"\{\{\{([a-zA-Z][-\w]*)(?:\((.*?)\))?\}\}\}")
+}
+
+impl MacroReference {
+    /// Parses a whole `{{{...}}}` lexeme.
+    pub fn parse(lexeme: &str) -> Option<MacroReference> {
+        let captures = reference_regex().captures(lexeme)?;
+
+        if captures[0].len() != lexeme.len() {
+            return None;
+        }
+
+        Some(MacroReference {
+            name: captures[1].to_lowercase(),
+            arguments: captures
+                .get(2)
+                .map(|m| arguments(m.as_str()))
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Splits macro arguments on commas. A comma preceded by an odd number of
+/// backslashes is literal, and each pair of backslashes before a comma
+/// stands for one, as in `org-macro-extract-arguments`.
+fn arguments(text: &str) -> Vec<String> {
+    let text = text.split_whitespace().collect::<Vec<&str>>().join(" ");
+    let mut arguments = vec![];
+    let mut current = String::new();
+    let mut backslashes = 0;
+
+    for c in text.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            ',' => {
+                current.push_str(&"\\".repeat(backslashes / 2));
+
+                if backslashes % 2 == 0 {
+                    arguments.push(current);
+                    current = String::new();
+                } else {
+                    current.push(',');
+                }
+
+                backslashes = 0;
+            }
+            _ => {
+                current.push_str(&"\\".repeat(backslashes));
+                current.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+
+    current.push_str(&"\\".repeat(backslashes));
+    arguments.push(current);
+    arguments
+}
+
+/// Replaces `$1`, `$2`, ... in `template`. Missing arguments are empty.
+fn substitute(template: &str, arguments: &[String]) -> String {
+    let placeholder = regex!(r"\$(\d+)");
+
+    placeholder
+        .replace_all(template, |captures: &regex::Captures| {
+            captures[1]
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| arguments.get(n.wrapping_sub(1)))
+                .cloned()
+                .unwrap_or_default()
+        })
+        .to_string()
+}
+
+impl Document {
+    /// `#+MACRO: name template` definitions, later ones overriding earlier
+    /// ones.
+    pub fn macros(&self) -> HashMap<String, String> {
+        let mut macros = HashMap::new();
+
+        for keyword in self.keywords() {
+            if keyword.key == "MACRO" {
+                let mut parts = keyword.value.splitn(2, char::is_whitespace);
+                let name = parts.next().unwrap_or_default().to_lowercase();
+
+                if !name.is_empty() {
+                    macros.insert(name, parts.next().unwrap_or_default().trim().to_string());
+                }
+            }
+        }
+
+        macros
+    }
+
+    /// A copy of the document with every macro reference in titles and
+    /// sections replaced, outside verbatim blocks. Besides `#+MACRO:`
+    /// definitions this knows `title`, `author`, `email`, `date`,
+    /// `keyword(NAME)`, `property(KEY)`, `property(KEY, *Title)` and the
+    /// counter `n(name, action)`.
+    pub fn expand_macros(&self) -> Result<Document, MacroError> {
+        let mut expander = Expander {
+            document: self,
+            macros: self.macros(),
+            counters: HashMap::new(),
+        };
+        let mut document = self.clone();
+
+        document.section = expander.section(&self.section, 1, &[])?;
+
+        for (path, heading) in self.all_headings() {
+            let title = expander.text(&heading.title, heading.line, &path)?;
+            let section = expander.section(&heading.section, heading.line + 1, &path)?;
+
+            let expanded = document.heading_mut(&path).unwrap();
+            expanded.title = title;
+            expanded.section = section;
+        }
+
+        Ok(document)
+    }
+}
+
+struct Expander<'a> {
+    document: &'a Document,
+    macros: HashMap<String, String>,
+    counters: HashMap<String, i64>,
+}
+
+impl<'a> Expander<'a> {
+    fn section(
+        &mut self,
+        lines: &[String],
+        first_line: usize,
+        path: &[usize],
+    ) -> Result<Vec<String>, MacroError> {
+        let mut out = vec![];
+        let mut blocks = Blocks::new(lines.iter().map(String::as_str));
+
+        for (i, line) in lines.iter().enumerate() {
+            let upper = line.trim_start().to_uppercase();
+            let verbatim = match blocks.read(line) {
+                BlockLine::Begin(name) | BlockLine::Inside(name) | BlockLine::End(name) => {
+                    VERBATIM_BLOCKS.contains(&name.as_str())
+                }
+                _ => false,
+            };
+
+            if verbatim || upper.starts_with("#+MACRO:") {
+                out.push(line.clone());
+            } else {
+                out.push(self.text(line, first_line + i, path)?);
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Expands the references in `text`, and then those in the result, up to
+    /// `MAX_DEPTH` times.
+    fn text(&mut self, text: &str, line: usize, path: &[usize]) -> Result<String, MacroError> {
+        let reference = reference_regex();
+        let mut text = text.to_string();
+
+        for _ in 0..MAX_DEPTH {
+            let mut out = String::new();
+            let mut last = 0;
+
+            for found in reference.find_iter(&text) {
+                let macro_reference = MacroReference::parse(found.as_str()).unwrap();
+
+                out.push_str(&text[last..found.start()]);
+                out.push_str(&self.expand(&macro_reference, line, path)?);
+                last = found.end();
+            }
+
+            if last == 0 {
+                return Ok(text);
+            }
+
+            out.push_str(&text[last..]);
+            text = out;
+        }
+
+        match reference.find(&text) {
+            Some(found) => Err(MacroError::Recursive {
+                name: MacroReference::parse(found.as_str()).unwrap().name,
+                line,
+            }),
+            None => Ok(text),
+        }
+    }
+
+    fn expand(
+        &mut self,
+        reference: &MacroReference,
+        line: usize,
+        path: &[usize],
+    ) -> Result<String, MacroError> {
+        if let Some(template) = self.macros.get(&reference.name) {
+            return Ok(substitute(template, &reference.arguments));
+        }
+
+        let argument = |i: usize| {
+            reference
+                .arguments
+                .get(i)
+                .map(|argument| argument.trim())
+                .unwrap_or_default()
+        };
+        let document = self.document;
+        let settings = || document.settings();
+
+        Ok(match reference.name.as_str() {
+            "title" => settings().title.unwrap_or_default(),
+            "author" => settings().author.unwrap_or_default(),
+            "email" => settings().email.unwrap_or_default(),
+            // Formatting the date needs `format-time-string`; it is left as
+            // written.
+            "date" => settings().date.unwrap_or_default(),
+            "keyword" => {
+                let key = argument(0).to_uppercase();
+
+                document
+                    .keywords()
+                    .into_iter()
+                    .filter(|keyword| keyword.key == key)
+                    .map(|keyword| keyword.value)
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            }
+            "property" => {
+                let heading = match argument(1) {
+                    "" => document.heading(path),
+                    search => find(document, search),
+                };
+
+                heading
+                    .and_then(|heading| heading.property(argument(0)))
+                    .unwrap_or_default()
+            }
+            "n" => self.counter(argument(0), argument(1)).to_string(),
+            _ => {
+                return Err(MacroError::Undefined {
+                    name: reference.name.clone(),
+                    line,
+                })
+            }
+        })
+    }
+
+    /// `{{{n(name)}}}` increments and returns the counter, `-` returns it
+    /// unchanged and a number resets it to that number.
+    fn counter(&mut self, name: &str, action: &str) -> i64 {
+        let counter = self.counters.entry(name.to_string()).or_insert(0);
+
+        match action {
+            "-" => (),
+            _ => match action.parse() {
+                Ok(value) => *counter = value,
+                Err(_) => *counter += 1,
+            },
+        }
+
+        *counter
+    }
+}
+
+/// The heading `*Title` or `#custom-id`.
+fn find<'a>(document: &'a Document, search: &str) -> Option<&'a Heading> {
+    let headings: Vec<(Path, &Heading)> = document.all_headings();
+
+    headings
+        .into_iter()
+        .find(|(_, heading)| match search.strip_prefix('#') {
+            Some(id) => heading.property("CUSTOM_ID").as_deref() == Some(id),
+            None => heading.normalised_title() == search.trim_start_matches('*').trim(),
+        })
+        .map(|(_, heading)| heading)
+}
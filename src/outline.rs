@@ -0,0 +1,188 @@
+use crate::document;
+use crate::document::heading_level;
+use crate::document::todo_setting;
+use crate::document::BlockLine;
+use crate::document::Blocks;
+use crate::document::Document;
+use crate::document::Heading;
+use crate::document::TODO_KEYWORDS;
+use std::ops::Range;
+
+/// The headings of a file without their sections, as read by `outline`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Outline {
+    pub headings: Vec<OutlineHeading>,
+    pub todo_keywords: Vec<String>,
+    pub done_keywords: Vec<String>,
+}
+
+/// A heading line and where its contents are. `range` is the byte range of
+/// the whole subtree and `section` that of the lines between the heading
+/// line and its first subheading.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutlineHeading {
+    pub level: usize,
+    pub keyword: Option<String>,
+    pub priority: Option<char>,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub line: usize,
+    pub range: Range<usize>,
+    pub section: Range<usize>,
+    pub children: Vec<OutlineHeading>,
+}
+
+/// Reads only the heading lines of `source`. Other lines are looked at just
+/// far enough to follow blocks, so a `* ` inside a block is not taken for a
+/// heading, and to find TODO settings.
+pub fn outline(source: &str) -> Outline {
+    let mut outline = Outline {
+        headings: vec![],
+        todo_keywords: vec![TODO_KEYWORDS[0].to_string()],
+        done_keywords: vec![TODO_KEYWORDS[1].to_string()],
+    };
+    let mut open: Vec<OutlineHeading> = vec![];
+    let mut blocks = Blocks::new(source.lines());
+    let mut custom_keywords = false;
+    // Whether a TODO setting came after a heading read with other keywords.
+    let mut stale = false;
+    let mut offset = 0;
+
+    for (i, text) in source.split_inclusive('\n').enumerate() {
+        let start = offset;
+        offset += text.len();
+
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+
+        let block = blocks.read(text);
+
+        if text.starts_with('*') && block == BlockLine::Outside {
+            if let Some(level) = heading_level(text) {
+                close(&mut outline, &mut open, level, start);
+
+                let keywords: Vec<&String> = outline
+                    .todo_keywords
+                    .iter()
+                    .chain(outline.done_keywords.iter())
+                    .collect();
+                let heading = Heading::parse(text, level, i + 1, &keywords);
+
+                open.push(OutlineHeading {
+                    level,
+                    keyword: heading.keyword,
+                    priority: heading.priority,
+                    title: heading.title,
+                    tags: heading.tags,
+                    line: heading.line,
+                    range: start..source.len(),
+                    section: offset..source.len(),
+                    children: vec![],
+                });
+                continue;
+            }
+        }
+
+        if let Some((todo, done)) = todo_setting(text) {
+            if !custom_keywords {
+                outline.todo_keywords.clear();
+                outline.done_keywords.clear();
+                custom_keywords = true;
+            }
+
+            outline.todo_keywords.extend(todo);
+            outline.done_keywords.extend(done);
+            stale |= !open.is_empty() || !outline.headings.is_empty();
+        }
+    }
+
+    close(&mut outline, &mut open, 1, source.len());
+
+    // TODO settings apply to the headings before them as well.
+    if stale {
+        let keywords: Vec<String> = outline
+            .todo_keywords
+            .iter()
+            .chain(outline.done_keywords.iter())
+            .cloned()
+            .collect();
+        let keywords: Vec<&String> = keywords.iter().collect();
+
+        reread(&mut outline.headings, source, &keywords);
+    }
+
+    outline
+}
+
+/// Parses the lines of `headings` and their children again with `keywords`.
+fn reread(headings: &mut [OutlineHeading], source: &str, keywords: &[&String]) {
+    for heading in headings {
+        let text = source[heading.range.start..].lines().next().unwrap_or("");
+        let parsed = Heading::parse(text, heading.level, heading.line, keywords);
+
+        heading.keyword = parsed.keyword;
+        heading.priority = parsed.priority;
+        heading.title = parsed.title;
+        heading.tags = parsed.tags;
+
+        reread(&mut heading.children, source, keywords);
+    }
+}
+
+/// Ends every open heading at `level` or deeper at byte `end`, moving it
+/// into its parent.
+fn close(outline: &mut Outline, open: &mut Vec<OutlineHeading>, level: usize, end: usize) {
+    while open.last().is_some_and(|heading| heading.level >= level) {
+        let mut heading = open.pop().unwrap();
+        heading.range.end = end;
+
+        if heading.children.is_empty() {
+            heading.section.end = end;
+        }
+
+        match open.last_mut() {
+            Some(parent) => {
+                if parent.children.is_empty() {
+                    parent.section.end = heading.range.start;
+                }
+
+                parent.children.push(heading);
+            }
+            None => outline.headings.push(heading),
+        }
+    }
+}
+
+impl Outline {
+    pub fn heading(&self, path: &[usize]) -> Option<&OutlineHeading> {
+        let (first, rest) = path.split_first()?;
+        let mut heading = self.headings.get(*first)?;
+
+        for i in rest {
+            heading = heading.children.get(*i)?;
+        }
+
+        Some(heading)
+    }
+
+    /// Parses the subtree at `path` in full, from the `source` the outline
+    /// was read from.
+    pub fn parse_heading(&self, source: &str, path: &[usize]) -> Option<Heading> {
+        let heading = self.heading(path)?;
+        let keywords = Document {
+            section: vec![],
+            headings: vec![],
+            todo_keywords: self.todo_keywords.clone(),
+            done_keywords: self.done_keywords.clone(),
+        };
+
+        let text = source.get(heading.range.clone())?;
+        let mut fragment = document::parse_fragment(text, heading.line, &keywords);
+
+        if fragment.headings.is_empty() {
+            None
+        } else {
+            Some(fragment.headings.remove(0))
+        }
+    }
+}
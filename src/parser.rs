@@ -3,125 +3,105 @@ use crate::scanner::TokenType;
 use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
-pub struct Node {
-    pub data: Vec<Token>,
-    pub children: HashMap<String, Node>,
+pub struct Node<'a> {
+    pub data: Vec<Token<'a>>,
+    pub children: HashMap<String, Node<'a>>,
 }
 
-pub fn parse(source: Vec<Token>) -> Node {
-    to_dom(source)
-}
-
-fn till_next(token_list: Vec<Token>, length: usize) -> Vec<Token> {
-    let mut tokens = vec![];
-
-    let mut asterisk = false;
-    let mut i = 1;
-
-    while i < token_list.len() && !asterisk && token_list[i].token_type != TokenType::EOF {
-        if token_list[i].token_type == TokenType::Asterisk && token_list[i].lexeme.len() == length {
-            asterisk = true;
-        } else {
-            tokens.push(token_list[i].clone());
+impl<'a> Node<'a> {
+    fn new(data: Vec<Token<'a>>) -> Node<'a> {
+        Node {
+            data,
+            children: HashMap::new(),
         }
-
-        i = i + 1;
     }
-
-    tokens
 }
 
-fn to_dom(source: Vec<Token>) -> Node {
-    let mut dom: Node = Node {
-        data: vec![],
-        children: HashMap::new(),
+/// Builds the outline tree from `tokens` in a single pass, holding only the
+/// current line and the headings still open.
+///
+/// Lines before the first heading make up the data of the root. A heading
+/// node holds the tokens of its heading line and has as children its
+/// subheadings, keyed by their text, and a node for each line of its
+/// section, keyed by the lexemes of that line.
+pub fn parse<'a, I>(tokens: I) -> Node<'a>
+where
+    I: IntoIterator<Item = Token<'a>>,
+{
+    let mut builder = Builder {
+        root: Node::new(vec![]),
+        open: vec![],
     };
-    let mut data = vec![];
-    let mut children = vec![];
+    let mut line: Vec<Token<'a>> = vec![];
 
-    let mut active: isize = -1;
-
-    for (i, token) in source.iter().enumerate() {
-        let lexeme_length = token.lexeme.len();
-
-        if i as isize > active {
-            if token.token_type == TokenType::Asterisk {
-                let (_left, right) = source.split_at(i);
-                let right = right.to_vec();
-                let inner_source = till_next(right, lexeme_length);
-
-                if (i + inner_source.len()) as isize > active {
-                    active = (i + inner_source.len()) as isize;
-                }
+    for token in tokens {
+        if token.token_type == TokenType::EOF {
+            break;
+        }
 
-                let mut sub_data: Vec<Token> = vec![token.clone()];
+        if line.last().is_some_and(|last| last.line != token.line) {
+            builder.line(std::mem::take(&mut line));
+        }
 
-                let mut z = 0;
+        line.push(token);
+    }
 
-                while z < inner_source.len() && inner_source[z].line == token.line {
-                    sub_data.push(inner_source[z].clone());
-                    z = z + 1;
-                }
+    if !line.is_empty() {
+        builder.line(line);
+    }
 
-                let (_left, right) = inner_source.split_at(z);
-                let mut sub_dom = to_dom(right.to_vec());
-
-                let mut child: Node = Node {
-                    data: vec![],
-                    children: HashMap::new(),
-                };
-
-                for (i, token) in sub_dom.data.iter().enumerate() {
-                    if i > 0 && sub_dom.data[i - 1].line != token.line {
-                        let mut node_title: String = "".to_owned();
-
-                        for token in child.data.clone() {
-                            node_title.push_str(&token.lexeme);
-                        }
-
-                        sub_dom.children.insert(node_title, child);
-                        child = Node {
-                            data: vec![],
-                            children: HashMap::new(),
-                        };
-                    } else {
-                        child.data.push(token.clone())
-                    }
-                }
+    builder.finish()
+}
 
-                let inner_element: Node = Node {
-                    data: sub_data,
-                    children: sub_dom.children,
-                };
+struct Builder<'a> {
+    root: Node<'a>,
+    /// The open headings, outermost first, with their levels.
+    open: Vec<(usize, Node<'a>)>,
+}
 
-                children.push(inner_element)
-            } else {
-                let mut z = i;
+impl<'a> Builder<'a> {
+    fn line(&mut self, line: Vec<Token<'a>>) {
+        if line[0].token_type == TokenType::Asterisk {
+            let level = line[0].lexeme.len();
 
-                while z < source.len() && source[z].line == token.line {
-                    data.push(source[z].clone());
+            while self.open.last().is_some_and(|(open, _)| *open >= level) {
+                self.close();
+            }
 
-                    z = z + 1;
+            self.open.push((level, Node::new(line)));
+        } else {
+            match self.open.last_mut() {
+                Some((_, heading)) => {
+                    let key = line.iter().map(|token| &*token.lexeme).collect();
+                    heading.children.insert(key, Node::new(line));
                 }
-
-                active = (z - 1) as isize;
+                None => self.root.data.extend(line),
             }
         }
     }
 
-    for node in children {
-        let mut node_title: String = "".to_owned();
+    /// Adds the innermost open heading to its parent.
+    fn close(&mut self) {
+        let (_, node) = self.open.pop().unwrap();
+
+        let key = node
+            .data
+            .iter()
+            .filter(|token| token.token_type == TokenType::String)
+            .map(|token| &*token.lexeme)
+            .collect();
+
+        match self.open.last_mut() {
+            Some((_, parent)) => parent.children.insert(key, node),
+            None => self.root.children.insert(key, node),
+        };
+    }
 
-        for token in node.data.clone() {
-            if token.token_type == TokenType::String {
-                node_title.push_str(&token.lexeme);
-            }
+    fn finish(mut self) -> Node<'a> {
+        while !self.open.is_empty() {
+            self.close();
         }
 
-        dom.children.insert(node_title, node);
+        self.root
     }
-
-    dom.data = data;
-
-    dom
 }
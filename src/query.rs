@@ -0,0 +1,486 @@
+use crate::document::Document;
+use crate::document::Heading;
+use crate::document::Path;
+use crate::tags::TagConfig;
+use crate::tags::TagDefinitions;
+use crate::timestamp::Date;
+use crate::timestamp::Timestamp;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A tags/property match string in the syntax of `org-match-sparse-tree`,
+/// e.g. `+work-boss+PRIORITY="A"|LEVEL>2/TODO|NEXT`.
+#[derive(Debug)]
+pub struct Query {
+    alternatives: Vec<Vec<Condition>>,
+    todo: Vec<Vec<Condition>>,
+}
+
+#[derive(Debug)]
+struct Condition {
+    negated: bool,
+    term: Term,
+}
+
+#[derive(Debug)]
+enum Term {
+    Tag(String),
+    TagRegex(Regex),
+    Todo(String),
+    Property {
+        name: String,
+        operator: Operator,
+        value: Value,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug)]
+enum Value {
+    String(String),
+    Number(f64),
+    Regex(Regex),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+/// What a query is evaluated against besides the heading itself.
+pub struct QueryOptions {
+    pub today: Date,
+    pub tags: TagConfig,
+}
+
+impl QueryOptions {
+    pub fn new(today: Date) -> QueryOptions {
+        QueryOptions {
+            today,
+            tags: TagConfig::default(),
+        }
+    }
+}
+
+/// A matching heading, with the titles of its ancestors and itself.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Match {
+    pub path: Path,
+    pub titles: Vec<String>,
+    pub line: usize,
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Query, QueryError> {
+        let mut parser = Parser {
+            chars: source.chars().collect(),
+            current: 0,
+        };
+
+        let alternatives = parser.alternatives(false)?;
+
+        let todo = if parser.peek() == Some('/') {
+            parser.current += 1;
+            parser.alternatives(true)?
+        } else {
+            vec![]
+        };
+
+        match parser.peek() {
+            Some(c) => Err(parser.error(&format!("unexpected {:?}", c))),
+            None => Ok(Query { alternatives, todo }),
+        }
+    }
+
+    /// Headings of `document` matching the query, in document order.
+    pub fn search(&self, document: &Document, options: &QueryOptions) -> Vec<Match> {
+        let definitions = document.tag_definitions();
+
+        document
+            .all_headings()
+            .into_iter()
+            .filter(|(path, _)| self.matches(document, path, &definitions, options))
+            .map(|(path, heading)| Match {
+                titles: (1..=path.len())
+                    .filter_map(|i| document.heading(&path[..i]))
+                    .map(|heading| heading.normalised_title())
+                    .collect(),
+                path,
+                line: heading.line,
+            })
+            .collect()
+    }
+
+    pub fn matches(
+        &self,
+        document: &Document,
+        path: &[usize],
+        definitions: &TagDefinitions,
+        options: &QueryOptions,
+    ) -> bool {
+        let heading = match document.heading(path) {
+            Some(heading) => heading,
+            None => return false,
+        };
+
+        let context = Context {
+            document,
+            path,
+            heading,
+            tags: document.all_tags(path, &options.tags),
+            definitions,
+            options,
+        };
+
+        let any = |alternatives: &Vec<Vec<Condition>>| {
+            alternatives.is_empty()
+                || alternatives.iter().any(|conditions| {
+                    conditions
+                        .iter()
+                        .all(|condition| context.holds(&condition.term) != condition.negated)
+                })
+        };
+
+        any(&self.alternatives) && any(&self.todo)
+    }
+}
+
+struct Context<'a> {
+    document: &'a Document,
+    path: &'a [usize],
+    heading: &'a Heading,
+    tags: Vec<String>,
+    definitions: &'a TagDefinitions,
+    options: &'a QueryOptions,
+}
+
+impl<'a> Context<'a> {
+    fn holds(&self, term: &Term) -> bool {
+        match term {
+            Term::Tag(tag) => self
+                .definitions
+                .expand(tag)
+                .iter()
+                .any(|tag| self.tags.contains(tag)),
+            Term::TagRegex(regex) => self.tags.iter().any(|tag| regex.is_match(tag)),
+            Term::Todo(keyword) => self.heading.keyword.as_deref() == Some(keyword.as_str()),
+            Term::Property {
+                name,
+                operator,
+                value,
+            } => match self.property(name) {
+                Some(property) => compare(&property, operator, value, self.options.today),
+                None => *operator == Operator::NotEqual,
+            },
+        }
+    }
+
+    /// The value of a property, including the special properties `TODO`,
+    /// `LEVEL`, `PRIORITY`, `ITEM`, `TAGS`, `ALLTAGS` and the planning
+    /// keywords. Other properties are inherited from ancestors.
+    fn property(&self, name: &str) -> Option<String> {
+        let heading = self.heading;
+        let planning = || heading.planning();
+
+        match name {
+            "TODO" => heading.keyword.clone(),
+            "LEVEL" => Some(heading.level.to_string()),
+            "PRIORITY" => Some(heading.priority.unwrap_or('B').to_string()),
+            "ITEM" => Some(heading.normalised_title()),
+            "TAGS" => Some(format!(":{}:", heading.tags.join(":"))),
+            "ALLTAGS" => Some(format!(":{}:", self.tags.join(":"))),
+            "SCHEDULED" => planning().scheduled.map(|t| t.to_org()),
+            "DEADLINE" => planning().deadline.map(|t| t.to_org()),
+            "CLOSED" => planning().closed.map(|t| t.to_org()),
+            _ => (1..=self.path.len())
+                .rev()
+                .filter_map(|i| self.document.heading(&self.path[..i]))
+                .find_map(|heading| heading.property(name)),
+        }
+    }
+}
+
+fn compare(property: &str, operator: &Operator, value: &Value, today: Date) -> bool {
+    let ordering = match value {
+        Value::Regex(regex) => {
+            let matched = regex.is_match(property);
+            return if *operator == Operator::NotEqual {
+                !matched
+            } else {
+                matched
+            };
+        }
+        Value::Number(number) => match property.trim().parse::<f64>() {
+            Ok(property) => property.partial_cmp(number),
+            Err(_) => None,
+        },
+        Value::String(text) if text.starts_with('<') || text.starts_with('[') => {
+            match (Timestamp::parse(property), time_value(text, today)) {
+                (Some(property), Some(value)) => Some(property.compare(&value)),
+                _ => None,
+            }
+        }
+        Value::String(text) => match (duration(property), duration(text)) {
+            (Some(property), Some(value)) => Some(property.cmp(&value)),
+            _ => Some(property.cmp(text.as_str())),
+        },
+    };
+
+    match ordering {
+        Some(ordering) => match operator {
+            Operator::Equal => ordering == Ordering::Equal,
+            Operator::NotEqual => ordering != Ordering::Equal,
+            Operator::Less => ordering == Ordering::Less,
+            Operator::LessOrEqual => ordering != Ordering::Greater,
+            Operator::Greater => ordering == Ordering::Greater,
+            Operator::GreaterOrEqual => ordering != Ordering::Less,
+        },
+        None => false,
+    }
+}
+
+/// A timestamp, or one of the relative forms `<today>`, `<tomorrow>`,
+/// `<yesterday>`, `<now>` and `<+3d>`/`<-2w>`.
+fn time_value(text: &str, today: Date) -> Option<Timestamp> {
+    let inner = match text
+        .strip_prefix('<')
+        .and_then(|text| text.strip_suffix('>'))
+    {
+        Some(inner) => inner,
+        None => return Timestamp::parse(text),
+    };
+
+    let date = match inner {
+        "today" | "now" => today,
+        "tomorrow" => today.add_days(1),
+        "yesterday" => today.add_days(-1),
+        _ => {
+            let relative = regex!(r"^([+-]\d+)([dwmy])$");
+
+            match relative.captures(inner) {
+                Some(captures) => {
+                    let n: i64 = captures[1].parse().ok()?;
+
+                    match &captures[2] {
+                        "d" => today.add_days(n),
+                        "w" => today.add_days(n * 7),
+                        "m" => today.add_months(n),
+                        _ => today.add_months(n * 12),
+                    }
+                }
+                None => return Timestamp::parse(text),
+            }
+        }
+    };
+
+    Some(Timestamp {
+        active: true,
+        date,
+        time: None,
+        end_time: None,
+        repeater: None,
+        warning: None,
+    })
+}
+
+/// Minutes in an effort-style duration such as `1:30`.
+fn duration(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.trim().split_once(':')?;
+    Some(hours.parse::<u32>().ok()? * 60 + minutes.parse::<u32>().ok()?)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    current: usize,
+}
+
+impl Parser {
+    fn alternatives(&mut self, todo: bool) -> Result<Vec<Vec<Condition>>, QueryError> {
+        let mut alternatives = vec![];
+
+        loop {
+            alternatives.push(self.conditions(todo)?);
+
+            if self.peek() == Some('|') {
+                self.current += 1;
+            } else {
+                break;
+            }
+        }
+
+        if alternatives.len() == 1 && alternatives[0].is_empty() {
+            alternatives.clear();
+        }
+
+        Ok(alternatives)
+    }
+
+    fn conditions(&mut self, todo: bool) -> Result<Vec<Condition>, QueryError> {
+        let mut conditions = vec![];
+
+        if todo && self.peek() == Some('!') {
+            self.current += 1;
+        }
+
+        while let Some(c) = self.peek() {
+            let negated = match c {
+                '+' | '&' => {
+                    self.current += 1;
+                    false
+                }
+                '-' => {
+                    self.current += 1;
+                    true
+                }
+                '|' | '/' => break,
+                _ => false,
+            };
+
+            let term = if todo {
+                Term::Todo(self.word()?)
+            } else {
+                self.term()?
+            };
+
+            conditions.push(Condition { negated, term });
+        }
+
+        Ok(conditions)
+    }
+
+    fn term(&mut self) -> Result<Term, QueryError> {
+        if self.peek() == Some('{') {
+            return Ok(Term::TagRegex(self.regex()?));
+        }
+
+        let name = self.word()?;
+
+        let operator = match (self.peek(), self.chars.get(self.current + 1)) {
+            (Some('<'), Some('>')) | (Some('!'), Some('=')) => Operator::NotEqual,
+            (Some('<'), Some('=')) => Operator::LessOrEqual,
+            (Some('>'), Some('=')) => Operator::GreaterOrEqual,
+            (Some('<'), _) => Operator::Less,
+            (Some('>'), _) => Operator::Greater,
+            (Some('='), _) => Operator::Equal,
+            _ => return Ok(Term::Tag(name)),
+        };
+
+        self.current += match operator {
+            Operator::Less | Operator::Greater | Operator::Equal => 1,
+            _ => 2,
+        };
+
+        let value = match self.peek() {
+            Some('"') => Value::String(self.string()?),
+            Some('{') => Value::Regex(self.regex()?),
+            _ => {
+                let start = self.current;
+                let mut number = String::new();
+
+                if self.peek() == Some('-') {
+                    number.push('-');
+                    self.current += 1;
+                }
+
+                number.push_str(&self.take_while(|c| c.is_ascii_digit() || c == '.'));
+
+                match number.parse() {
+                    Ok(number) => Value::Number(number),
+                    Err(_) => {
+                        self.current = start;
+                        return Err(self.error("expected a string, number or regex"));
+                    }
+                }
+            }
+        };
+
+        Ok(Term::Property {
+            name,
+            operator,
+            value,
+        })
+    }
+
+    fn word(&mut self) -> Result<String, QueryError> {
+        let word = self.take_while(|c| c.is_alphanumeric() || "_@#%:".contains(c));
+
+        if word.is_empty() {
+            Err(self.error("expected a tag or property name"))
+        } else {
+            Ok(word)
+        }
+    }
+
+    fn string(&mut self) -> Result<String, QueryError> {
+        self.current += 1;
+        let text = self.take_while(|c| c != '"');
+
+        if self.peek() != Some('"') {
+            return Err(self.error("unterminated string"));
+        }
+
+        self.current += 1;
+        Ok(text)
+    }
+
+    fn regex(&mut self) -> Result<Regex, QueryError> {
+        self.current += 1;
+        let start = self.current;
+        let pattern = self.take_while(|c| c != '}');
+
+        if self.peek() != Some('}') {
+            return Err(self.error("unterminated regex"));
+        }
+
+        self.current += 1;
+
+        // Org escapes `|` inside match strings; the regex crate doesn't need it.
+        Regex::new(&pattern.replace("\\|", "|")).map_err(|_| QueryError {
+            position: start,
+            message: "invalid regex".to_string(),
+        })
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> String {
+        let mut text = String::new();
+
+        while let Some(c) = self.peek() {
+            if !predicate(c) {
+                break;
+            }
+
+            text.push(c);
+            self.current += 1;
+        }
+
+        text
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn error(&self, message: &str) -> QueryError {
+        QueryError {
+            position: self.current,
+            message: message.to_string(),
+        }
+    }
+}
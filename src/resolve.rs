@@ -0,0 +1,242 @@
+use crate::diagnostic::Diagnostic;
+use crate::diagnostic::Severity;
+use crate::document;
+use crate::document::Document;
+use crate::document::Heading;
+use crate::document::Path;
+use crate::link::Link;
+use crate::link::LinkKind;
+use crate::scanner::TokenType;
+
+/// The node a link points at: a file, and within it the heading path (empty
+/// for the top of the file) and line.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub path: Path,
+    pub line: usize,
+}
+
+/// Resolves internal links across a set of documents, keyed by file path.
+pub struct Resolver<'a> {
+    documents: Vec<(String, &'a Document)>,
+}
+
+impl<'a> Default for Resolver<'a> {
+    fn default() -> Self {
+        Resolver::new()
+    }
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new() -> Resolver<'a> {
+        Resolver { documents: vec![] }
+    }
+
+    pub fn add(&mut self, file: &str, document: &'a Document) {
+        let file = normalise_path(file);
+
+        self.documents.retain(|(f, _)| *f != file);
+        self.documents.push((file, document));
+    }
+
+    /// Whether `link` refers to something inside the documents, as opposed
+    /// to a web page, mail address or non-Org file.
+    pub fn is_internal(link: &Link) -> bool {
+        match &link.kind {
+            LinkKind::Heading | LinkKind::CustomId | LinkKind::Id | LinkKind::Fuzzy => true,
+            LinkKind::File => link.path.ends_with(".org"),
+            _ => false,
+        }
+    }
+
+    /// Resolves `link`, found in the file `from`, to the node it refers to.
+    /// Returns `None` for external links and for internal links that don't
+    /// resolve.
+    pub fn resolve(&self, from: &str, link: &Link) -> Option<Location> {
+        match &link.kind {
+            LinkKind::Heading | LinkKind::CustomId | LinkKind::Fuzzy => {
+                let (file, document) = self.document(&normalise_path(from))?;
+                search(file, document, &search_option(link))
+            }
+            LinkKind::Id => self.documents.iter().find_map(|(file, document)| {
+                find_heading(file, document, |heading| {
+                    heading.property("ID").as_deref() == Some(link.path.as_str())
+                })
+            }),
+            LinkKind::File if Resolver::is_internal(link) => {
+                let (file, document) = self.document(&join(from, &link.path))?;
+
+                match &link.search_option {
+                    Some(option) => search(file, document, option),
+                    None => Some(Location {
+                        file: file.to_string(),
+                        path: vec![],
+                        line: 1,
+                    }),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Every internal link that doesn't resolve, as a diagnostic.
+    pub fn broken_links(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+
+        for (file, document) in &self.documents {
+            let mut links = document.links();
+
+            for (_, heading) in document.all_headings() {
+                links.extend(heading.links());
+            }
+
+            for (line, link) in links {
+                if Resolver::is_internal(&link) && self.resolve(file, &link).is_none() {
+                    let message = format!("link to {} does not resolve", describe(&link));
+                    diagnostics.push(
+                        Diagnostic::new(Severity::Warning, "broken-link", message, line)
+                            .in_file(file),
+                    );
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    fn document(&self, file: &str) -> Option<(&str, &'a Document)> {
+        self.documents
+            .iter()
+            .find(|(f, _)| f == file)
+            .map(|(f, document)| (f.as_str(), *document))
+    }
+}
+
+/// The search option equivalent to an in-file link, e.g. `*Heading` for
+/// `[[*Heading]]`.
+fn search_option(link: &Link) -> String {
+    match link.kind {
+        LinkKind::Heading => format!("*{}", link.path),
+        LinkKind::CustomId => format!("#{}", link.path),
+        _ => link.path.clone(),
+    }
+}
+
+fn search(file: &str, document: &Document, option: &str) -> Option<Location> {
+    if let Some(title) = option.strip_prefix('*') {
+        let title = title.split_whitespace().collect::<Vec<&str>>().join(" ");
+        return find_heading(file, document, |heading| {
+            heading.normalised_title() == title
+        });
+    }
+
+    if let Some(id) = option.strip_prefix('#') {
+        return find_heading(file, document, |heading| {
+            heading.property("CUSTOM_ID").as_deref() == Some(id)
+        });
+    }
+
+    if let Ok(line) = option.parse::<usize>() {
+        return Some(Location {
+            file: file.to_string(),
+            path: document.path_at_line(line),
+            line,
+        });
+    }
+
+    let text = option.to_lowercase();
+
+    targets(document)
+        .into_iter()
+        .find(|(target, _)| target.to_lowercase() == text)
+        .map(|(_, line)| Location {
+            file: file.to_string(),
+            path: document.path_at_line(line),
+            line,
+        })
+        .or_else(|| {
+            find_heading(file, document, |heading| {
+                heading.normalised_title() == option
+            })
+        })
+}
+
+fn find_heading<F>(file: &str, document: &Document, predicate: F) -> Option<Location>
+where
+    F: Fn(&Heading) -> bool,
+{
+    document
+        .all_headings()
+        .into_iter()
+        .find(|(_, heading)| predicate(heading))
+        .map(|(path, heading)| Location {
+            file: file.to_string(),
+            path,
+            line: heading.line,
+        })
+}
+
+/// Dedicated (`<<target>>`) and radio (`<<<target>>>`) targets with their
+/// lines.
+fn targets(document: &Document) -> Vec<(String, usize)> {
+    let mut sections = vec![(&document.section, 1)];
+
+    for (_, heading) in document.all_headings() {
+        sections.push((&heading.section, heading.line + 1));
+    }
+
+    let mut targets = vec![];
+
+    for (section, first_line) in sections {
+        for token in document::tokens(&section.join("\n"), first_line) {
+            if token.token_type == TokenType::Target || token.token_type == TokenType::RadioTarget {
+                let name = token.lexeme.trim_matches(|c| c == '<' || c == '>');
+                targets.push((name.to_string(), token.line));
+            }
+        }
+    }
+
+    targets
+}
+
+fn describe(link: &Link) -> String {
+    match &link.search_option {
+        Some(option) => format!("{}::{}", link.path, option),
+        None => search_option(link),
+    }
+}
+
+/// Resolves `path` relative to the directory of the file `from`.
+pub fn join(from: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        return normalise_path(path);
+    }
+
+    match from.rfind('/') {
+        Some(i) => normalise_path(&format!("{}/{}", &from[..i], path)),
+        None => normalise_path(path),
+    }
+}
+
+/// Collapses `.`, `..` and repeated separators, so that the same file is
+/// always keyed the same way.
+pub fn normalise_path(path: &str) -> String {
+    let mut components: Vec<&str> = vec![];
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." if components.last().is_some_and(|c| *c != "..") => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+
+    if path.starts_with('/') {
+        format!("/{}", components.join("/"))
+    } else {
+        components.join("/")
+    }
+}
@@ -1,7 +1,13 @@
 extern crate wasm_bindgen;
-use regex::Regex;
+use crate::document::heading_level;
+use crate::entities::entity;
+use crate::link::LINK_TYPES;
+use crate::macros::MacroReference;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ops::Range;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Repeat-character tokens.
@@ -11,6 +17,7 @@ pub enum TokenType {
     String,
 
     // Keywords.
+    Keyword,
     Title,
     Author,
     InitiationDate,
@@ -19,7 +26,19 @@ pub enum TokenType {
     Italic,
     Underline,
     Strikethrough,
+    Verbatim,
+    Code,
+    /// `\(...\)`, `\[...\]`, `$...$`, `$$...$$` or a command that isn't
+    /// an entity, such as `\frac{a}{b}` or `\newpage`.
+    LatexFragment,
+    /// A backslash and the name of an entity, such as `\alpha` or
+    /// `\alpha{}`.
+    Entity,
     Link,
+    Macro,
+    Target,
+    RadioTarget,
+    Tags,
     Clock,
     End,
     Duration,
@@ -34,50 +53,123 @@ pub enum TokenType {
     EOF,
 }
 
+/// A token of the input. The lexeme borrows from the scanned text; visitors
+/// that rewrite it may replace it with an owned string.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-pub struct Token {
+pub struct Token<'a> {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Cow<'a, str>,
     pub line: usize,
 }
 
-pub fn scan(source: String) -> Vec<Token> {
-    Scanner {
-        source: source,
-        tokens: vec![],
+impl Token<'_> {
+    /// A copy that no longer borrows from the input.
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            token_type: self.token_type,
+            lexeme: Cow::Owned(self.lexeme.into_owned()),
+            line: self.line,
+        }
+    }
+}
+
+/// Scans `source` in a single pass. Positions are byte offsets, so each
+/// character is visited once and lexemes are slices of the input.
+pub fn scan(source: &str) -> Vec<Token<'_>> {
+    tokens(source).collect()
+}
+
+/// The tokens of `source`, scanned lazily as the iterator is advanced.
+pub fn tokens(source: &str) -> Tokens<'_> {
+    Tokens {
+        source,
+        pending: None,
+        span: 0..0,
+        last_line: None,
         start: 0,
         current: 0,
         line: 1,
+        heading: false,
+        done: false,
+        paragraph: 0..0,
+        brackets: None,
+        searches: HashMap::new(),
+        closers_line: 0..0,
+        closers: Default::default(),
     }
-    .scan_source()
 }
 
-struct Scanner {
-    source: String,
-    tokens: Vec<Token>,
+/// The characters that mark up emphasis, in the order `Tokens::closers`
+/// keeps them.
+const EMPHASIS_MARKERS: [char; 6] = ['*', '/', '_', '+', '=', '~'];
+
+/// An iterator over the tokens of a text, ending with an `EOF` token. Only
+/// the position in the text and what is known of the current paragraph are
+/// kept, so memory doesn't grow with the length of the text.
+pub struct Tokens<'a> {
+    source: &'a str,
+    pending: Option<(Token<'a>, Range<usize>)>,
+    span: Range<usize>,
+    /// The line of the last token, to tell the stars of a heading apart.
+    last_line: Option<usize>,
     start: usize,
     current: usize,
     line: usize,
+    heading: bool,
+    done: bool,
+    /// The part of the current paragraph from where it was first looked at,
+    /// which brackets and delimiters can't reach past.
+    paragraph: Range<usize>,
+    /// The `]` closing each `[` in `paragraph`, once matched.
+    brackets: Option<HashMap<usize, usize>>,
+    /// For each delimiter looked for in `paragraph`, where the last search
+    /// started and what it found.
+    searches: HashMap<&'static str, (usize, Option<usize>)>,
+    /// The bytes of a line, from the first marker looked at, whose closing
+    /// emphasis markers are in `closers`, by marker.
+    closers_line: Range<usize>,
+    closers: [Vec<usize>; 6],
 }
 
-impl Scanner {
-    fn scan_source(mut self) -> Vec<Token> {
-        while !self.is_at_end() {
-            {
-                self.start = self.current;
+impl Tokens<'_> {
+    /// The byte range in the source of the token last returned by `next`.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            if let Some((token, span)) = self.pending.take() {
+                self.span = span;
+                return Some(token);
             }
-            self.scan_token();
-        }
 
-        self.tokens.push(Token {
-            token_type: TokenType::EOF,
-            lexeme: "".to_string(),
-            line: self.line,
-        });
+            if self.is_at_end() {
+                if self.done {
+                    return None;
+                }
+
+                self.done = true;
+                self.span = self.current..self.current;
+
+                return Some(Token {
+                    token_type: TokenType::EOF,
+                    lexeme: Cow::Borrowed(""),
+                    line: self.line,
+                });
+            }
 
-        self.tokens
+            self.start = self.current;
+            self.scan_token();
+        }
     }
+}
 
+impl<'a> Tokens<'a> {
     fn scan_token(&mut self) {
         let c: char = self.advance();
 
@@ -85,22 +177,28 @@ impl Scanner {
             '*' => self.asterisk(),
             '<' => self.angle_bracket(),
             '[' => self.square_bracket(),
+            '{' => self.brace(),
             '/' => self.forward_slash(),
             '_' => self.underline(),
             '+' => self.plus(),
+            '=' | '~' => self.verbatim(c),
+            '\\' => self.backslash(),
+            '$' => self.dollar(),
             '0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => self.number(),
             ' ' => (),
             '\r' => (),
             '\t' => (),
-            '\n' => self.line = self.line + 1,
+            ':' if self.heading => self.tags(),
+            '\n' => {
+                self.line += 1;
+                self.heading = false;
+            }
+            c if c.is_whitespace() => (),
             _ => {
                 if is_alpha(c) {
                     self.identifier();
                 } else {
-                    panic!(format!(
-                        "line {:?}, Unexpected character: {:?}",
-                        self.line, c
-                    ));
+                    self.add_token(TokenType::String);
                 }
             }
         }
@@ -111,257 +209,695 @@ impl Scanner {
             self.advance();
         }
 
-        let text: String = self.source[self.start..self.current].to_string();
+        let text = self.text();
 
-        let duration = Regex::new(r"^[\d]*:[\d]*$").unwrap();
+        let duration = regex!(r"^[\d]*:[\d]*$");
 
-        let token_type: TokenType;
-
-        if duration.is_match(&text) {
-            token_type = TokenType::Duration
+        let token_type = if duration.is_match(text) {
+            TokenType::Duration
         } else {
-            token_type = TokenType::String
+            TokenType::String
         };
 
-        self.tokens.push(Token {
-            token_type: token_type,
-            lexeme: text,
+        self.emit(Token {
+            token_type,
+            lexeme: Cow::Borrowed(text),
             line: self.line,
         })
     }
 
     fn asterisk(&mut self) {
+        if !self.heading_stars() && self.emphasis('*') {
+            return self.add_token(TokenType::Bold);
+        }
+
         while is_alpha(self.peek()) | self.peek().is_alphanumeric() {
             self.advance();
         }
 
-        let text: String = self.source[self.start..self.current].to_string();
+        let token_type = if self.text().chars().all(|c| c == '*') {
+            self.heading = self.last_line != Some(self.line);
+            TokenType::Asterisk
+        } else {
+            TokenType::String
+        };
 
-        let star_only = Regex::new(r"^[*]*$").unwrap();
-        let bold = Regex::new(r"^[*].*[*]$").unwrap();
+        self.add_token(token_type)
+    }
 
-        let token_type: TokenType;
+    /// Whether the `*` just consumed begins the stars of a heading: a run of
+    /// stars at the start of a line followed by whitespace.
+    fn heading_stars(&self) -> bool {
+        let line_start = self.start == 0 || self.source[..self.start].ends_with('\n');
+        let rest = self.source[self.start..].trim_start_matches('*');
 
-        if star_only.is_match(&text) {
-            token_type = TokenType::Asterisk
-        } else if bold.is_match(&text) {
-            token_type = TokenType::Bold
-        } else {
-            token_type = TokenType::String
-        };
+        line_start && rest.chars().next().is_none_or(char::is_whitespace)
+    }
 
-        self.tokens.push(Token {
-            token_type: token_type,
-            lexeme: text,
-            line: self.line,
-        })
+    /// Consumes the rest of `*bold*`, `/italic/`, `_underline_` or
+    /// `+strikethrough+` if the marker just consumed opens emphasis that
+    /// closes on the same line.
+    fn emphasis(&mut self, marker: char) -> bool {
+        match self.closing(self.start, marker) {
+            Some(end) => {
+                self.current = end;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The end of the emphasis opened by `marker` at byte `at`, if any.
+    ///
+    /// As in Org, the opening marker follows whitespace, an opening
+    /// punctuation mark or the start of the line and comes before
+    /// non-whitespace other than another marker, and the closing one the
+    /// other way around. CJK
+    /// characters count as borders too, since such text has no spaces
+    /// between words.
+    fn closing(&mut self, at: usize, marker: char) -> Option<usize> {
+        let before = self.source[..at].chars().next_back();
+        let after = self.source[at + 1..].chars().next();
+
+        let opens = before.is_none_or(is_emphasis_pre)
+            && after.is_some_and(|c| !c.is_whitespace() && c != marker);
+
+        if !opens {
+            return None;
+        }
+
+        if !self.closers_line.contains(&at) {
+            self.find_closers(at);
+        }
+
+        let marker = EMPHASIS_MARKERS.iter().position(|m| *m == marker)?;
+        let closers = &self.closers[marker];
+        let i = closers.partition_point(|close| *close < at + 2);
+
+        closers.get(i).map(|close| close + 1)
+    }
+
+    /// Finds every marker from byte `from` to the end of its line that could
+    /// close emphasis, so that looking for the end of each opening marker
+    /// doesn't read the rest of the line again.
+    fn find_closers(&mut self, from: usize) {
+        let line = self.source[from..].split('\n').next().unwrap();
+        let mut chars = line.char_indices().peekable();
+        let mut previous = ' ';
+
+        self.closers_line = from..from + line.len();
+
+        for closers in self.closers.iter_mut() {
+            closers.clear();
+        }
+
+        while let Some((i, c)) = chars.next() {
+            let next = chars.peek().map(|(_, c)| *c);
+
+            if let Some(marker) = EMPHASIS_MARKERS.iter().position(|m| *m == c) {
+                if !previous.is_whitespace() && next.is_none_or(is_emphasis_post) {
+                    self.closers[marker].push(from + i);
+                }
+            }
+
+            previous = c;
+        }
     }
 
     fn angle_bracket(&mut self) {
+        if self.peek() == '<' {
+            return self.target();
+        }
+
         while is_alpha(self.peek()) | self.peek().is_alphanumeric() {
             self.advance();
         }
 
-        let text: String = self.source[self.start..self.current].to_string();
+        let text = self.text();
+
+        let date_format = regex!(r"^<\d{4}-\d{2}-\d{2}$");
 
-        let date_format = Regex::new(r"^<\d{4}-\d{2}-\d{2}$").unwrap();
+        if is_link_type(&text[1..]) {
+            if let Some(i) = text.find('>') {
+                self.current = self.start + i;
+            }
 
-        if !date_format.is_match(&text) {
-            self.tokens.push(Token {
+            while self.peek() != '>' && self.peek() != '\n' && !self.is_at_end() {
+                self.advance();
+            }
+
+            let token_type = if self.peek() == '>' {
+                self.advance();
+                TokenType::Link
+            } else {
+                TokenType::String
+            };
+
+            self.add_token(token_type);
+        } else if !date_format.is_match(text) {
+            self.emit(Token {
                 token_type: TokenType::String,
-                lexeme: text,
+                lexeme: Cow::Borrowed(text),
                 line: self.line,
             });
         } else {
-            while '>' != self.peek() {
+            while self.peek() != '>' && self.peek() != '\n' && !self.is_at_end() {
                 self.advance();
             }
 
+            let token_type = if self.peek() == '>' {
+                self.advance();
+                TokenType::Date
+            } else {
+                TokenType::String
+            };
+
+            self.add_token(token_type);
+        }
+    }
+
+    fn target(&mut self) {
+        while self.peek() == '<' {
             self.advance();
+        }
 
-            let text: String = self.source[self.start..self.current].to_string();
+        while self.peek() != '>' && self.peek() != '\n' && !self.is_at_end() {
+            self.advance();
+        }
 
-            self.tokens.push(Token {
-                token_type: TokenType::Date,
-                lexeme: text,
-                line: self.line,
-            });
+        while self.peek() == '>' {
+            self.advance();
         }
+
+        let text = self.text();
+
+        let radio_target = regex!(r"^<<<[^<>]+>>>$");
+        let target = regex!(r"^<<[^<>]+>>$");
+
+        let token_type = if radio_target.is_match(text) {
+            TokenType::RadioTarget
+        } else if target.is_match(text) {
+            TokenType::Target
+        } else {
+            TokenType::String
+        };
+
+        self.add_token(token_type)
     }
 
     fn square_bracket(&mut self) {
-        let mut within_par: bool = true;
-        let mut nesting: usize = 1;
-
-        while within_par {
-            if self.peek() == '[' {
-                nesting = nesting + 1;
-            } else if self.peek() == ']' {
-                nesting = nesting - 1;
+        self.paragraph_end(self.start);
+
+        let brackets = match self.brackets.take() {
+            Some(brackets) => brackets,
+            None => self.match_brackets(),
+        };
+        let close = brackets.get(&self.start).copied();
+        self.brackets = Some(brackets);
+
+        // An unclosed bracket is just text.
+        match close {
+            Some(close) => self.current = close + 1,
+            None => return self.add_token(TokenType::String),
+        }
+
+        let text = self.text();
+
+        let timestamp = regex!(r"^\[\d{4}-\d{2}-\d{2} \w{3} \d{2}:\d{2}]$");
+        let link = regex!(r"^\[\[[^\]]+\](\[.*\])?\]$");
+
+        let token_type = if timestamp.is_match(text) {
+            TokenType::Timestamp
+        } else if link.is_match(text) {
+            TokenType::Link
+        } else {
+            TokenType::String
+        };
+
+        self.add_token(token_type);
+
+        // Brackets may span lines, as link descriptions do.
+        self.line += text.matches('\n').count();
+    }
+
+    /// Pairs up the brackets in `paragraph` in one pass, keeping the
+    /// innermost open ones on a stack.
+    fn match_brackets(&self) -> HashMap<usize, usize> {
+        let from = self.paragraph.start;
+        let mut brackets = HashMap::new();
+        let mut open = vec![];
+
+        for (i, c) in self.source[self.paragraph.clone()].bytes().enumerate() {
+            match c {
+                b'[' => open.push(from + i),
+                b']' => {
+                    if let Some(start) = open.pop() {
+                        brackets.insert(start, from + i);
+                    }
+                }
+                _ => (),
             }
+        }
 
-            self.advance();
+        brackets
+    }
+
+    /// The end of the paragraph that byte `from` is on, or of its line if
+    /// that is a heading.
+    fn paragraph_end(&mut self, from: usize) -> usize {
+        if !self.paragraph.contains(&from) {
+            let end = if self.heading {
+                line_end(self.source, from)
+            } else {
+                paragraph_end(self.source, from)
+            };
+
+            self.paragraph = from..end;
+            self.brackets = None;
+            self.searches.clear();
+        }
+
+        self.paragraph.end
+    }
+
+    /// The next `delimiter` from byte `from` in the same paragraph. Each
+    /// search picks up where the last one for `delimiter` left off, so a
+    /// run of openers without a closer doesn't read the paragraph again
+    /// for each one.
+    fn find(&mut self, delimiter: &'static str, from: usize) -> Option<usize> {
+        let end = self.paragraph_end(from);
 
-            if self.peek() == ']' && nesting == 1 {
-                within_par = false;
+        if let Some((start, found)) = self.searches.get(delimiter) {
+            if *start <= from && found.is_none_or(|i| i >= from) {
+                return *found;
             }
         }
 
-        self.advance();
+        let found = self.source[from..end].find(delimiter).map(|i| from + i);
+        self.searches.insert(delimiter, (from, found));
 
-        let text: String = self.source[self.start..self.current].to_string();
+        found
+    }
+
+    fn brace(&mut self) {
+        if self.peek() != '{' {
+            return self.add_token(TokenType::String);
+        }
+
+        while self.peek() != '\n' && !self.is_at_end() {
+            let text = &self.source[self.start..self.current];
 
-        let timestamp = Regex::new(r"^\[\d{4}-\d{2}-\d{2} \w{3} \d{2}:\d{2}]$").unwrap();
-        let link = Regex::new(r"^\[{2}.*[\]|\[]{2}.*]{2}$").unwrap();
+            if text.len() > 3 && text.ends_with("}}}") {
+                break;
+            }
+
+            self.advance();
+        }
 
-        let token_type: TokenType;
+        let text = self.text();
 
-        if timestamp.is_match(&text) {
-            token_type = TokenType::Timestamp
-        } else if link.is_match(&text) {
-            token_type = TokenType::Link
+        let token_type = if MacroReference::parse(text).is_some() {
+            TokenType::Macro
         } else {
-            token_type = TokenType::String
+            self.current = self.start + 1;
+            TokenType::String
         };
 
-        self.tokens.push(Token {
-            token_type: token_type,
-            lexeme: text,
-            line: self.line,
-        })
+        self.add_token(token_type)
     }
 
     fn forward_slash(&mut self) {
+        if self.emphasis('/') {
+            return self.add_token(TokenType::Italic);
+        }
+
         while is_alpha(self.peek()) | self.peek().is_alphanumeric() {
             self.advance();
         }
 
-        let text: String = self.source[self.start..self.current].to_string();
-
-        let italic = Regex::new(r"^[/].*[/]$").unwrap();
+        self.add_token(TokenType::String)
+    }
 
-        let token_type: TokenType;
+    fn underline(&mut self) {
+        if self.emphasis('_') {
+            return self.add_token(TokenType::Underline);
+        }
 
-        if italic.is_match(&text) {
-            token_type = TokenType::Italic
-        } else {
-            token_type = TokenType::String
-        };
+        while is_alpha(self.peek()) | self.peek().is_alphanumeric() {
+            self.advance();
+        }
 
-        self.tokens.push(Token {
-            token_type: token_type,
-            lexeme: text,
-            line: self.line,
-        })
+        self.add_token(TokenType::String)
     }
 
-    fn underline(&mut self) {
+    fn plus(&mut self) {
+        if self.emphasis('+') {
+            return self.add_token(TokenType::Strikethrough);
+        }
+
         while is_alpha(self.peek()) | self.peek().is_alphanumeric() {
             self.advance();
         }
 
-        let text: String = self.source[self.start..self.current].to_string();
+        self.add_token(TokenType::String)
+    }
+
+    /// `=verbatim=` or `~code~`, or else the start of a word.
+    fn verbatim(&mut self, marker: char) {
+        if self.emphasis(marker) {
+            let token_type = if marker == '=' {
+                TokenType::Verbatim
+            } else {
+                TokenType::Code
+            };
+
+            return self.add_token(token_type);
+        }
+
+        self.identifier()
+    }
 
-        let underline = Regex::new(r"^[_].*[_]$").unwrap();
+    /// A LaTeX fragment or entity, or else a lone backslash. `\\`, a line
+    /// break, stays text.
+    fn backslash(&mut self) {
+        match self.peek() {
+            '(' => return self.delimited("\\)"),
+            '[' => return self.delimited("\\]"),
+            '\\' => {
+                self.advance();
+                return self.add_token(TokenType::String);
+            }
+            c if !c.is_ascii_alphabetic() => return self.add_token(TokenType::String),
+            _ => (),
+        }
 
-        let token_type: TokenType;
+        while self.peek().is_ascii_alphabetic() {
+            self.advance();
+        }
+
+        let source = self.source;
+        let name = &source[self.start + 1..self.current];
+        let mut arguments = false;
+
+        if source[self.current..].starts_with("{}") {
+            self.current += 2;
+        } else {
+            while let Some(length) = argument(&source[self.current..]) {
+                self.current += length;
+                arguments = true;
+            }
+        }
 
-        if underline.is_match(&text) {
-            token_type = TokenType::Underline
+        let token_type = if entity(name).is_some() && !arguments {
+            TokenType::Entity
         } else {
-            token_type = TokenType::String
+            TokenType::LatexFragment
         };
 
-        self.tokens.push(Token {
-            token_type: token_type,
-            lexeme: text,
-            line: self.line,
-        })
+        self.add_token(token_type)
     }
 
-    fn plus(&mut self) {
-        while is_alpha(self.peek()) | self.peek().is_alphanumeric() {
-            self.advance();
+    /// The rest of a fragment that ends with `end`, which may be on a later
+    /// line of the same paragraph.
+    fn delimited(&mut self, end: &'static str) {
+        match self.find(end, self.current + 1) {
+            Some(i) => {
+                self.current = i + end.len();
+                self.add_token(TokenType::LatexFragment);
+                self.line += self.text().matches('\n').count();
+            }
+            None => self.add_token(TokenType::String),
         }
+    }
 
-        let text: String = self.source[self.start..self.current].to_string();
+    /// `$$...$$`, or `$...$` when the contents neither start nor end with
+    /// whitespace and the closing `$` isn't followed by a word, so that
+    /// prices like `$5 or $6` stay text.
+    fn dollar(&mut self) {
+        let source = self.source;
+        let rest = &source[self.current..];
+
+        if rest.starts_with('$') {
+            if let Some(i) = self
+                .find("$$", self.current + 1)
+                .filter(|i| *i > self.current + 1)
+            {
+                self.current = i + 2;
+                self.add_token(TokenType::LatexFragment);
+                self.line += self.text().matches('\n').count();
+                return;
+            }
+        }
 
-        let strikethrough = Regex::new(r"^[+].*[+]$").unwrap();
+        let before = source[..self.start].chars().next_back();
+        let close = match before {
+            Some('$') => None,
+            _ => self.find("$", self.current),
+        };
 
-        let token_type: TokenType;
+        if let Some(i) = close.map(|close| close - self.current) {
+            let contents = &rest[..i];
+            let first = contents.chars().next();
+            let last = contents.chars().next_back();
+            let after = rest[i + 1..].chars().next();
+
+            let math = first.is_some_and(|c| !c.is_whitespace() && !".,;".contains(c))
+                && last.is_some_and(|c| !c.is_whitespace() && !".,".contains(c))
+                && after.is_none_or(|c| !c.is_alphanumeric() && c != '$');
+
+            if math {
+                self.current += i + 1;
+                self.add_token(TokenType::LatexFragment);
+                self.line += self.text().matches('\n').count();
+                return;
+            }
+        }
 
-        if strikethrough.is_match(&text) {
-            token_type = TokenType::Strikethrough
-        } else {
-            token_type = TokenType::String
-        };
+        self.add_token(TokenType::String)
+    }
 
-        self.tokens.push(Token {
-            token_type: token_type,
-            lexeme: text,
-            line: self.line,
-        })
+    /// Whether the character at byte `at` opens emphasis, e.g. the `*` in
+    /// `日本語*強調*です` or `(*bold*)`.
+    fn opens_emphasis(&mut self, at: usize) -> bool {
+        match self.source[at..].chars().next() {
+            Some(marker @ ('*' | '/' | '_' | '+' | '=' | '~')) => {
+                self.closing(at, marker).is_some()
+            }
+            _ => false,
+        }
     }
 
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 
+    /// Moves past the next character. `current` is a byte offset, so
+    /// stepping is constant time and slices always fall on char boundaries.
     fn advance(&mut self) -> char {
-        self.current = self.current + 1;
-        self.source.chars().nth(self.current - 1).unwrap()
+        let c = self.source[self.current..].chars().next().unwrap();
+        self.current += c.len_utf8();
+        c
+    }
+
+    fn text(&self) -> &'a str {
+        &self.source[self.start..self.current]
+    }
+
+    fn emit(&mut self, token: Token<'a>) {
+        self.last_line = Some(token.line);
+        self.pending = Some((token, self.start..self.current));
     }
 
     fn add_token(&mut self, token_type: TokenType) {
-        let text: String = self.source[self.start..self.current].to_string();
-        self.tokens.push(Token {
-            token_type: token_type,
-            lexeme: text,
+        let text = self.text();
+        self.emit(Token {
+            token_type,
+            lexeme: Cow::Borrowed(text),
             line: self.line,
         })
     }
 
     fn peek(&mut self) -> char {
-        let c: char;
-
         if self.is_at_end() {
-            c = '\0'
+            '\0'
         } else {
-            c = self.source.chars().nth(self.current).unwrap()
+            self.source[self.current..].chars().next().unwrap()
         }
-
-        c
     }
 
     fn identifier(&mut self) {
-        while is_alpha(self.peek()) {
+        while is_alpha(self.peek()) && !self.opens_emphasis(self.current) {
             self.advance();
         }
 
-        let mut keywords = HashMap::new();
+        let text = self.text();
+        let next = (self.current + self.peek().len_utf8()).min(self.source.len());
 
-        keywords.insert("#+TITLE:".to_string(), TokenType::Title);
-        keywords.insert("#+AUTHOR:".to_string(), TokenType::Author);
-        keywords.insert("#+DATE:".to_string(), TokenType::InitiationDate);
-        keywords.insert(":LOGBOOK:".to_string(), TokenType::LogBook);
-        keywords.insert("CLOCK:".to_string(), TokenType::Clock);
-        keywords.insert(":END:".to_string(), TokenType::End);
-        keywords.insert("SCHEDULED:".to_string(), TokenType::Scheduled);
-        keywords.insert("DEADLINE:".to_string(), TokenType::Deadline);
-        keywords.insert("TODO".to_string(), TokenType::Todo);
-        keywords.insert("DONE".to_string(), TokenType::Done);
-
-        let text: String = self.source[self.start..self.current].to_string();
-
-        let identifier = keywords.get(&text);
+        if is_link_type(&self.source[self.start..next]) {
+            return self.plain_link();
+        }
 
-        let token_type = match identifier {
-            Some(x) => x.clone(),
+        let token_type = match keyword(text) {
+            Some(token_type) => token_type,
+            None if regex!(r"^#\+[^\s:]+:$").is_match(text) => TokenType::Keyword,
             None => TokenType::String,
         };
 
         self.add_token(token_type)
     }
+
+    fn tags(&mut self) {
+        while self.peek().is_alphanumeric() || "_@#%:".contains(self.peek()) {
+            self.advance();
+        }
+
+        let tags = regex!(r"^:([\w@#%]+:)+$");
+        let text = self.text();
+
+        let rest = self.source[self.current..].split('\n').next().unwrap();
+
+        if tags.is_match(text) && rest.trim().is_empty() {
+            self.add_token(TokenType::Tags)
+        } else {
+            self.current = self.start + 1;
+            self.identifier()
+        }
+    }
+
+    fn plain_link(&mut self) {
+        while !is_link_boundary(self.peek()) {
+            self.advance();
+        }
+
+        loop {
+            let text = &self.source[self.start..self.current];
+            let last = text.chars().last().unwrap();
+            let unbalanced = last == ')' && text.matches('(').count() < text.matches(')').count();
+
+            if is_link_trailer(last) || last == '>' || unbalanced {
+                self.current -= last.len_utf8();
+            } else {
+                break;
+            }
+        }
+
+        self.add_token(TokenType::Link)
+    }
+}
+
+/// The end of the line that byte `from` is on.
+fn line_end(source: &str, from: usize) -> usize {
+    source[from..].find('\n').map_or(source.len(), |i| from + i)
+}
+
+/// The end of the paragraph that byte `from` is on: the start of the next
+/// blank line or heading, or the end of the text.
+fn paragraph_end(source: &str, from: usize) -> usize {
+    let mut offset = line_end(source, from) + 1;
+
+    if offset >= source.len() {
+        return source.len();
+    }
+
+    for line in source[offset..].split_inclusive('\n') {
+        let text = line.trim_end_matches(['\n', '\r']);
+
+        if text.trim().is_empty() || heading_level(text).is_some() {
+            return offset;
+        }
+
+        offset += line.len();
+    }
+
+    source.len()
+}
+
+/// The length of a `[...]` or `{...}` argument of a LaTeX command at the
+/// start of `text`. Braces may nest; neither may span lines.
+fn argument(text: &str) -> Option<usize> {
+    let close = match text.chars().next()? {
+        '[' => ']',
+        '{' => '}',
+        _ => return None,
+    };
+    let mut depth = 0;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '\n' => return None,
+            '{' if close == '}' => depth += 1,
+            '}' if close == '}' => depth -= 1,
+            ']' if close == ']' => depth -= 1,
+            '[' if close == ']' => depth += 1,
+            _ => (),
+        }
+
+        if depth == 0 {
+            return Some(i + 1);
+        }
+    }
+
+    None
+}
+
+fn keyword(text: &str) -> Option<TokenType> {
+    match text {
+        "#+TITLE:" => Some(TokenType::Title),
+        "#+AUTHOR:" => Some(TokenType::Author),
+        "#+DATE:" => Some(TokenType::InitiationDate),
+        ":LOGBOOK:" => Some(TokenType::LogBook),
+        "CLOCK:" => Some(TokenType::Clock),
+        ":END:" => Some(TokenType::End),
+        "SCHEDULED:" => Some(TokenType::Scheduled),
+        "DEADLINE:" => Some(TokenType::Deadline),
+        "TODO" => Some(TokenType::Todo),
+        "DONE" => Some(TokenType::Done),
+        _ => None,
+    }
+}
+
+/// Whether `text` starts with a known link type followed by a colon and at
+/// least one character of path, e.g. `https://orgmode.org` or `id:1234`.
+fn is_link_type(text: &str) -> bool {
+    match text.find(':') {
+        Some(i) => {
+            LINK_TYPES.contains(&&text[..i])
+                && text[i + 1..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| !is_link_boundary(c))
+        }
+        None => false,
+    }
+}
+
+fn is_link_boundary(c: char) -> bool {
+    c.is_whitespace() || c == '\0' || c == '(' || c == ')' || c == '<' || c == '>'
+}
+
+fn is_link_trailer(c: char) -> bool {
+    c == '.' || c == ',' || c == ';' || c == ':' || c == '!' || c == '?' || c == '\u{27}'
+}
+
+fn is_emphasis_pre(c: char) -> bool {
+    c.is_whitespace() || "-({'\"".contains(c) || is_cjk(c)
+}
+
+fn is_emphasis_post(c: char) -> bool {
+    c.is_whitespace() || "-.,;:!?')}[\"\\".contains(c) || is_cjk(c)
+}
+
+/// Han, kana, Hangul and full-width forms, along with CJK punctuation.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{303F}'
+        | '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF00}'..='\u{FFEF}'
+        | '\u{20000}'..='\u{2FA1F}')
 }
 
 fn is_alpha(c: char) -> bool {
@@ -0,0 +1,114 @@
+use crate::document::Document;
+use crate::element::Keyword;
+
+/// How `^:` in `#+OPTIONS:` treats `a^b` and `a_b`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SubSuperscripts {
+    On,
+    Off,
+    Braces,
+}
+
+/// The `key:value` pairs of `#+OPTIONS:` lines, in order, later ones
+/// overriding earlier ones.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ExportOptions {
+    pub items: Vec<(String, String)>,
+}
+
+/// Document-wide settings collected from keywords such as `#+TITLE:` and
+/// `#+OPTIONS:`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Settings {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub date: Option<String>,
+    pub email: Option<String>,
+    pub language: Option<String>,
+    pub options: ExportOptions,
+    pub startup: Vec<String>,
+    pub exclude_tags: Vec<String>,
+    pub select_tags: Vec<String>,
+    pub setupfiles: Vec<String>,
+    pub keywords: Vec<Keyword>,
+}
+
+impl ExportOptions {
+    pub fn parse(value: &str) -> ExportOptions {
+        let mut options = ExportOptions::default();
+        options.extend(value);
+        options
+    }
+
+    fn extend(&mut self, value: &str) {
+        for option in value.split_whitespace() {
+            if let Some((key, value)) = option.split_once(':') {
+                self.items.retain(|(k, _)| k != key);
+                self.items.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.items
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// `t` or `nil`. Anything else set also counts as true, as in Emacs.
+    pub fn flag(&self, key: &str) -> Option<bool> {
+        self.get(key).map(|value| value != "nil")
+    }
+
+    /// A numeric option such as `toc:2` or `H:3`. `toc:t` and `toc:nil` have
+    /// no number; use `flag` for those.
+    pub fn level(&self, key: &str) -> Option<usize> {
+        self.get(key).and_then(|value| value.parse().ok())
+    }
+
+    pub fn sub_superscripts(&self) -> SubSuperscripts {
+        match self.get("^") {
+            Some("nil") => SubSuperscripts::Off,
+            Some("{}") => SubSuperscripts::Braces,
+            _ => SubSuperscripts::On,
+        }
+    }
+}
+
+impl Document {
+    pub fn settings(&self) -> Settings {
+        let mut settings = Settings::default();
+        let mut title: Vec<String> = vec![];
+
+        for keyword in self.keywords() {
+            let value = keyword.value.clone();
+            let words = || value.split_whitespace().map(|word| word.to_string());
+
+            match keyword.key.as_str() {
+                "TITLE" => title.push(value.clone()),
+                "AUTHOR" => settings.author = Some(value.clone()),
+                "DATE" => settings.date = Some(value.clone()),
+                "EMAIL" => settings.email = Some(value.clone()),
+                "LANGUAGE" => settings.language = Some(value.clone()),
+                "OPTIONS" => settings.options.extend(&value),
+                "STARTUP" => settings.startup.extend(words()),
+                "EXCLUDE_TAGS" => settings.exclude_tags.extend(words()),
+                "SELECT_TAGS" => settings.select_tags.extend(words()),
+                "SETUPFILE" => settings
+                    .setupfiles
+                    .push(value.trim_matches('"').to_string()),
+                _ => (),
+            }
+
+            settings.keywords.push(keyword);
+        }
+
+        // Multiple title lines make up one title.
+        if !title.is_empty() {
+            settings.title = Some(title.join(" "));
+        }
+
+        settings
+    }
+}
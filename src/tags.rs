@@ -0,0 +1,217 @@
+use crate::document::Document;
+
+/// How tags flow from a heading to its descendants, like
+/// `org-use-tag-inheritance` and `org-tags-exclude-from-inheritance`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TagConfig {
+    pub inheritance: bool,
+    pub exclude_from_inheritance: Vec<String>,
+}
+
+impl Default for TagConfig {
+    fn default() -> Self {
+        TagConfig {
+            inheritance: true,
+            exclude_from_inheritance: vec![],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub key: Option<char>,
+}
+
+/// A `{ ... }` or `[ ... ]` group from `#+TAGS:`. Braces make the tags
+/// mutually exclusive; a leading `name :` makes `name` a group tag standing
+/// for all of them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TagGroup {
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+    pub exclusive: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct TagDefinitions {
+    pub tags: Vec<Tag>,
+    pub groups: Vec<TagGroup>,
+}
+
+impl TagDefinitions {
+    pub fn parse(value: &str) -> TagDefinitions {
+        let mut definitions = TagDefinitions::default();
+        let mut group: Option<TagGroup> = None;
+        let mut expect_name = false;
+
+        let value = value
+            .replace('{', " { ")
+            .replace('}', " } ")
+            .replace('[', " [ ")
+            .replace(']', " ] ");
+
+        for word in value.split_whitespace() {
+            match word {
+                "{" | "[" => {
+                    group = Some(TagGroup {
+                        name: None,
+                        tags: vec![],
+                        exclusive: word == "{",
+                    });
+                    expect_name = true;
+                }
+                "}" | "]" => definitions.groups.extend(group.take()),
+                ":" => {
+                    if let Some(group) = group.as_mut() {
+                        if expect_name && group.tags.len() == 1 {
+                            group.name = group.tags.pop();
+                        }
+                    }
+                }
+                "\\n" => (),
+                _ => {
+                    let tag = parse_tag(word);
+
+                    if let Some(group) = group.as_mut() {
+                        group.tags.push(tag.name.clone());
+                        expect_name = group.tags.len() == 1 && group.name.is_none();
+                    }
+
+                    if !definitions.tags.iter().any(|t| t.name == tag.name) {
+                        definitions.tags.push(tag);
+                    }
+                }
+            }
+        }
+
+        definitions
+    }
+
+    /// `tag` together with every tag it stands for through group tags,
+    /// following nested groups.
+    pub fn expand(&self, tag: &str) -> Vec<String> {
+        let mut expanded = vec![tag.to_string()];
+        let mut i = 0;
+
+        while i < expanded.len() {
+            for group in &self.groups {
+                if group.name.as_deref() == Some(expanded[i].as_str()) {
+                    for member in &group.tags {
+                        if !expanded.contains(member) {
+                            expanded.push(member.clone());
+                        }
+                    }
+                }
+            }
+
+            i += 1;
+        }
+
+        expanded
+    }
+
+    /// Pairs of tags in `tags` that belong to the same mutually exclusive
+    /// group.
+    pub fn conflicts(&self, tags: &[String]) -> Vec<(String, String)> {
+        let mut conflicts = vec![];
+
+        for group in self.groups.iter().filter(|group| group.exclusive) {
+            let present: Vec<&String> = tags.iter().filter(|t| group.tags.contains(t)).collect();
+
+            for (i, a) in present.iter().enumerate() {
+                for b in &present[i + 1..] {
+                    conflicts.push((a.to_string(), b.to_string()));
+                }
+            }
+        }
+
+        conflicts
+    }
+}
+
+/// `@home(h)` is the tag `@home` with the fast selection key `h`.
+fn parse_tag(word: &str) -> Tag {
+    match word.find('(') {
+        Some(i) if word.ends_with(')') => Tag {
+            name: word[..i].to_string(),
+            key: word[i + 1..].chars().next().filter(|c| *c != ')'),
+        },
+        _ => Tag {
+            name: word.to_string(),
+            key: None,
+        },
+    }
+}
+
+impl Document {
+    /// Tags from `#+FILETAGS:`, which every heading in the file inherits.
+    pub fn filetags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = vec![];
+
+        for value in self.keyword_values("FILETAGS") {
+            for tag in value.split(|c: char| c == ':' || c.is_whitespace()) {
+                if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+                    tags.push(tag.to_string());
+                }
+            }
+        }
+
+        tags
+    }
+
+    /// Tags, groups and mutually exclusive sets declared with `#+TAGS:`.
+    pub fn tag_definitions(&self) -> TagDefinitions {
+        TagDefinitions::parse(&self.keyword_values("TAGS").join(" \\n "))
+    }
+
+    /// Tags written on the heading at `path` itself.
+    pub fn local_tags(&self, path: &[usize]) -> Vec<String> {
+        self.heading(path)
+            .map(|heading| heading.tags.clone())
+            .unwrap_or_default()
+    }
+
+    /// Tags the heading at `path` gets from `#+FILETAGS:` and its ancestors,
+    /// outermost first, without those it also has locally.
+    pub fn inherited_tags(&self, path: &[usize], config: &TagConfig) -> Vec<String> {
+        if !config.inheritance || self.heading(path).is_none() {
+            return vec![];
+        }
+
+        let mut sources = vec![self.filetags()];
+
+        for i in 1..path.len() {
+            sources.push(self.local_tags(&path[..i]));
+        }
+
+        let local = self.local_tags(path);
+        let mut tags: Vec<String> = vec![];
+
+        for tag in sources.into_iter().flatten() {
+            if !config.exclude_from_inheritance.contains(&tag)
+                && !local.contains(&tag)
+                && !tags.contains(&tag)
+            {
+                tags.push(tag);
+            }
+        }
+
+        tags
+    }
+
+    /// Inherited tags followed by local ones.
+    pub fn all_tags(&self, path: &[usize], config: &TagConfig) -> Vec<String> {
+        let mut tags = self.inherited_tags(path, config);
+        tags.extend(self.local_tags(path));
+        tags
+    }
+
+    fn keyword_values(&self, key: &str) -> Vec<String> {
+        self.keywords()
+            .into_iter()
+            .filter(|keyword| keyword.key == key)
+            .map(|keyword| keyword.value)
+            .collect()
+    }
+}
@@ -0,0 +1,274 @@
+use std::cmp::Ordering;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Time {
+    pub hour: u32,
+    pub minute: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+/// A repeater (`+1w`, `++1d`, `.+1m`) or warning delay (`-2d`, `--2d`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interval {
+    pub mark: String,
+    pub value: u32,
+    pub unit: Unit,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Timestamp {
+    pub active: bool,
+    pub date: Date,
+    pub time: Option<Time>,
+    pub end_time: Option<Time>,
+    pub repeater: Option<Interval>,
+    pub warning: Option<Interval>,
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+impl Date {
+    pub fn new(year: i32, month: u32, day: u32) -> Option<Date> {
+        if month == 0 || month > 12 || day == 0 || day > days_in_month(year, month) {
+            return None;
+        }
+
+        Some(Date { year, month, day })
+    }
+
+    /// Parses `2019-09-25`.
+    pub fn parse(text: &str) -> Option<Date> {
+        let mut parts = text.splitn(3, '-');
+        let year = parts.next()?.parse().ok()?;
+        let month = parts.next()?.parse().ok()?;
+        let day = parts.next()?.parse().ok()?;
+
+        Date::new(year, month, day)
+    }
+
+    /// Days since 1970-01-01.
+    pub fn days(&self) -> i64 {
+        let year = if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        } as i64;
+        let era = if year >= 0 { year } else { year - 399 } / 400;
+        let year_of_era = year - era * 400;
+        let month = self.month as i64;
+        let day_of_year =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    pub fn from_days(days: i64) -> Date {
+        let days = days + 719_468;
+        let era = if days >= 0 { days } else { days - 146_096 } / 146_097;
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Date {
+            year: year as i32,
+            month: month as u32,
+            day: day as u32,
+        }
+    }
+
+    pub fn add_days(&self, days: i64) -> Date {
+        Date::from_days(self.days() + days)
+    }
+
+    /// Adds whole months, clamping the day to the end of shorter months.
+    pub fn add_months(&self, months: i64) -> Date {
+        let total = self.year as i64 * 12 + (self.month as i64 - 1) + months;
+        let year = total.div_euclid(12) as i32;
+        let month = total.rem_euclid(12) as u32 + 1;
+
+        Date {
+            year,
+            month,
+            day: self.day.min(days_in_month(year, month)),
+        }
+    }
+
+    /// Abbreviated English day name, as Org writes it: `Wed`.
+    pub fn weekday(&self) -> &'static str {
+        WEEKDAYS[(self.days() + 4).rem_euclid(7) as usize]
+    }
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Time {
+    pub fn minutes(&self) -> u32 {
+        self.hour * 60 + self.minute
+    }
+
+    fn parse(text: &str) -> Option<Time> {
+        let (hour, minute) = text.split_once(':')?;
+        let time = Time {
+            hour: hour.parse().ok()?,
+            minute: minute.parse().ok()?,
+        };
+
+        if time.hour > 24 || time.minute > 59 || (time.hour == 24 && time.minute > 0) {
+            return None;
+        }
+
+        Some(time)
+    }
+}
+
+impl Interval {
+    fn parse(text: &str) -> Option<Interval> {
+        let split = text.find(|c: char| c.is_ascii_digit())?;
+        let (mark, rest) = text.split_at(split);
+        let unit = match rest.chars().last()? {
+            'h' => Unit::Hour,
+            'd' => Unit::Day,
+            'w' => Unit::Week,
+            'm' => Unit::Month,
+            'y' => Unit::Year,
+            _ => return None,
+        };
+
+        Some(Interval {
+            mark: mark.to_string(),
+            value: rest[..rest.len() - 1].parse().ok()?,
+            unit,
+        })
+    }
+
+    fn to_org(&self) -> String {
+        let unit = match self.unit {
+            Unit::Hour => 'h',
+            Unit::Day => 'd',
+            Unit::Week => 'w',
+            Unit::Month => 'm',
+            Unit::Year => 'y',
+        };
+
+        format!("{}{}{}", self.mark, self.value, unit)
+    }
+}
+
+impl Timestamp {
+    /// Parses an active (`<2019-09-25 Wed 10:00-11:30 +1w -2d>`) or inactive
+    /// (`[2019-09-19 Thu 10:40]`) timestamp.
+    pub fn parse(text: &str) -> Option<Timestamp> {
+        let timestamp = regex!(
+            r"^([<\[])(\d{4}-\d{2}-\d{2})(?: +[^\s\d\]>+.-]+)?(?: +(\d{1,2}:\d{2})(?:-(\d{1,2}:\d{2}))?)?((?: +[.+-]{1,2}\d+[hdwmy])*) *([>\]])$"
+        );
+
+        let captures = timestamp.captures(text.trim())?;
+        let active = &captures[1] == "<";
+
+        if active != (&captures[6] == ">") {
+            return None;
+        }
+
+        let mut result = Timestamp {
+            active,
+            date: Date::parse(&captures[2])?,
+            time: None,
+            end_time: None,
+            repeater: None,
+            warning: None,
+        };
+
+        if let Some(time) = captures.get(3) {
+            result.time = Some(Time::parse(time.as_str())?);
+        }
+
+        if let Some(time) = captures.get(4) {
+            result.end_time = Some(Time::parse(time.as_str())?);
+        }
+
+        for modifier in captures[5].split_whitespace() {
+            let interval = Interval::parse(modifier)?;
+
+            if interval.mark.starts_with('-') {
+                result.warning = Some(interval);
+            } else {
+                result.repeater = Some(interval);
+            }
+        }
+
+        Some(result)
+    }
+
+    /// Minutes since 1970-01-01 00:00, for timestamps with a time.
+    pub fn minutes(&self) -> Option<i64> {
+        let time = self.time?;
+        Some(self.date.days() * 24 * 60 + time.minutes() as i64)
+    }
+
+    pub fn to_org(&self) -> String {
+        let mut text = format!(
+            "{:04}-{:02}-{:02} {}",
+            self.date.year,
+            self.date.month,
+            self.date.day,
+            self.date.weekday()
+        );
+
+        if let Some(time) = self.time {
+            text.push_str(&format!(" {:02}:{:02}", time.hour, time.minute));
+
+            if let Some(end) = self.end_time {
+                text.push_str(&format!("-{:02}:{:02}", end.hour, end.minute));
+            }
+        }
+
+        for interval in self.repeater.iter().chain(self.warning.iter()) {
+            text.push(' ');
+            text.push_str(&interval.to_org());
+        }
+
+        if self.active {
+            format!("<{}>", text)
+        } else {
+            format!("[{}]", text)
+        }
+    }
+
+    /// Orders by date, with a timestamp without a time sorting before any
+    /// time on the same day.
+    pub fn compare(&self, other: &Timestamp) -> Ordering {
+        let minutes = |t: &Timestamp| t.time.map(|time| time.minutes() as i64).unwrap_or(-1);
+
+        self.date
+            .cmp(&other.date)
+            .then(minutes(self).cmp(&minutes(other)))
+    }
+}
@@ -0,0 +1,244 @@
+use crate::document::tokens;
+use crate::document::Document;
+use crate::document::Heading;
+use crate::element;
+use crate::element::Element;
+use crate::element::Item;
+use crate::element::Kind;
+use crate::scanner::Token;
+
+/// Hooks called by `walk` as it goes through a document. Every hook does
+/// nothing by default, so a visitor only implements the ones it needs.
+///
+/// Inline objects are the tokens of heading titles, paragraphs, list items
+/// and tables; block contents are left alone.
+pub trait Visitor {
+    fn enter_document(&mut self, _document: &Document) {}
+    fn leave_document(&mut self, _document: &Document) {}
+    fn enter_heading(&mut self, _path: &[usize], _heading: &Heading) {}
+    fn leave_heading(&mut self, _path: &[usize], _heading: &Heading) {}
+    /// Called for every element, around the more specific hooks below.
+    fn enter_element(&mut self, _element: &Element) {}
+    fn leave_element(&mut self, _element: &Element) {}
+    fn enter_paragraph(&mut self, _element: &Element) {}
+    fn leave_paragraph(&mut self, _element: &Element) {}
+    fn enter_item(&mut self, _item: &Item) {}
+    fn leave_item(&mut self, _item: &Item) {}
+    fn enter_table(&mut self, _element: &Element) {}
+    fn leave_table(&mut self, _element: &Element) {}
+    fn enter_block(&mut self, _element: &Element) {}
+    fn leave_block(&mut self, _element: &Element) {}
+    fn visit_token(&mut self, _token: &Token<'_>) {}
+}
+
+/// Like `Visitor`, but with mutable access. Changes to a heading, to the
+/// lines of an element or item, or to the lexeme of a token are written back
+/// into the document.
+pub trait VisitorMut {
+    fn enter_document(&mut self, _document: &mut Document) {}
+    fn leave_document(&mut self, _document: &mut Document) {}
+    fn enter_heading(&mut self, _path: &[usize], _heading: &mut Heading) {}
+    fn leave_heading(&mut self, _path: &[usize], _heading: &mut Heading) {}
+    fn enter_element(&mut self, _element: &mut Element) {}
+    fn leave_element(&mut self, _element: &mut Element) {}
+    fn enter_paragraph(&mut self, _element: &mut Element) {}
+    fn leave_paragraph(&mut self, _element: &mut Element) {}
+    fn enter_item(&mut self, _item: &mut Item) {}
+    fn leave_item(&mut self, _item: &mut Item) {}
+    fn enter_table(&mut self, _element: &mut Element) {}
+    fn leave_table(&mut self, _element: &mut Element) {}
+    fn enter_block(&mut self, _element: &mut Element) {}
+    fn leave_block(&mut self, _element: &mut Element) {}
+    fn visit_token(&mut self, _token: &mut Token<'_>) {}
+}
+
+/// Visits `document` depth first, in document order.
+pub fn walk<V: Visitor>(document: &Document, visitor: &mut V) {
+    visitor.enter_document(document);
+
+    for element in document.elements() {
+        walk_element(&element, visitor);
+    }
+
+    for (i, heading) in document.headings.iter().enumerate() {
+        walk_heading(&[i], heading, visitor);
+    }
+
+    visitor.leave_document(document);
+}
+
+fn walk_heading<V: Visitor>(path: &[usize], heading: &Heading, visitor: &mut V) {
+    visitor.enter_heading(path, heading);
+
+    for token in tokens(&heading.title, heading.line) {
+        visitor.visit_token(&token);
+    }
+
+    for element in heading.elements() {
+        walk_element(&element, visitor);
+    }
+
+    for (i, child) in heading.children.iter().enumerate() {
+        let mut path = path.to_vec();
+        path.push(i);
+        walk_heading(&path, child, visitor);
+    }
+
+    visitor.leave_heading(path, heading);
+}
+
+fn walk_element<V: Visitor>(element: &Element, visitor: &mut V) {
+    visitor.enter_element(element);
+    let first_line = element.line + element.affiliated.len();
+
+    match &element.kind {
+        Kind::Paragraph => {
+            visitor.enter_paragraph(element);
+            walk_tokens(&element.body().join("\n"), first_line, visitor);
+            visitor.leave_paragraph(element);
+        }
+        Kind::List(items) => {
+            for item in items {
+                visitor.enter_item(item);
+                walk_tokens(&item.lines.join("\n"), item.line, visitor);
+                visitor.leave_item(item);
+            }
+        }
+        Kind::Table => {
+            visitor.enter_table(element);
+            walk_tokens(&element.body().join("\n"), first_line, visitor);
+            visitor.leave_table(element);
+        }
+        Kind::Block { .. } => {
+            visitor.enter_block(element);
+            visitor.leave_block(element);
+        }
+        _ => (),
+    }
+
+    visitor.leave_element(element);
+}
+
+fn walk_tokens<V: Visitor>(text: &str, first_line: usize, visitor: &mut V) {
+    for token in tokens(text, first_line) {
+        visitor.visit_token(&token);
+    }
+}
+
+/// Visits `document` like `walk`, writing changes back as it goes.
+pub fn walk_mut<V: VisitorMut>(document: &mut Document, visitor: &mut V) {
+    visitor.enter_document(document);
+
+    document.section = walk_section_mut(&document.section, 1, false, visitor);
+
+    for (i, heading) in document.headings.iter_mut().enumerate() {
+        walk_heading_mut(&[i], heading, visitor);
+    }
+
+    visitor.leave_document(document);
+}
+
+fn walk_heading_mut<V: VisitorMut>(path: &[usize], heading: &mut Heading, visitor: &mut V) {
+    visitor.enter_heading(path, heading);
+
+    heading.title = rewrite_tokens(&heading.title, heading.line, visitor);
+    heading.section = walk_section_mut(&heading.section, heading.line + 1, true, visitor);
+
+    for (i, child) in heading.children.iter_mut().enumerate() {
+        let mut path = path.to_vec();
+        path.push(i);
+        walk_heading_mut(&path, child, visitor);
+    }
+
+    visitor.leave_heading(path, heading);
+}
+
+fn walk_section_mut<V: VisitorMut>(
+    section: &[String],
+    first_line: usize,
+    heading: bool,
+    visitor: &mut V,
+) -> Vec<String> {
+    let mut lines = vec![];
+
+    for mut element in element::parse(section, first_line, heading) {
+        walk_element_mut(&mut element, visitor);
+        lines.extend(element.lines);
+    }
+
+    lines
+}
+
+fn walk_element_mut<V: VisitorMut>(element: &mut Element, visitor: &mut V) {
+    visitor.enter_element(element);
+    let first_line = element.line + element.affiliated.len();
+
+    match element.kind.clone() {
+        Kind::Paragraph => {
+            visitor.enter_paragraph(element);
+            rewrite_body(element, first_line, visitor);
+            visitor.leave_paragraph(element);
+        }
+        Kind::List(mut items) => {
+            for item in items.iter_mut() {
+                visitor.enter_item(item);
+                let text = rewrite_tokens(&item.lines.join("\n"), item.line, visitor);
+                item.lines = text.split('\n').map(|line| line.to_string()).collect();
+                visitor.leave_item(item);
+            }
+
+            // The lines of a list are those of its items.
+            let affiliated = element.affiliated.len();
+            element.lines.truncate(affiliated);
+            element
+                .lines
+                .extend(items.iter().flat_map(|item| item.lines.clone()));
+            element.kind = Kind::List(items);
+        }
+        Kind::Table => {
+            visitor.enter_table(element);
+            rewrite_body(element, first_line, visitor);
+            visitor.leave_table(element);
+        }
+        Kind::Block { .. } => {
+            visitor.enter_block(element);
+            visitor.leave_block(element);
+        }
+        _ => (),
+    }
+
+    visitor.leave_element(element);
+}
+
+fn rewrite_body<V: VisitorMut>(element: &mut Element, first_line: usize, visitor: &mut V) {
+    let affiliated = element.affiliated.len();
+    let text = rewrite_tokens(&element.body().join("\n"), first_line, visitor);
+
+    element.lines.truncate(affiliated);
+    element
+        .lines
+        .extend(text.split('\n').map(|line| line.to_string()));
+}
+
+/// Passes each token of `text` to the visitor and splices changed lexemes
+/// back in, keeping the text between tokens as it was.
+fn rewrite_tokens<V: VisitorMut>(text: &str, first_line: usize, visitor: &mut V) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+
+    for mut token in tokens(text, first_line) {
+        let start = match rest.find(&*token.lexeme) {
+            Some(start) => start,
+            None => continue,
+        };
+
+        out.push_str(&rest[..start]);
+        rest = &rest[start + token.lexeme.len()..];
+
+        visitor.visit_token(&mut token);
+        out.push_str(&token.lexeme);
+    }
+
+    out.push_str(rest);
+    out
+}